@@ -0,0 +1,108 @@
+//! Animation clips for landscape objects. A clip holds the BMD frames of every
+//! level of a `GfxLandscape` (`GfxFrames`) and plays them according to
+//! `GfxStatic` and `GfxLoopAnimation`.
+
+use crate::bmd::BmdFile;
+
+use std::collections::BTreeMap;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Playback {
+  /// Always shows the first frame.
+  Static,
+  /// Plays the frames once and holds the last one.
+  Once,
+  Loop,
+}
+
+impl Playback {
+  /// `GfxStatic` wins over `GfxLoopAnimation`.
+  pub fn from_flags(is_static: bool, is_loop: bool) -> Playback {
+    if is_static {
+      Playback::Static
+    } else if is_loop {
+      Playback::Loop
+    } else {
+      Playback::Once
+    }
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AnimationFrame {
+  /// Frame index in the BMD.
+  pub frame: usize,
+  pub dx: i32,
+  pub dy: i32,
+}
+
+#[derive(Clone, Debug)]
+pub struct AnimationClip {
+  pub playback: Playback,
+  /// Milliseconds per frame.
+  pub frame_duration: f32,
+  pub levels: BTreeMap<u8, Vec<AnimationFrame>>,
+}
+
+impl AnimationClip {
+  /// Resolves the frame indices of every level against `bmd` and takes the
+  /// anchors from its frame table.
+  pub fn new(playback: Playback, frame_duration: f32, levels: &BTreeMap<u8, Vec<usize>>, bmd: &BmdFile) -> Result<AnimationClip, &'static str> {
+    let mut resolved = BTreeMap::new();
+
+    for (&level, frames) in levels.iter() {
+      let frames = frames.iter()
+        .map(|&frame| bmd.frame(frame).map(|f| AnimationFrame { frame, dx: f.dx, dy: f.dy }))
+        .collect::<Result<Vec<_>, _>>()?;
+
+      resolved.insert(level, frames);
+    }
+
+    Ok(AnimationClip { playback, frame_duration, levels: resolved })
+  }
+
+  /// Length of one pass through the frames of `level` in milliseconds.
+  pub fn duration(&self, level: u8) -> f32 {
+    self.levels.get(&level).map_or(0.0, |f| f.len() as f32 * self.frame_duration)
+  }
+
+  /// Frame of `level` shown `time` milliseconds after the start.
+  pub fn frame_at(&self, level: u8, time: f32) -> Option<&AnimationFrame> {
+    let frames = self.levels.get(&level)?;
+    if frames.is_empty() {
+      return None;
+    }
+
+    let step = if self.frame_duration > 0.0 { (time.max(0.0) / self.frame_duration) as usize } else { 0 };
+
+    let i = match self.playback {
+      Playback::Static => 0,
+      Playback::Once => step.min(frames.len() - 1),
+      Playback::Loop => step % frames.len(),
+    };
+
+    frames.get(i)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn clip(playback: Playback) -> AnimationClip {
+    let frames = (0..3).map(|i| AnimationFrame { frame: 10 + i, dx: -(i as i32), dy: 0 }).collect();
+    let mut levels = BTreeMap::new();
+    levels.insert(1, frames);
+
+    AnimationClip { playback, frame_duration: 100.0, levels }
+  }
+
+  #[test]
+  fn test_frame_at() {
+    assert_eq!(clip(Playback::Loop).frame_at(1, 350.0).unwrap().frame, 10);
+    assert_eq!(clip(Playback::Loop).frame_at(1, 250.0).unwrap().frame, 12);
+    assert_eq!(clip(Playback::Once).frame_at(1, 1000.0).unwrap().frame, 12);
+    assert_eq!(clip(Playback::Static).frame_at(1, 250.0).unwrap().frame, 10);
+    assert!(clip(Playback::Loop).frame_at(2, 0.0).is_none());
+  }
+}
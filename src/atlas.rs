@@ -0,0 +1,162 @@
+//! Packs rectangles into fixed size pages with a bottom-left skyline packer.
+
+use std::cmp;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Placement {
+  pub page: usize,
+  pub x: usize,
+  pub y: usize,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Segment {
+  x: usize,
+  y: usize,
+  width: usize,
+}
+
+struct Page {
+  skyline: Vec<Segment>,
+}
+
+impl Page {
+  fn new(width: usize) -> Page {
+    Page { skyline: vec![Segment { x: 0, y: 0, width }] }
+  }
+
+  /// Lowest `y` a `width` × `height` rectangle can be placed at when its left
+  /// edge is at the start of segment `i`.
+  fn fit(&self, i: usize, width: usize, height: usize, page_width: usize, page_height: usize) -> Option<usize> {
+    let x = self.skyline[i].x;
+    if x + width > page_width {
+      return None;
+    }
+
+    let mut y = 0;
+    let mut width_left = width;
+    let mut j = i;
+
+    while width_left > 0 {
+      let s = &self.skyline[j];
+      y = cmp::max(y, s.y);
+      if y + height > page_height {
+        return None;
+      }
+
+      width_left -= cmp::min(width_left, s.width);
+      j += 1;
+    }
+
+    Some(y)
+  }
+
+  fn find(&self, width: usize, height: usize, page_width: usize, page_height: usize) -> Option<(usize, usize, usize)> {
+    let mut best: Option<(usize, usize, usize)> = None;
+
+    for i in 0..self.skyline.len() {
+      if let Some(y) = self.fit(i, width, height, page_width, page_height) {
+        let x = self.skyline[i].x;
+        if best.is_none_or(|(_, bx, by)| y < by || (y == by && x < bx)) {
+          best = Some((i, x, y));
+        }
+      }
+    }
+
+    best
+  }
+
+  fn add(&mut self, i: usize, x: usize, y: usize, width: usize, height: usize) {
+    self.skyline.insert(i, Segment { x, y: y + height, width });
+
+    let end = x + width;
+    while i + 1 < self.skyline.len() && self.skyline[i + 1].x < end {
+      let s = &mut self.skyline[i + 1];
+      let shrink = end - s.x;
+
+      if s.width <= shrink {
+        self.skyline.remove(i + 1);
+      } else {
+        s.x += shrink;
+        s.width -= shrink;
+        break;
+      }
+    }
+
+    let mut j = 0;
+    while j + 1 < self.skyline.len() {
+      if self.skyline[j].y == self.skyline[j + 1].y {
+        self.skyline[j].width += self.skyline[j + 1].width;
+        self.skyline.remove(j + 1);
+      } else {
+        j += 1;
+      }
+    }
+  }
+}
+
+/// Places every `(width, height)` rectangle on a `page_width` ×
+/// `page_height` page, opening new pages as needed. `padding` pixels are
+/// kept free to the right of and below every rectangle. Empty rectangles are
+/// placed at the origin of page 0.
+pub fn pack(sizes: &[(usize, usize)], page_width: usize, page_height: usize, padding: usize) -> Result<(Vec<Placement>, usize), &'static str> {
+  let mut placements = vec![Placement { page: 0, x: 0, y: 0 }; sizes.len()];
+  let mut pages: Vec<Page> = Vec::new();
+
+  // Tall rectangles first keeps the skyline flat.
+  let mut order: Vec<usize> = (0..sizes.len()).filter(|&i| sizes[i].0 > 0 && sizes[i].1 > 0).collect();
+  order.sort_by(|&a, &b| sizes[b].1.cmp(&sizes[a].1).then(sizes[b].0.cmp(&sizes[a].0)));
+
+  for i in order {
+    let w = cmp::min(sizes[i].0 + padding, page_width);
+    let h = cmp::min(sizes[i].1 + padding, page_height);
+    if sizes[i].0 > page_width || sizes[i].1 > page_height {
+      return Err("pack: rectangle larger than the page.");
+    }
+
+    let found = pages.iter().enumerate()
+      .find_map(|(p, page)| page.find(w, h, page_width, page_height).map(|f| (p, f)));
+
+    let (p, (s, x, y)) = match found {
+      Some(f) => f,
+      None => {
+        pages.push(Page::new(page_width));
+        let p = pages.len() - 1;
+        (p, pages[p].find(w, h, page_width, page_height).unwrap())
+      }
+    };
+
+    pages[p].add(s, x, y, w, h);
+    placements[i] = Placement { page: p, x, y };
+  }
+
+  Ok((placements, pages.len()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_pack_without_overlap() {
+    let sizes: Vec<(usize, usize)> = (0..60).map(|i| (3 + i * 7 % 29, 2 + i * 11 % 23)).collect();
+    let (placements, pages) = pack(&sizes, 64, 64, 1).unwrap();
+
+    assert!(pages > 1);
+
+    for (i, (a, sa)) in placements.iter().zip(sizes.iter()).enumerate() {
+      assert!(a.x + sa.0 <= 64 && a.y + sa.1 <= 64);
+
+      for (b, sb) in placements[i + 1..].iter().zip(sizes[i + 1..].iter()) {
+        let overlap = a.page == b.page && a.x < b.x + sb.0 && b.x < a.x + sa.0 && a.y < b.y + sb.1 && b.y < a.y + sa.1;
+        assert!(!overlap);
+      }
+    }
+  }
+
+  #[test]
+  fn test_pack_rejects_oversized() {
+    assert!(pack(&[(10, 10), (65, 1)], 64, 64, 0).is_err());
+    assert_eq!(pack(&[(64, 64), (0, 0)], 64, 64, 1).unwrap().1, 1);
+  }
+}
@@ -1,32 +1,105 @@
-use web_sys::console;
-use wasm_bindgen::JsValue;
-// use image::dxt::{DXTEncoder, DXTVariant};
+use crate::texture::{PixelFormat, TextureFormat};
 
 use std::cmp;
-// use std::fmt;
-use std::io::{BufWriter, Write};
 
-struct BmdHeader {
-  num_frames: usize,
-  num_pixels: usize,
-  num_rows: usize,
+/// Magic of the frame, pixel and row sections.
+pub const SECTION_MAGIC: u16 = 0x03E9;
+const SECTION_HEADER_LENGTH: usize = 12;
+const FILE_HEADER_LENGTH: usize = 0x24;
+const FRAME_INFO_LENGTH: usize = 24;
+const EMPTY_ROW: u32 = 0xFFFFFFFF;
+
+#[derive(Clone, Debug)]
+pub struct BmdHeader {
+  pub magic: u32,
+  pub zero0: u32,
+  pub zero1: u32,
+  pub num_frames: u32,
+  pub num_pixels: u32,
+  pub num_rows: u32,
+  pub unknown0: u32,
+  pub unknown1: u32,
+  pub zero2: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BmdFrameType {
+  /// One palette index per pixel.
+  Normal,
+  /// Pixels without data, drawn as translucent black.
+  Shadow,
+  /// A palette index and a level per pixel.
+  Extended,
+}
+
+impl BmdFrameType {
+  pub fn from_u32(v: u32) -> Result<BmdFrameType, &'static str> {
+    match v {
+      1 => Ok(BmdFrameType::Normal),
+      2 => Ok(BmdFrameType::Shadow),
+      4 => Ok(BmdFrameType::Extended),
+      _ => Err("BmdFrameType: unknown frame type."),
+    }
+  }
+
+  pub fn id(&self) -> u32 {
+    match self {
+      BmdFrameType::Normal => 1,
+      BmdFrameType::Shadow => 2,
+      BmdFrameType::Extended => 4,
+    }
+  }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct BmdFrameInfo {
+  pub frame_type: BmdFrameType,
+  pub dx: i32,
+  pub dy: i32,
+  pub width: usize,
+  /// Number of rows.
+  pub len: usize,
+  /// Index of the first row in the row table.
+  pub off: usize,
 }
 
 #[derive(Copy, Clone, Debug)]
-struct BmdFrameInfo {
-  frame_type: u32,
-  dx: i32,
-  dy: i32,
-  width: usize,
-  len: usize,
-  off: usize,
+pub struct BmdRowInfo {
+  pub raw: u32,
+  /// Upper 10 bits: transparent pixels before the first block.
+  pub indent: usize,
+  /// Lower 22 bits: start of the row in the pixel section.
+  pub offset: usize,
+}
+
+impl BmdRowInfo {
+  pub fn from_raw(raw: u32) -> BmdRowInfo {
+    BmdRowInfo {
+      raw,
+      indent: (raw >> 22) as usize,
+      offset: (raw & ((1 << 22) - 1)) as usize,
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.raw == EMPTY_ROW
+  }
 }
 
 #[derive(Clone, Debug)]
-struct BmdRowInfo {
-  raw: u32,
-  indent: usize,
-  offset: usize,
+pub struct DecodedFrame {
+  pub width: usize,
+  pub height: usize,
+  pub data: Vec<u8>,
+}
+
+/// A parsed BMD sprite library. Pixel data is borrowed from the input.
+#[derive(Clone, Debug)]
+pub struct BmdFile<'a> {
+  pub header: BmdHeader,
+  pub frames: Vec<BmdFrameInfo>,
+  pub pixels: &'a [u8],
+  pub rows: Vec<BmdRowInfo>,
 }
 
 #[derive(Clone, Debug)]
@@ -43,124 +116,191 @@ fn read_uint32_le(buf: &[u8]) -> u32 {
   ((buf[3] as u32) << 24) + ((buf[2] as u32) << 16) + ((buf[1] as u32) << 8) + buf[0] as u32
 }
 
-fn read_bmd_header(buf: &[u8]) -> (&[u8], BmdHeader) {
+fn read_bmd_header(buf: &[u8]) -> Result<(&[u8], BmdHeader), &'static str> {
+  if buf.len() < FILE_HEADER_LENGTH {
+    return Err("read_bmd_header: buffer too short.");
+  }
+
   let header = BmdHeader {
-    num_frames: read_uint32_le(&buf[12..16]) as usize,
-    num_pixels: read_uint32_le(&buf[16..20]) as usize,
-    num_rows: read_uint32_le(&buf[20..24]) as usize,
+    magic: read_uint32_le(&buf[0..]),
+    zero0: read_uint32_le(&buf[4..]),
+    zero1: read_uint32_le(&buf[8..]),
+    num_frames: read_uint32_le(&buf[12..]),
+    num_pixels: read_uint32_le(&buf[16..]),
+    num_rows: read_uint32_le(&buf[20..]),
+    unknown0: read_uint32_le(&buf[24..]),
+    unknown1: read_uint32_le(&buf[28..]),
+    zero2: read_uint32_le(&buf[32..]),
   };
 
-  (&buf[0x24..], header)
+  Ok((&buf[FILE_HEADER_LENGTH..], header))
 }
 
-fn read_frames<'a>(buf: &'a[u8], frames: &mut [BmdFrameInfo]) -> Result<&'a[u8], &'static str> {
-  if buf[0] != 0xE9 || buf[1] != 0x03 {
-    return Err("read_frames: starting point is incorrect.");
+/// Splits a section into its content and the rest of the buffer.
+fn read_section<'a>(buf: &'a[u8], name: &'static str) -> Result<(&'a[u8], &'a[u8]), &'static str> {
+  if buf.len() < SECTION_HEADER_LENGTH || buf[0] != (SECTION_MAGIC & 0xFF) as u8 || buf[1] != (SECTION_MAGIC >> 8) as u8 {
+    return Err(name);
   }
 
   let section_length = read_uint32_le(&buf[0x08..]) as usize;
+  if buf.len() < SECTION_HEADER_LENGTH + section_length {
+    return Err(name);
+  }
+
+  Ok((&buf[SECTION_HEADER_LENGTH + section_length..], &buf[SECTION_HEADER_LENGTH..SECTION_HEADER_LENGTH + section_length]))
+}
 
-  for (ch, f) in buf[12..section_length + 12].chunks(24).zip(frames.iter_mut()) {
-    f.frame_type = read_uint32_le(&ch);
-    f.dx = read_uint32_le(&ch[4..]) as i32;
-    f.dy = read_uint32_le(&ch[8..]) as i32;
-    f.width = read_uint32_le(&ch[12..]) as usize;
-    f.len = read_uint32_le(&ch[16..]) as usize;
-    f.off = read_uint32_le(&ch[20..]) as usize;
+fn read_frames(buf: &[u8]) -> Result<(&[u8], Vec<BmdFrameInfo>), &'static str> {
+  let (rest, section) = read_section(buf, "read_frames: starting point is incorrect.")?;
+  if section.len() % FRAME_INFO_LENGTH != 0 {
+    return Err("read_frames: section length is not a multiple of the frame info length.");
   }
 
-  Ok(&buf[section_length + 12..])
+  let mut frames = Vec::with_capacity(section.len() / FRAME_INFO_LENGTH);
+
+  for ch in section.chunks_exact(FRAME_INFO_LENGTH) {
+    frames.push(BmdFrameInfo {
+      frame_type: BmdFrameType::from_u32(read_uint32_le(ch))?,
+      dx: read_uint32_le(&ch[4..]) as i32,
+      dy: read_uint32_le(&ch[8..]) as i32,
+      width: read_uint32_le(&ch[12..]) as usize,
+      len: read_uint32_le(&ch[16..]) as usize,
+      off: read_uint32_le(&ch[20..]) as usize,
+    });
+  }
+
+  Ok((rest, frames))
 }
 
-fn skip_section(buf: &[u8]) -> &[u8] {
-  let section_length = read_uint32_le(&buf[0x08..]) as usize;
-  &buf[12 + section_length..]
+fn read_rows(buf: &[u8]) -> Result<(&[u8], Vec<BmdRowInfo>), &'static str> {
+  let (rest, section) = read_section(buf, "read_rows: starting point is incorrect.")?;
+  if section.len() % 4 != 0 {
+    return Err("read_rows: section length is not a multiple of 4.");
+  }
+
+  let rows = section.chunks_exact(4).map(|ch| BmdRowInfo::from_raw(read_uint32_le(ch))).collect();
+
+  Ok((rest, rows))
+}
+
+fn read_pixels(buf: &[u8]) -> Result<(&[u8], &[u8]), &'static str> {
+  read_section(buf, "read_pixels: starting point is incorrect.")
 }
 
-fn read_rows<'a>(buf: &'a[u8], rows: &mut [BmdRowInfo]) -> Result<&'a[u8], &'static str> {
-  if buf[0] != 0xE9 || buf[1] != 0x03 {
-    return Err("read_frames: starting point is incorrect.");
+/// Parses a BMD file at the start of `buf` and returns the bytes after it,
+/// e.g. an appended shadow BMD. The value of the header magic is not known,
+/// so the header is checked through its zero fields and its counts instead.
+pub fn read_bmd_file(buf: &[u8]) -> Result<(&[u8], BmdFile<'_>), &'static str> {
+  let (rest, header) = read_bmd_header(buf)?;
+  if header.zero0 != 0 || header.zero1 != 0 || header.zero2 != 0 {
+    return Err("read_bmd_file: not a BMD header.");
   }
 
-  let section_length = read_uint32_le(&buf[0x08..]) as usize;
-  let mut rest: &[u8] = &buf[12..];
+  let (rest, frames) = read_frames(rest)?;
+  let (rest, pixels) = read_pixels(rest)?;
+  let (rest, rows) = read_rows(rest)?;
+
+  if header.num_frames as usize != frames.len() || header.num_rows as usize != rows.len() {
+    return Err("read_bmd_file: frame or row count does not match the header.");
+  }
 
-  for i in 0..(section_length / 4) {
-    let u = read_uint32_le(&rest);
-    rows[i].raw = u;
-    rows[i].indent = (u >> 22) as usize;
-    rows[i].offset = (u & ((1 << 22) - 1)) as usize;
-    rest = &rest[4..];
+  for f in frames.iter() {
+    if f.off.checked_add(f.len).is_none_or(|e| e > rows.len()) {
+      return Err("read_bmd_file: frame rows out of range.");
+    }
+  }
+
+  for r in rows.iter() {
+    if !r.is_empty() && r.offset >= pixels.len() {
+      return Err("read_bmd_file: row offset out of range.");
+    }
   }
 
-  Ok(&rest)
+  Ok((rest, BmdFile { header, frames, pixels, rows }))
 }
 
-fn read_pixels<'a>(buf: &'a[u8]) -> Result<(&'a[u8], &'a[u8]), &'static str> {
-  if buf[0] != 0xE9 || buf[1] != 0x03 {
-    return Err("read_pixels: starting point is incorrect.");
+impl<'a> BmdFile<'a> {
+  pub fn parse(buf: &'a [u8]) -> Result<BmdFile<'a>, &'static str> {
+    read_bmd_file(buf).map(|(_, bmd)| bmd)
   }
 
-  let section_length = read_uint32_le(&buf[0x08..]) as usize;
+  pub fn frame(&self, frame: usize) -> Result<&BmdFrameInfo, &'static str> {
+    self.frames.get(frame).ok_or("BmdFile: frame index out of range.")
+  }
 
-  Ok((&buf[section_length + 12..], &buf[12..12 + section_length]))
-}
+  pub fn frame_rows(&self, f: &BmdFrameInfo) -> &[BmdRowInfo] {
+    &self.rows[f.off..f.off + f.len]
+  }
 
-pub fn bmd_stats(buf: &[u8], has_shadow: &[u8], count: usize) -> Vec<BmdStats> {
-  let mut remaining_slice = buf;
-  let mut bmd_stats_vec = vec![BmdStats { frames: 0, width: 0, height: 0, encoded_length: 0 }; count];
+  /// Whether a frame has no rows with pixels.
+  pub fn is_frame_empty(&self, f: &BmdFrameInfo) -> bool {
+    self.frame_pixels(f).is_none()
+  }
 
-  for i in 0..count {
-    let (rest, header) = read_bmd_header(remaining_slice);
-    let mut frames = vec![BmdFrameInfo { frame_type: 0, dx: 0, dy: 0, width: 0, len: 0, off: 0 }; header.num_frames];
-    let mut shadow_frames: Option<Vec<BmdFrameInfo>> = None;
-    let rest = read_frames(rest, &mut frames[..]).expect("read_frames failed");
-    let rest = skip_section(rest);
-    let rest = skip_section(rest);
-    remaining_slice = rest;
+  /// Pixel data of a frame, starting at its first non-empty row. `None` for
+  /// frames without any pixels.
+  pub fn frame_pixels(&self, f: &BmdFrameInfo) -> Option<&'a [u8]> {
+    self.frame_rows(f).iter().find(|r| !r.is_empty()).map(|r| &self.pixels[r.offset..])
+  }
 
-    if has_shadow[i] > 0 {
-      let (rest, header) = read_bmd_header(remaining_slice);
-      let mut fv = vec![BmdFrameInfo { frame_type: 0, dx: 0, dy: 0, width: 0, len: 0, off: 0 }; header.num_frames];
-      let rest = read_frames(rest, &mut fv[..]).expect("read_frames failed");
-      let rest = skip_section(rest);
-      let rest = skip_section(rest);
-      remaining_slice = rest;
+  pub fn decode_frame(&self, frame: usize, palette: &[u8], pixel_format: PixelFormat) -> Result<DecodedFrame, &'static str> {
+    let f = self.frame(frame)?;
+    let mut data = vec![0u8; 4 * f.width * f.len];
+    read_bmd_frame(f.width, 0, 0, self, f, &mut data, &Paint { palette, pixel_format, shadow_alpha: DEFAULT_SHADOW_ALPHA })?;
 
-      shadow_frames = Some(fv);
-    }
+    Ok(DecodedFrame { width: f.width, height: f.len, data })
+  }
 
-    let mut stat = &mut bmd_stats_vec[i];
-    stat.frames += header.num_frames;
+  pub fn decode_frame_indexed(&self, frame: usize) -> Result<IndexedFrame, &'static str> {
+    let f = self.frame(frame)?;
+    let mut out = IndexedFrame::new(f.width, f.len);
+    read_bmd_frame_indexed(0, 0, self, f, &mut out)?;
 
-    if let Some(s_frames) = shadow_frames {
-      for (f, fs) in frames.iter().zip(s_frames.iter()) {
-        let x0 = cmp::min(f.dx, fs.dx);
-        let y0 = cmp::min(f.dy, fs.dy);
-        let x1 = cmp::max(f.width as i32 + f.dx, fs.width as i32 + fs.dx);
-        let y1 = cmp::max(f.width as i32 + f.dx, fs.width as i32 + fs.dx);
+    Ok(out)
+  }
+}
 
-        stat.width = cmp::max(stat.width, (x1 - x0) as usize);
-        stat.height = cmp::max(stat.height, (y1 - y0) as usize);
-      }
-    } else {
-      for f in frames {
-        if stat.width < f.width {
-          stat.width = f.width;
-        }
-        if stat.height < f.len {
-          stat.height = f.len;
-        }
-      }
-    }
+/// Area covered by a frame and its shadow frame. `x`/`y` is the top left
+/// corner relative to the anchor.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FrameBounds {
+  pub x: i32,
+  pub y: i32,
+  pub width: usize,
+  pub height: usize,
+}
+
+pub fn frame_bounds(f: &BmdFrameInfo, shadow: Option<&BmdFrameInfo>) -> FrameBounds {
+  let (mut x0, mut y0) = (f.dx, f.dy);
+  let (mut x1, mut y1) = (f.dx + f.width as i32, f.dy + f.len as i32);
+
+  if let Some(fs) = shadow {
+    x0 = cmp::min(x0, fs.dx);
+    y0 = cmp::min(y0, fs.dy);
+    x1 = cmp::max(x1, fs.dx + fs.width as i32);
+    y1 = cmp::max(y1, fs.dy + fs.len as i32);
+  }
+
+  FrameBounds { x: x0, y: y0, width: (x1 - x0) as usize, height: (y1 - y0) as usize }
+}
 
-    // stat.width += stat.width % 4;
-    // stat.height += stat.height % 4;
+/// Size of the texture array slot needed for every frame of a BMD and its
+/// optional shadow.
+pub fn bmd_stats(bmd: &BmdFile, shadow: Option<&BmdFile>, format: TextureFormat) -> BmdStats {
+  let mut stat = BmdStats { frames: bmd.frames.len(), width: 0, height: 0, encoded_length: 0 };
 
-    stat.encoded_length = 4 * stat.width * stat.height; // calc_output_size(stat.width as u32, stat.height as u32);
+  for (i, f) in bmd.frames.iter().enumerate() {
+    let b = frame_bounds(f, shadow.and_then(|s| s.frames.get(i)));
+    stat.width = cmp::max(stat.width, b.width);
+    stat.height = cmp::max(stat.height, b.height);
   }
 
-  return bmd_stats_vec;
+  let (width, height) = format.align(stat.width, stat.height);
+  stat.width = width;
+  stat.height = height;
+  stat.encoded_length = format.encoded_length(stat.width, stat.height);
+
+  return stat;
 }
 
 #[inline]
@@ -171,185 +311,679 @@ fn write_uint32_le(buf: &mut [u8], val: u32) {
   buf[3] = ((val & 0xFF000000) >> 24) as u8;
 }
 
-macro_rules! bmd {
-  ($e:expr) => {
-    {
-      let (rest, header) = read_bmd_header($e);
-    
-      let mut frames = vec![BmdFrameInfo { frame_type: 0, dx: 0, dy: 0, width: 0, len: 0, off: 0 }; header.num_frames];
-      let rest = read_frames(rest, &mut frames[..]).expect("read_frames failed");
-      let (rest, pixels) = read_pixels(rest).expect("read_pixels failed.");
-      let mut rows = vec![BmdRowInfo { raw: 0, indent: 0, offset: 0 }; header.num_rows];
-      let rest = read_rows(rest, &mut rows[..]).expect("read_rows failed.");
+/// Alpha of merged shadow pixels in the original game.
+pub const DEFAULT_SHADOW_ALPHA: u8 = 0x50;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ShadowMode {
+  /// Shadow pixels are drawn under the sprite with the given alpha.
+  Merged(u8),
+  /// Shadow pixels are written to their own layer as an opaque mask, so all
+  /// shadows can be drawn in one pass without darkening twice where they
+  /// overlap.
+  Separate,
+  Drop,
+}
 
-      (frames, (pixels, (rows, rest)))
+impl ShadowMode {
+  pub fn from_u8(mode: u8, alpha: u8) -> Option<ShadowMode> {
+    match mode {
+      0 => Some(ShadowMode::Merged(alpha)),
+      1 => Some(ShadowMode::Separate),
+      2 => Some(ShadowMode::Drop),
+      _ => None,
     }
-  };
-}
+  }
 
-pub fn read_bmd<'a>(w: usize, h: usize, instance_count: usize, has_shadow: bool, buf: &[u8], out: &mut [u8], frame_palette_index: &mut impl std::iter::Iterator<Item = (&'a usize, &'a usize)>, palettes: &Vec<&[u8]>, _debug: bool) -> usize {
-  // if _debug { console::log_2(&"read_bmd: 1".into(), &JsValue::from(has_shadow)); }
-  let (frames, (pixels, (rows, rest))) = bmd!(buf);
+  /// Layers written per frame.
+  pub fn layers(&self) -> usize {
+    match self {
+      ShadowMode::Separate => 2,
+      _ => 1,
+    }
+  }
+}
 
-  let mut frame_offset_ptr = 0usize;
-  let mut out_pointer: usize = instance_count * 8;
+/// How `read_bmd` writes its slots.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ReadOptions {
+  pub format: TextureFormat,
+  pub pixel_format: PixelFormat,
+  pub shadow_mode: ShadowMode,
+}
 
-  let encoded_frame_length = w * h * 4;
+/// Writes the `dx`/`dy` table and one slot per (frame, palette) instance of a
+/// BMD into `out`. With `ShadowMode::Separate` every slot is followed by the
+/// shadow slot of the instance. Returns the number of bytes written.
+pub fn read_bmd<'a>(stat: &BmdStats, bmd: &BmdFile, shadow: Option<&BmdFile>, out: &mut [u8], frame_palette_index: &mut impl std::iter::Iterator<Item = (&'a usize, &'a usize)>, palettes: &[&[u8]], options: &ReadOptions) -> Result<usize, &'static str> {
+  let ReadOptions { format, pixel_format, shadow_mode } = *options;
+  let (w, h) = (stat.width, stat.height);
+  let instances: Vec<(usize, usize)> = frame_palette_index.map(|(&fi, &pi)| (fi, pi)).collect();
 
-  if has_shadow {
-    let (s_frames, (s_pixels, (s_rows, _))) = bmd!(rest);
+  let mut frame_offset_ptr = 0usize;
+  let mut out_pointer: usize = instances.len() * 8;
 
-    for (i, (&fi, &pi)) in frame_palette_index.enumerate() {  // .map(|(&fi, &pi)| { (((&s_frames[fi], &frames[fi]), palettes[pi])) })
-      // if _debug { console::log_1(&format!("read_bmd #{}: begin - {} - fi: {} - pi: {}", &frames.len(), i, fi, pi).into()); }
+  let encoded_frame_length = format.encoded_length(w, h);
+  let mut frame = vec![0u8; w * h * 4];
 
-      if fi < frames.len() {
+  let (shadow, shadow_alpha) = match shadow_mode {
+    ShadowMode::Merged(alpha) => (shadow, alpha),
+    ShadowMode::Separate => (shadow, 0xFF),
+    ShadowMode::Drop => (None, 0),
+  };
+  let mut shadow_layer = vec![0u8; if shadow_mode == ShadowMode::Separate { w * h * 4 } else { 0 }];
 
-        let f = &frames[fi];
-        let p = &palettes[pi];
+  for (fi, pi) in instances {
+    if fi < bmd.frames.len() {
+      let f = &bmd.frames[fi];
+      let p = palettes.get(pi).ok_or("read_bmd: palette index out of range.")?;
 
-        if fi >= s_frames.len() {
+      match shadow.and_then(|s| s.frames.get(fi).map(|fs| (s, fs))) {
+        None => {
           write_uint32_le(&mut out[frame_offset_ptr..], f.dx as u32);
           write_uint32_le(&mut out[frame_offset_ptr + 4..], f.dy as u32);
 
-          read_bmd_frame(
-            w,
-            cmp::max(0, f.dx) as usize,
-            cmp::max(0, f.dy) as usize,
-            f,
-            &rows[f.off..f.off + f.len],
-            &pixels[rows[f.off].offset..],
-            &mut out[out_pointer..],
-            p,
-            _debug
-          );
-        } else {
-          let fs = &s_frames[fi];
-
+          read_bmd_frame(w, cmp::max(0, f.dx) as usize, cmp::max(0, f.dy) as usize, bmd, f, &mut frame[..], &Paint { palette: p, pixel_format, shadow_alpha })?;
+        }
+        Some((s, fs)) => {
           write_uint32_le(&mut out[frame_offset_ptr..], cmp::min(f.dx, fs.dx) as u32);
           write_uint32_le(&mut out[frame_offset_ptr + 4..], cmp::min(f.dy, fs.dy) as u32);
-    
-          // if _debug { console::log_1(&format!("read_bmd #{}", i).into()); }
-          read_bmd_frame(
-            w,
-            cmp::max(0, fs.dx - f.dx) as usize,
-            cmp::max(0, fs.dy - f.dy) as usize,
-            fs,
-            &s_rows[fs.off..fs.off + fs.len],
-            &s_pixels[s_rows[fs.off].offset..],
-            &mut out[out_pointer..],
-            p,
-            _debug
-          );
-          read_bmd_frame(
-            w,
-            cmp::max(0, f.dx - fs.dx) as usize,
-            cmp::max(0, f.dy - fs.dy) as usize,
-            f,
-            &rows[f.off..f.off + f.len],
-            &pixels[rows[f.off].offset..],
-            &mut out[out_pointer..],
-            p,
-            _debug
-          );
+
+          let target = if shadow_mode == ShadowMode::Separate { &mut shadow_layer[..] } else { &mut frame[..] };
+          read_bmd_frame(w, cmp::max(0, fs.dx - f.dx) as usize, cmp::max(0, fs.dy - f.dy) as usize, s, fs, target, &Paint { palette: p, pixel_format, shadow_alpha })?;
+          read_bmd_frame(w, cmp::max(0, f.dx - fs.dx) as usize, cmp::max(0, f.dy - fs.dy) as usize, bmd, f, &mut frame[..], &Paint { palette: p, pixel_format, shadow_alpha })?;
         }
       }
+    }
 
-      // if _debug { console::log_1(&format!("read_bmd #{}: done", i).into()); }
+    format.encode(&frame, w, h, &mut out[out_pointer..out_pointer + encoded_frame_length]);
+    frame.iter_mut().for_each(|b| *b = 0);
+    out_pointer += encoded_frame_length;
 
-      frame_offset_ptr += 8;
+    if shadow_mode == ShadowMode::Separate {
+      format.encode(&shadow_layer, w, h, &mut out[out_pointer..out_pointer + encoded_frame_length]);
+      shadow_layer.iter_mut().for_each(|b| *b = 0);
       out_pointer += encoded_frame_length;
     }
-  } else {
-    for (i, (&fi, &pi)) in frame_palette_index.enumerate() {  // .map(|(&fi, &pi)| { (((&s_frames[fi], &frames[fi]), palettes[pi])) })
-      if _debug { console::log_1(&format!("read_bmd #{}: begin - {} - fi: {} - pi: {}", &frames.len(), i, fi, pi).into()); }
 
-      if fi < frames.len() {
-        let f = &frames[fi];
-        let p = &palettes[pi];
+    frame_offset_ptr += 8;
+  }
+
+  Ok(out_pointer)
+}
+
+/// A frame drawn over its shadow frame. `x`/`y` is the position of the top
+/// left pixel relative to the anchor.
+#[derive(Clone, Debug, Default)]
+pub struct ComposedFrame {
+  pub x: i32,
+  pub y: i32,
+  pub width: usize,
+  pub height: usize,
+  pub data: Vec<u8>,
+}
+
+impl ComposedFrame {
+  /// Bounding box `(x, y, width, height)` of the visible pixels, `None` for an
+  /// empty frame.
+  pub fn trim(&self) -> Option<(usize, usize, usize, usize)> {
+    let visible = |x: usize, y: usize| self.data[4 * (y * self.width + x) + 3] != 0;
+
+    let y0 = (0..self.height).find(|&y| (0..self.width).any(|x| visible(x, y)))?;
+    let y1 = (0..self.height).rfind(|&y| (0..self.width).any(|x| visible(x, y))).unwrap();
+    let x0 = (0..self.width).find(|&x| (y0..=y1).any(|y| visible(x, y))).unwrap();
+    let x1 = (0..self.width).rfind(|&x| (y0..=y1).any(|y| visible(x, y))).unwrap();
+
+    Some((x0, y0, x1 - x0 + 1, y1 - y0 + 1))
+  }
+
+  /// Copies a `width` × `height` rectangle at `x`/`y` into `out`, whose rows
+  /// are `stride` pixels wide.
+  pub fn copy_rect(&self, x: usize, y: usize, width: usize, height: usize, out: &mut [u8], stride: usize) {
+    for row in 0..height {
+      let src = 4 * ((y + row) * self.width + x);
+      let dst = 4 * row * stride;
+      out[dst..dst + 4 * width].copy_from_slice(&self.data[src..src + 4 * width]);
+    }
+  }
+}
+
+/// Decodes frame `frame` of `bmd` on top of the same frame of `shadow` into an
+/// image that just covers both. Shadow pixels get `shadow_alpha`. Missing
+/// frames give an empty image.
+pub fn compose_frame(bmd: &BmdFile, shadow: Option<&BmdFile>, frame: usize, palette: &[u8], pixel_format: PixelFormat, shadow_alpha: u8) -> Result<ComposedFrame, &'static str> {
+  let f = match bmd.frames.get(frame) {
+    Some(f) => f,
+    None => return Ok(ComposedFrame::default()),
+  };
+
+  let fs = shadow.and_then(|s| s.frames.get(frame).map(|fs| (s, fs)));
+
+  let b = frame_bounds(f, fs.map(|(_, fs)| fs));
+  let (x0, y0, width, height) = (b.x, b.y, b.width, b.height);
+  let mut data = vec![0u8; 4 * width * height];
+
+  if let Some((s, fs)) = fs {
+    read_bmd_frame(width, (fs.dx - x0) as usize, (fs.dy - y0) as usize, s, fs, &mut data, &Paint { palette, pixel_format, shadow_alpha })?;
+  }
+  read_bmd_frame(width, (f.dx - x0) as usize, (f.dy - y0) as usize, bmd, f, &mut data, &Paint { palette, pixel_format, shadow_alpha })?;
+
+  Ok(ComposedFrame { x: x0, y: y0, width, height, data })
+}
+
+/// Like `compose_frame`, but draws the shadow into a second image of the same
+/// size as an opaque mask.
+pub fn compose_frame_separate(bmd: &BmdFile, shadow: &BmdFile, frame: usize, palette: &[u8], pixel_format: PixelFormat) -> Result<(ComposedFrame, ComposedFrame), &'static str> {
+  let f = match bmd.frames.get(frame) {
+    Some(f) => f,
+    None => return Ok((ComposedFrame::default(), ComposedFrame::default())),
+  };
+
+  let fs = shadow.frames.get(frame);
+  let b = frame_bounds(f, fs);
+  let mut sprite = ComposedFrame { x: b.x, y: b.y, width: b.width, height: b.height, data: vec![0u8; 4 * b.width * b.height] };
+  let mut mask = sprite.clone();
+
+  read_bmd_frame(sprite.width, (f.dx - sprite.x) as usize, (f.dy - sprite.y) as usize, bmd, f, &mut sprite.data, &Paint { palette, pixel_format, shadow_alpha: 0 })?;
+  if let Some(fs) = fs {
+    read_bmd_frame(mask.width, (fs.dx - mask.x) as usize, (fs.dy - mask.y) as usize, shadow, fs, &mut mask.data, &Paint { palette, pixel_format, shadow_alpha: 0xFF })?;
+  }
+
+  Ok((sprite, mask))
+}
+
+#[inline]
+fn next_byte(pixels: &[u8], ptr: &mut usize) -> Result<u8, &'static str> {
+  let v = *pixels.get(*ptr).ok_or("walk_frame: pixel data truncated.")?;
+  *ptr += 1;
+  Ok(v)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum BmdPixel {
+  Shadow,
+  /// Palette index and level.
+  Color(u8, u8),
+}
+
+/// Runs the pixel RLE of a frame and calls `draw` with the frame relative
+/// position of every drawn pixel.
+fn walk_frame(bmd: &BmdFile, fi: &BmdFrameInfo, mut draw: impl FnMut(usize, usize, BmdPixel) -> Result<(), &'static str>) -> Result<(), &'static str> {
+  let pixels = match bmd.frame_pixels(fi) {
+    Some(p) => p,
+    None => return Ok(()),
+  };
+
+  let mut pixels_ptr = 0;
+
+  for (y, r) in bmd.frame_rows(fi).iter().enumerate() {
+    if r.is_empty() { continue; }
+
+    let mut x = r.indent;
+    let mut pixel_block_length = next_byte(pixels, &mut pixels_ptr)? as usize;
+
+    while pixel_block_length != 0 {
+      if pixel_block_length < 0x80 {
+        for _ in 0..pixel_block_length {
+          let pixel = match fi.frame_type {
+            BmdFrameType::Shadow => BmdPixel::Shadow,
+            BmdFrameType::Normal => BmdPixel::Color(next_byte(pixels, &mut pixels_ptr)?, 0xFF),
+            BmdFrameType::Extended => {
+              let c = next_byte(pixels, &mut pixels_ptr)?;
+              BmdPixel::Color(c, next_byte(pixels, &mut pixels_ptr)?)
+            }
+          };
+          draw(x, y, pixel)?;
+          x += 1;
+        }
+      } else {
+        x += pixel_block_length - 0x80;
+      }
+
+      pixel_block_length = next_byte(pixels, &mut pixels_ptr)? as usize;
+    }
+  }
+
+  Ok(())
+}
+
+/// Colours of the pixels drawn by `read_bmd_frame`.
+#[derive(Copy, Clone)]
+struct Paint<'p> {
+  palette: &'p [u8],
+  pixel_format: PixelFormat,
+  shadow_alpha: u8,
+}
+
+fn read_bmd_frame(w: usize, p_w: usize, p_h: usize, bmd: &BmdFile, fi: &BmdFrameInfo, out: &mut [u8], paint: &Paint) -> Result<(), &'static str> {
+  let Paint { palette, pixel_format, shadow_alpha } = *paint;
 
-        if _debug { console::log_1(&format!("read_bmd (no shadow) #{}: dx: {}, dy: {}", i, f.dx, f.dy).into()); }
+  walk_frame(bmd, fi, |x, y, pixel| {
+    let out_pos = 4 * ((y + p_h) * w + x + p_w);
+    let px = out.get_mut(out_pos..out_pos + 4).ok_or("read_bmd_frame: frame does not fit the output.")?;
 
+    match pixel {
+      BmdPixel::Shadow => pixel_format.write(px, 0, 0, 0, shadow_alpha),
+      BmdPixel::Color(c, level) => {
+        let c = 3 * c as usize;
+        pixel_format.write(px, palette[c], palette[c + 1], palette[c + 2], level);
+      }
+    }
+
+    Ok(())
+  })
+}
+
+/// A frame decoded without a palette. All planes are `width` × `height`.
+#[derive(Clone, Debug)]
+pub struct IndexedFrame {
+  pub width: usize,
+  pub height: usize,
+  /// Palette index of every pixel, 0 where nothing is drawn.
+  pub indices: Vec<u8>,
+  /// Coverage: 0xFF for normal pixels, the level for extended pixels and 0
+  /// for transparent and shadow pixels.
+  pub alpha: Vec<u8>,
+  /// 0xFF where a shadow pixel is drawn.
+  pub shadow: Vec<u8>,
+}
+
+impl IndexedFrame {
+  pub fn new(width: usize, height: usize) -> IndexedFrame {
+    IndexedFrame {
+      width,
+      height,
+      indices: vec![0u8; width * height],
+      alpha: vec![0u8; width * height],
+      shadow: vec![0u8; width * height],
+    }
+  }
+
+  /// Writes the index, alpha and shadow planes one after another.
+  pub fn write_planes(&self, out: &mut [u8]) {
+    let n = self.width * self.height;
+    out[..n].copy_from_slice(&self.indices);
+    out[n..2 * n].copy_from_slice(&self.alpha);
+    out[2 * n..3 * n].copy_from_slice(&self.shadow);
+  }
+
+  fn clear(&mut self) {
+    self.indices.iter_mut().for_each(|b| *b = 0);
+    self.alpha.iter_mut().for_each(|b| *b = 0);
+    self.shadow.iter_mut().for_each(|b| *b = 0);
+  }
+}
+
+fn read_bmd_frame_indexed(p_w: usize, p_h: usize, bmd: &BmdFile, fi: &BmdFrameInfo, out: &mut IndexedFrame) -> Result<(), &'static str> {
+  let (w, h) = (out.width, out.height);
+
+  walk_frame(bmd, fi, |x, y, pixel| {
+    let (x, y) = (x + p_w, y + p_h);
+    if x >= w || y >= h {
+      return Err("read_bmd_frame_indexed: frame does not fit the output.");
+    }
+
+    let pos = y * w + x;
+    match pixel {
+      BmdPixel::Shadow => out.shadow[pos] = 0xFF,
+      BmdPixel::Color(c, level) => {
+        out.indices[pos] = c;
+        out.alpha[pos] = level;
+        out.shadow[pos] = 0;
+      }
+    }
+
+    Ok(())
+  })
+}
+
+/// Palette independent counterpart of `read_bmd`: writes the `dx`/`dy` table
+/// and the index, alpha and shadow planes of every frame into `out`. Returns
+/// the number of bytes written.
+pub fn read_bmd_indexed(stat: &BmdStats, bmd: &BmdFile, shadow: Option<&BmdFile>, out: &mut [u8]) -> Result<usize, &'static str> {
+  let mut frame_offset_ptr = 0usize;
+  let mut out_pointer: usize = bmd.frames.len() * 8;
+
+  let plane_length = stat.width * stat.height;
+  let mut frame = IndexedFrame::new(stat.width, stat.height);
+
+  for (fi, f) in bmd.frames.iter().enumerate() {
+    match shadow.and_then(|s| s.frames.get(fi).map(|fs| (s, fs))) {
+      None => {
         write_uint32_le(&mut out[frame_offset_ptr..], f.dx as u32);
         write_uint32_le(&mut out[frame_offset_ptr + 4..], f.dy as u32);
 
-        read_bmd_frame(
-          w,
-          cmp::max(0, f.dx) as usize,
-          cmp::max(0, f.dy) as usize,
-          f,
-          &rows[f.off..f.off + f.len],
-          &pixels[rows[f.off].offset..],
-          &mut out[out_pointer..],
-          p,
-          _debug
-        );
+        read_bmd_frame_indexed(cmp::max(0, f.dx) as usize, cmp::max(0, f.dy) as usize, bmd, f, &mut frame)?;
       }
+      Some((s, fs)) => {
+        write_uint32_le(&mut out[frame_offset_ptr..], cmp::min(f.dx, fs.dx) as u32);
+        write_uint32_le(&mut out[frame_offset_ptr + 4..], cmp::min(f.dy, fs.dy) as u32);
 
-      frame_offset_ptr += 8;
-      out_pointer += encoded_frame_length;
+        read_bmd_frame_indexed(cmp::max(0, fs.dx - f.dx) as usize, cmp::max(0, fs.dy - f.dy) as usize, s, fs, &mut frame)?;
+        read_bmd_frame_indexed(cmp::max(0, f.dx - fs.dx) as usize, cmp::max(0, f.dy - fs.dy) as usize, bmd, f, &mut frame)?;
+      }
     }
+
+    frame.write_planes(&mut out[out_pointer..out_pointer + 3 * plane_length]);
+    frame.clear();
+
+    frame_offset_ptr += 8;
+    out_pointer += 3 * plane_length;
   }
 
-  return out_pointer;
+  Ok(out_pointer)
 }
 
-fn read_bmd_frame(w: usize, p_w: usize, p_h: usize, fi: &BmdFrameInfo, rows: &[BmdRowInfo], pixels: &[u8], out: &mut [u8], palette: &[u8], _debug: bool) {
-  let mut out_pos;
-  let mut pixels_ptr = 0;
+/// A frame to encode. Normal and extended frames draw the pixels with a
+/// non-zero `alpha`, shadow frames the pixels with a non-zero `shadow`.
+/// Extended pixels with level 0 are therefore written as transparent, and
+/// their palette index is dropped: a level 0 pixel would draw nothing anyway.
+#[derive(Clone, Debug)]
+pub struct BmdFrameInput {
+  pub frame_type: BmdFrameType,
+  pub dx: i32,
+  pub dy: i32,
+  pub image: IndexedFrame,
+}
+
+impl BmdFrameInput {
+  #[inline]
+  fn is_drawn(&self, pos: usize) -> bool {
+    match self.frame_type {
+      BmdFrameType::Shadow => self.image.shadow[pos] != 0,
+      BmdFrameType::Normal | BmdFrameType::Extended => self.image.alpha[pos] != 0,
+    }
+  }
+}
+
+const MAX_BLOCK: usize = 0x7F;
+const MAX_INDENT: usize = (1 << 10) - 1;
+const MAX_OFFSET: usize = (1 << 22) - 1;
 
-  // println!("#### {}", rows.len());
+fn write_section(out: &mut Vec<u8>, content: &[u8]) {
+  out.extend_from_slice(&(SECTION_MAGIC as u32).to_le_bytes());
+  out.extend_from_slice(&0u32.to_le_bytes());
+  out.extend_from_slice(&(content.len() as u32).to_le_bytes());
+  out.extend_from_slice(content);
+}
+
+/// Encodes one row and returns its indent, or `None` for an empty row.
+fn encode_row(f: &BmdFrameInput, y: usize, pixels: &mut Vec<u8>) -> Option<usize> {
+  let w = f.image.width;
+  let row = y * w;
+  let first = (0..w).find(|&x| f.is_drawn(row + x))?;
+  let last = (0..w).rfind(|&x| f.is_drawn(row + x)).unwrap();
+
+  let indent = cmp::min(first, MAX_INDENT);
+  let mut x = indent;
+
+  while x <= last {
+    let drawn = f.is_drawn(row + x);
+    let mut n = 0;
+    while x + n <= last && n < MAX_BLOCK && f.is_drawn(row + x + n) == drawn {
+      n += 1;
+    }
+
+    if drawn {
+      pixels.push(n as u8);
+      for pos in row + x..row + x + n {
+        match f.frame_type {
+          BmdFrameType::Shadow => {}
+          BmdFrameType::Normal => pixels.push(f.image.indices[pos]),
+          BmdFrameType::Extended => {
+            pixels.push(f.image.indices[pos]);
+            pixels.push(f.image.alpha[pos]);
+          }
+        }
+      }
+    } else {
+      pixels.push((0x80 + n) as u8);
+    }
 
-  for (i, r) in rows.iter().enumerate() {
-    // if _debug { console::log_2(&"read_bmd_frame: row:".into(), &JsValue::from(i as u32)); }
-    // if _debug { console::log_1(&format!("r.indent = {}, r.offset = {}", r.indent, r.offset).into()); }
+    x += n;
+  }
 
-    // println!("{:?}", r);
+  pixels.push(0);
 
-    if pixels_ptr >= pixels.len() { return; }
-    if r.raw as i32 == -1 { continue; }
+  Some(indent)
+}
 
-    out_pos = 4 * ((i + p_h) * w + r.indent + p_w);
-    // if _debug { console::log_1(&format!("{} = 4 * (({} + {}) * {} + {} + {})", out_pos, i, p_h, w, r.indent, p_w).into()); }
+/// Writes a BMD file. The header fields other than the counts are copied from
+/// `template`, e.g. the header of a file from the game.
+pub fn encode_bmd(template: &BmdHeader, frames: &[BmdFrameInput]) -> Result<Vec<u8>, &'static str> {
+  let mut frame_section = Vec::with_capacity(frames.len() * FRAME_INFO_LENGTH);
+  let mut pixels = Vec::new();
+  let mut rows: Vec<u32> = Vec::new();
 
-    let mut pixel_block_length: usize = pixels[pixels_ptr] as usize; pixels_ptr += 1;
+  for f in frames {
+    let (w, h) = (f.image.width, f.image.height);
 
-    while pixel_block_length != 0 {
-      if pixel_block_length < 0x80 {
-        // if _debug { console::log_1(&format!("out_pos = {}, out.len() = {}", out_pos, out.len()).into()); }
-        // if _debug { console::log_1(&format!("writing {} pixels", pixel_block_length).into()); }
+    for v in [f.frame_type.id(), f.dx as u32, f.dy as u32, w as u32, h as u32, rows.len() as u32].iter() {
+      frame_section.extend_from_slice(&v.to_le_bytes());
+    }
 
-        for _ in 0..pixel_block_length {
-          // if _debug { console::log_1(&format!("pixels #{}", j).into()); }
-
-          if fi.frame_type == 2 {     // Shadow frame
-            out[out_pos + 0] = 0;
-            out[out_pos + 1] = 0;
-            out[out_pos + 2] = 0;
-            out[out_pos + 3] = 0x50;
-          } else if fi.frame_type == 1 {    // Normal frame
-            let color_index = pixels[pixels_ptr] as usize; pixels_ptr += 1;
-            out[out_pos + 0] = palette[3 * color_index + 0];
-            out[out_pos + 1] = palette[3 * color_index + 1];
-            out[out_pos + 2] = palette[3 * color_index + 2];
-            out[out_pos + 3] = 0xFF;
-          } else if fi.frame_type == 4 {    // Extended frame
-            let color_index = pixels[pixels_ptr] as usize; pixels_ptr += 1;
-            let pixel_level = pixels[pixels_ptr]; pixels_ptr += 1;
-
-            out[out_pos + 0] = palette[3 * color_index + 0];
-            out[out_pos + 1] = palette[3 * color_index + 1];
-            out[out_pos + 2] = palette[3 * color_index + 2];
-            out[out_pos + 3] = pixel_level; // if pixel_level == 255 { 0xFF } else { 0x00 };
-          } else {
-            // console::log_2(&"read_bmd: frame type unknown:".into(), &JsValue::from(fi.frame_type as u32));
+    for y in 0..h {
+      let offset = pixels.len();
+
+      let raw = match encode_row(f, y, &mut pixels) {
+        None => EMPTY_ROW,
+        Some(indent) => {
+          if offset > MAX_OFFSET {
+            return Err("encode_bmd: pixel section exceeds 22-bit row offsets.");
+          }
+          ((indent as u32) << 22) | offset as u32
+        }
+      };
+
+      rows.push(raw);
+    }
+  }
+
+  let mut out = Vec::with_capacity(FILE_HEADER_LENGTH + 3 * SECTION_HEADER_LENGTH + frame_section.len() + pixels.len() + 4 * rows.len());
+  for v in [template.magic, template.zero0, template.zero1, frames.len() as u32, pixels.len() as u32, rows.len() as u32, template.unknown0, template.unknown1, template.zero2].iter() {
+    out.extend_from_slice(&v.to_le_bytes());
+  }
+
+  write_section(&mut out, &frame_section);
+  write_section(&mut out, &pixels);
+  write_section(&mut out, &rows.iter().flat_map(|r| r.to_le_bytes().to_vec()).collect::<Vec<u8>>());
+
+  Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn header() -> BmdHeader {
+    BmdHeader { magic: 0x25, zero0: 0, zero1: 0, num_frames: 0, num_pixels: 0, num_rows: 0, unknown0: 0, unknown1: 0, zero2: 0 }
+  }
+
+  fn input(frame_type: BmdFrameType, width: usize, height: usize, dx: i32, dy: i32) -> BmdFrameInput {
+    let mut image = IndexedFrame::new(width, height);
+
+    for y in 0..height {
+      for x in 0..width {
+        let pos = y * width + x;
+        // Leave holes and empty rows to exercise skips and indents.
+        if y == 1 || (x + y) % 5 == 0 {
+          continue;
+        }
+
+        match frame_type {
+          BmdFrameType::Shadow => image.shadow[pos] = 0xFF,
+          BmdFrameType::Normal => {
+            image.indices[pos] = (x * 7 + y) as u8;
+            image.alpha[pos] = 0xFF;
+          }
+          BmdFrameType::Extended => {
+            image.indices[pos] = (x * 3 + y) as u8;
+            image.alpha[pos] = (1 + x + y) as u8;
           }
-          out_pos += 4;
         }
-      } else {
-        out_pos += 4 * 1 * (pixel_block_length - 0x80);
       }
+    }
+
+    BmdFrameInput { frame_type, dx, dy, image }
+  }
+
+  #[test]
+  fn test_encode_roundtrip() {
+    let frames = vec![
+      input(BmdFrameType::Normal, 300, 4, -5, 3),
+      input(BmdFrameType::Extended, 9, 6, 0, -2),
+      input(BmdFrameType::Shadow, 12, 3, 1, 1),
+      BmdFrameInput { frame_type: BmdFrameType::Normal, dx: 0, dy: 0, image: IndexedFrame::new(4, 2) },
+    ];
+
+    let buf = encode_bmd(&header(), &frames).unwrap();
+    let bmd = BmdFile::parse(&buf).unwrap();
+
+    assert_eq!(bmd.header.magic, 0x25);
+    assert_eq!(bmd.frames.len(), frames.len());
+
+    for (i, f) in frames.iter().enumerate() {
+      let info = bmd.frame(i).unwrap();
+      assert_eq!((info.frame_type, info.dx, info.dy, info.width, info.len), (f.frame_type, f.dx, f.dy, f.image.width, f.image.height));
+
+      let decoded = bmd.decode_frame_indexed(i).unwrap();
+      assert_eq!(decoded.indices, f.image.indices);
+      assert_eq!(decoded.alpha, f.image.alpha);
+      assert_eq!(decoded.shadow, f.image.shadow);
+    }
+  }
+
+  #[test]
+  fn test_encode_level_zero_is_transparent() {
+    let mut f = input(BmdFrameType::Extended, 3, 1, 0, 0);
+    f.image.indices = vec![9, 9, 9];
+    f.image.alpha = vec![0x40, 0, 0x40];
+
+    let buf = encode_bmd(&header(), &[f]).unwrap();
+    let decoded = BmdFile::parse(&buf).unwrap().decode_frame_indexed(0).unwrap();
+    assert_eq!(decoded.indices, vec![9, 0, 9]);
+    assert_eq!(decoded.alpha, vec![0x40, 0, 0x40]);
+  }
+
+  #[test]
+  fn test_compose_frame_with_shadow() {
+    let bmd_buf = encode_bmd(&header(), &[input(BmdFrameType::Normal, 4, 3, -2, -3)]).unwrap();
+    let shadow_buf = encode_bmd(&header(), &[input(BmdFrameType::Shadow, 6, 2, 1, -1)]).unwrap();
+    let (bmd, shadow) = (BmdFile::parse(&bmd_buf).unwrap(), BmdFile::parse(&shadow_buf).unwrap());
+    let palette = vec![0x40u8; 768];
+
+    let frame = compose_frame(&bmd, Some(&shadow), 0, &palette, PixelFormat::STRAIGHT_SRGB, DEFAULT_SHADOW_ALPHA).unwrap();
+    assert_eq!((frame.x, frame.y, frame.width, frame.height), (-2, -3, 9, 4));
+    // The frame is drawn over the shadow.
+    assert_eq!(&frame.data[4 * (2 * 9 + 2)..4 * (2 * 9 + 3)], &[0x40, 0x40, 0x40, 0xFF]);
+    assert_eq!(frame.trim(), Some((0, 0, 8, 3)));
+
+    let missing = compose_frame(&bmd, None, 5, &palette, PixelFormat::STRAIGHT_SRGB, DEFAULT_SHADOW_ALPHA).unwrap();
+    assert_eq!(missing.trim(), None);
+
+    let (sprite, mask) = compose_frame_separate(&bmd, &shadow, 0, &palette, PixelFormat::STRAIGHT_SRGB).unwrap();
+    assert_eq!((sprite.x, sprite.y, sprite.width, sprite.height), (-2, -3, 9, 4));
+    assert_eq!((mask.x, mask.y, mask.width, mask.height), (-2, -3, 9, 4));
+    assert_eq!(sprite.trim(), Some((0, 0, 4, 3)));
+    assert_eq!(mask.trim(), Some((4, 2, 4, 1)));
+    assert_eq!(&mask.data[4 * (2 * 9 + 4)..4 * (2 * 9 + 5)], &[0, 0, 0, 0xFF]);
+  }
+
+  #[test]
+  fn test_bmd_stats_uses_row_count() {
+    let bmd_buf = encode_bmd(&header(), &[input(BmdFrameType::Normal, 4, 10, 0, -10)]).unwrap();
+    let shadow_buf = encode_bmd(&header(), &[input(BmdFrameType::Shadow, 12, 2, 0, -1)]).unwrap();
+    let (bmd, shadow) = (BmdFile::parse(&bmd_buf).unwrap(), BmdFile::parse(&shadow_buf).unwrap());
+
+    let stat = bmd_stats(&bmd, Some(&shadow), TextureFormat::Rgba8);
+    assert_eq!((stat.width, stat.height), (12, 11));
+  }
+
+  #[test]
+  fn test_read_bmd_shadow_modes() {
+    let bmd_buf = encode_bmd(&header(), &[input(BmdFrameType::Normal, 2, 1, 0, 0)]).unwrap();
+    let shadow_buf = encode_bmd(&header(), &[input(BmdFrameType::Shadow, 3, 1, 0, 0)]).unwrap();
+    let (bmd, shadow) = (BmdFile::parse(&bmd_buf).unwrap(), BmdFile::parse(&shadow_buf).unwrap());
+    let palette = vec![0x40u8; 768];
+    let stat = bmd_stats(&bmd, Some(&shadow), TextureFormat::Rgba8);
+
+    let read = |mode: ShadowMode| {
+      let mut out = vec![0u8; 8 + mode.layers() * 4 * 3];
+      let n = read_bmd(&stat, &bmd, Some(&shadow), &mut out, &mut [(&0, &0)].iter().cloned(), &[&palette], &ReadOptions { format: TextureFormat::Rgba8, pixel_format: PixelFormat::STRAIGHT_SRGB, shadow_mode: mode }).unwrap();
+      assert_eq!(n, out.len());
+      out
+    };
+
+    // The first pixel of every row is transparent in the test frames.
+    assert_eq!(&read(ShadowMode::Merged(0x30))[8..], &[0, 0, 0, 0, 0x40, 0x40, 0x40, 0xFF, 0, 0, 0, 0x30]);
+    assert_eq!(&read(ShadowMode::Drop)[8..], &[0, 0, 0, 0, 0x40, 0x40, 0x40, 0xFF, 0, 0, 0, 0]);
+    assert_eq!(&read(ShadowMode::Separate)[20..], &[0, 0, 0, 0, 0, 0, 0, 0xFF, 0, 0, 0, 0xFF]);
+  }
+
+  /// A single frame BMD written by hand, independent of `encode_bmd`.
+  fn raw_bmd(frame: [u32; 6], pixels: &[u8], rows: &[u32]) -> Vec<u8> {
+    let mut buf = vec![0u8; FILE_HEADER_LENGTH];
+    write_uint32_le(&mut buf[0..], 0x25);
+    write_uint32_le(&mut buf[12..], 1);
+    write_uint32_le(&mut buf[16..], pixels.len() as u32);
+    write_uint32_le(&mut buf[20..], rows.len() as u32);
+
+    let frame: Vec<u8> = frame.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+    let rows: Vec<u8> = rows.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+    for section in [&frame[..], pixels, &rows[..]].iter() {
+      buf.extend_from_slice(&[0xE9, 0x03, 0, 0, 0, 0, 0, 0]);
+      buf.extend_from_slice(&(section.len() as u32).to_le_bytes());
+      buf.extend_from_slice(section);
+    }
+
+    buf
+  }
+
+  #[test]
+  fn test_read_bmd_indexed_known_frame() {
+    // An extended 3×2 frame: two pixels in the first row, one indented pixel
+    // in the second.
+    let buf = raw_bmd([4, 0, 0, 3, 2, 0], &[2, 10, 0xFF, 11, 0x80, 0, 1, 12, 0x40, 0], &[0, 1 << 22 | 6]);
+    // A 3×1 shadow frame, one pixel to the right.
+    let shadow_buf = raw_bmd([2, 1, 0, 3, 1, 0], &[3, 0], &[0]);
+    let (bmd, shadow) = (BmdFile::parse(&buf).unwrap(), BmdFile::parse(&shadow_buf).unwrap());
+
+    let frame = bmd.decode_frame_indexed(0).unwrap();
+    assert_eq!(frame.indices, vec![10, 11, 0, 0, 12, 0]);
+    assert_eq!(frame.alpha, vec![0xFF, 0x80, 0, 0, 0x40, 0]);
+    assert_eq!(frame.shadow, vec![0; 6]);
+
+    let stat = bmd_stats(&bmd, Some(&shadow), TextureFormat::Rgba8);
+    assert_eq!((stat.width, stat.height), (4, 2));
+
+    let mut out = vec![0u8; 8 + 3 * 8];
+    assert_eq!(read_bmd_indexed(&stat, &bmd, Some(&shadow), &mut out).unwrap(), out.len());
+    assert_eq!(&out[..8], &[0; 8]);
+    // Index, alpha and shadow planes. The sprite covers the shadow.
+    assert_eq!(&out[8..16], &[10, 11, 0, 0, 0, 12, 0, 0]);
+    assert_eq!(&out[16..24], &[0xFF, 0x80, 0, 0, 0, 0x40, 0, 0]);
+    assert_eq!(&out[24..32], &[0, 0, 0xFF, 0xFF, 0, 0, 0, 0]);
+  }
+
+  #[test]
+  fn test_parse_rejects_bad_section_magic() {
+    let mut buf = encode_bmd(&header(), &[input(BmdFrameType::Normal, 4, 4, 0, 0)]).unwrap();
+    buf[FILE_HEADER_LENGTH] = 0;
+
+    assert!(BmdFile::parse(&buf).is_err());
+    assert!(BmdFile::parse(&buf[..10]).is_err());
+  }
+
+  #[test]
+  fn test_parse_checks_header() {
+    let buf = encode_bmd(&header(), &[input(BmdFrameType::Normal, 4, 4, 0, 0), input(BmdFrameType::Normal, 2, 2, 0, 0)]).unwrap();
+    assert!(BmdFile::parse(&buf).is_ok());
 
-      pixel_block_length = pixels[pixels_ptr] as usize; pixels_ptr += 1;
+    // num_frames, num_rows and a zero field.
+    for &at in &[12, 20, 32] {
+      let mut bad = buf.clone();
+      bad[at] += 1;
+      assert!(BmdFile::parse(&bad).is_err(), "byte {}", at);
     }
   }
 }
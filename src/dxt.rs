@@ -0,0 +1,235 @@
+use std::cmp;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DxtVariant {
+  /// 8 bytes per block, RGB with 1-bit alpha.
+  BC1,
+  /// 16 bytes per block, RGB with interpolated 8-bit alpha.
+  BC3,
+}
+
+impl DxtVariant {
+  pub fn block_size(&self) -> usize {
+    match self {
+      DxtVariant::BC1 => 8,
+      DxtVariant::BC3 => 16,
+    }
+  }
+}
+
+/// Rounds a dimension up to the next multiple of the 4×4 block size.
+#[inline]
+pub fn align_to_block(n: usize) -> usize {
+  (n + 3) & !3
+}
+
+pub fn calc_output_size(width: usize, height: usize, variant: DxtVariant) -> usize {
+  (align_to_block(width) / 4) * (align_to_block(height) / 4) * variant.block_size()
+}
+
+/// Compresses a `width` × `height` RGBA image. Sizes that are not a multiple
+/// of 4 are padded by repeating the last row and column.
+pub fn encode(rgba: &[u8], width: usize, height: usize, variant: DxtVariant, out: &mut [u8]) {
+  let blocks_x = align_to_block(width) / 4;
+  let blocks_y = align_to_block(height) / 4;
+  let block_size = variant.block_size();
+  let mut block = [[0u8; 4]; 16];
+
+  for by in 0..blocks_y {
+    for bx in 0..blocks_x {
+      for (i, px) in block.iter_mut().enumerate() {
+        let x = cmp::min(bx * 4 + i % 4, width - 1);
+        let y = cmp::min(by * 4 + i / 4, height - 1);
+        let p = 4 * (y * width + x);
+        px.copy_from_slice(&rgba[p..p + 4]);
+      }
+
+      let o = (by * blocks_x + bx) * block_size;
+
+      match variant {
+        DxtVariant::BC1 => encode_color_block(&block, true, &mut out[o..o + 8]),
+        DxtVariant::BC3 => {
+          encode_alpha_block(&block, &mut out[o..o + 8]);
+          encode_color_block(&block, false, &mut out[o + 8..o + 16]);
+        }
+      }
+    }
+  }
+}
+
+#[inline]
+fn to_565(c: &[u8]) -> u16 {
+  let r = (c[0] as u16 * 31 + 127) / 255;
+  let g = (c[1] as u16 * 63 + 127) / 255;
+  let b = (c[2] as u16 * 31 + 127) / 255;
+  (r << 11) | (g << 5) | b
+}
+
+#[inline]
+fn from_565(c: u16) -> [i32; 3] {
+  let r = ((c >> 11) & 0x1F) as i32;
+  let g = ((c >> 5) & 0x3F) as i32;
+  let b = (c & 0x1F) as i32;
+  [(r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2)]
+}
+
+#[inline]
+fn distance(a: &[i32; 3], b: &[u8]) -> i32 {
+  let dr = a[0] - b[0] as i32;
+  let dg = a[1] - b[1] as i32;
+  let db = a[2] - b[2] as i32;
+  dr * dr + dg * dg + db * db
+}
+
+/// Uses the corners of the colour bounding box of the opaque pixels as end
+/// points, which is cheap and good enough for the mostly smooth game textures.
+fn endpoints(block: &[[u8; 4]; 16], punch_through: bool) -> Option<(u16, u16)> {
+  let opaque = block.iter().filter(|px| !punch_through || px[3] >= 0x80);
+
+  let mut min = [255u8; 3];
+  let mut max = [0u8; 3];
+  let mut any = false;
+
+  for px in opaque {
+    any = true;
+    for c in 0..3 {
+      min[c] = cmp::min(min[c], px[c]);
+      max[c] = cmp::max(max[c], px[c]);
+    }
+  }
+
+  if !any {
+    return None;
+  }
+
+  // Inset the bounding box slightly to reduce the error of the end points.
+  for c in 0..3 {
+    let inset = (max[c] - min[c]) / 16;
+    min[c] += inset;
+    max[c] -= inset;
+  }
+
+  Some((to_565(&max), to_565(&min)))
+}
+
+fn encode_color_block(block: &[[u8; 4]; 16], punch_through: bool, out: &mut [u8]) {
+  let has_transparency = punch_through && block.iter().any(|px| px[3] < 0x80);
+
+  let (mut c0, mut c1) = endpoints(block, punch_through).unwrap_or_default();
+
+  // Four colour mode needs c0 > c1, three colour mode with transparency c0 <= c1.
+  if has_transparency {
+    if c0 > c1 {
+      std::mem::swap(&mut c0, &mut c1);
+    }
+  } else if c0 < c1 {
+    std::mem::swap(&mut c0, &mut c1);
+  }
+
+  let e0 = from_565(c0);
+  let e1 = from_565(c1);
+
+  let palette: Vec<[i32; 3]> = if c0 > c1 {
+    vec![
+      e0,
+      e1,
+      [(2 * e0[0] + e1[0]) / 3, (2 * e0[1] + e1[1]) / 3, (2 * e0[2] + e1[2]) / 3],
+      [(e0[0] + 2 * e1[0]) / 3, (e0[1] + 2 * e1[1]) / 3, (e0[2] + 2 * e1[2]) / 3],
+    ]
+  } else {
+    vec![e0, e1, [(e0[0] + e1[0]) / 2, (e0[1] + e1[1]) / 2, (e0[2] + e1[2]) / 2]]
+  };
+
+  let mut indices = 0u32;
+
+  for (i, px) in block.iter().enumerate() {
+    let index = if has_transparency && px[3] < 0x80 {
+      3
+    } else {
+      (0..palette.len()).min_by_key(|&j| distance(&palette[j], px)).unwrap() as u32
+    };
+
+    indices |= index << (2 * i);
+  }
+
+  out[0..2].copy_from_slice(&c0.to_le_bytes());
+  out[2..4].copy_from_slice(&c1.to_le_bytes());
+  out[4..8].copy_from_slice(&indices.to_le_bytes());
+}
+
+fn encode_alpha_block(block: &[[u8; 4]; 16], out: &mut [u8]) {
+  let a0 = block.iter().map(|px| px[3]).max().unwrap();
+  let a1 = block.iter().map(|px| px[3]).min().unwrap();
+
+  out[0] = a0;
+  out[1] = a1;
+
+  // With a0 > a1 the block interpolates six values between the end points.
+  let mut palette = [a0 as i32, a1 as i32, 0, 0, 0, 0, 0, 0];
+  for k in 1..7 {
+    palette[k + 1] = ((7 - k as i32) * a0 as i32 + k as i32 * a1 as i32) / 7;
+  }
+
+  let mut indices = 0u64;
+
+  for (i, px) in block.iter().enumerate() {
+    let index = if a0 == a1 {
+      0
+    } else {
+      (0..8).min_by_key(|&j| (palette[j] - px[3] as i32).abs()).unwrap() as u64
+    };
+
+    indices |= index << (3 * i);
+  }
+
+  out[2..8].copy_from_slice(&indices.to_le_bytes()[..6]);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_calc_output_size() {
+    assert_eq!(calc_output_size(256, 256, DxtVariant::BC1), 64 * 64 * 8);
+    assert_eq!(calc_output_size(5, 3, DxtVariant::BC3), 2 * 16);
+  }
+
+  #[test]
+  fn test_encode_solid_block() {
+    let rgba = [0xFFu8, 0, 0, 0xFF].repeat(16);
+    let mut out = [0u8; 8];
+    encode(&rgba, 4, 4, DxtVariant::BC1, &mut out);
+
+    assert_eq!(u16::from_le_bytes([out[0], out[1]]), 0xF800);
+    assert_eq!(&out[4..8], &[0, 0, 0, 0]);
+  }
+
+  #[test]
+  fn test_encode_bc1_transparency() {
+    let mut rgba = [0x80u8, 0x80, 0x80, 0xFF].repeat(16);
+    rgba[3] = 0;
+    let mut out = [0u8; 8];
+    encode(&rgba, 4, 4, DxtVariant::BC1, &mut out);
+
+    let c0 = u16::from_le_bytes([out[0], out[1]]);
+    let c1 = u16::from_le_bytes([out[2], out[3]]);
+    assert!(c0 <= c1);
+    assert_eq!(out[4] & 0b11, 3);
+  }
+
+  #[test]
+  fn test_encode_bc3_alpha() {
+    let mut rgba = vec![0u8; 4 * 16];
+    for (i, px) in rgba.chunks_mut(4).enumerate() {
+      px[3] = if i % 2 == 0 { 0xFF } else { 0 };
+    }
+    let mut out = [0u8; 16];
+    encode(&rgba, 4, 4, DxtVariant::BC3, &mut out);
+
+    assert_eq!(out[0], 0xFF);
+    assert_eq!(out[1], 0);
+    // First texel uses a0, the second one a1.
+    assert_eq!(out[2] & 0b111111, 0b001_000);
+  }
+}
@@ -102,7 +102,8 @@ GfxTransition 2 "tree debris small"
 ```
  */
 #[allow(non_snake_case)]
-#[derive(Builder)]
+#[derive(Builder, Default)]
+#[builder(default)]
 pub struct GfxLandscape {
     pub EditName: String,
     pub EditGroups: String,
@@ -122,7 +123,7 @@ pub struct GfxLandscape {
     /// Defining multiple ids can be done in multiple lines (for non-landscapes, like "particels")
     /// But again, not used for landscapes
     #[builder(field(public))]
-    pub GfxFrames: HashMap<u8, Vec<u8>>,
+    pub GfxFrames: HashMap<u8, Vec<u16>>,
     pub GfxStatic: bool,
     pub GfxLoopAnimation: bool,
     pub GfxShadingFactor: f32,
@@ -142,7 +143,7 @@ pub struct GfxLandscape {
     pub GfxTransition: HashMap<u8, String>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct GfxBobLibs {
     pub bmd: String,
     pub shadow: Option<String>,
@@ -52,13 +52,13 @@ fn read_3fd_cif(view: &mut Cursor<Box<[u8]>>) -> std::io::Result<Vec<IniCategory
     };
 
     let mut index_table = vec![0u8; header.SizeOfIndexTable as usize];
-    view.read_exact(index_table.as_mut_slice()).unwrap();
+    view.read_exact(index_table.as_mut_slice())?;
     decode_cif(index_table.as_mut_slice());
 
     view.seek(Current(1 + 4 + 4 + 4)).unwrap();
 
     let mut text_table = vec![0u8; header.SizeOfTextTable as usize];
-    view.read_exact(text_table.as_mut_slice()).unwrap();
+    view.read_exact(text_table.as_mut_slice())?;
     decode_cif(text_table.as_mut_slice());
 
     let mut sections: Vec<Section> = Vec::new();
@@ -77,11 +77,12 @@ fn read_3fd_cif(view: &mut Cursor<Box<[u8]>>) -> std::io::Result<Vec<IniCategory
         }
     }
 
-   Ok(reduce_sections(sections))
+   reduce_sections(sections)
 }
 
 fn parse(line: String) -> Option<Item> {
-    let regex = Regex::new("^([a-zA-Z0-9]+)((?:(?: \"[^\"]+\")|(?: [0-9]+))+)$").unwrap();
+    // Numbers may be negative, e.g. block areas, or fractional, e.g. `GfxShadingFactor`.
+    let regex = Regex::new("^([a-zA-Z0-9]+)((?:(?: \"[^\"]+\")|(?: -?[0-9.]+))+)$").unwrap();
 
     match regex.captures(line.as_str()) {
         None => None,
@@ -95,11 +96,20 @@ fn parse(line: String) -> Option<Item> {
 
 pub async fn read_cif(blob: FileAbstraction) -> std::io::Result<Vec<IniCategory> > {
     let mut view = blob.get_as_cursor().await;
+    read_cif_view(&mut view)
+}
+
+/// Reads a CIF file that is already in memory.
+pub fn read_cif_buf(buf: &[u8]) -> std::io::Result<Vec<IniCategory> > {
+    let mut view = Cursor::new(buf.to_vec().into_boxed_slice());
+    read_cif_view(&mut view)
+}
 
+fn read_cif_view(view: &mut Cursor<Box<[u8]>>) -> std::io::Result<Vec<IniCategory> > {
     let magic = view.read_u16::<LittleEndian>()?;
     match magic {
-        0x03FD => read_3fd_cif(&mut view),
-        _ => panic!("Unknown CIF file!")
+        0x03FD => read_3fd_cif(view),
+        _ => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Unknown CIF file!")),
     }
 }
 
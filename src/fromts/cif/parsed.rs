@@ -1,115 +1,138 @@
 use std::collections::HashMap;
-use std::fmt::Debug;
+use std::convert::TryFrom;
+use std::io::{Error, ErrorKind, Result};
 use std::str::FromStr;
-use casey::lower;
 use crate::fromts::cif::definitions::{GfxBobLibs, GfxLandscape, GfxLandscapeBuilder, IniCategory};
 use crate::fromts::cif::definitions::IniCategory::Unknown;
 use crate::fromts::cif::{Item, Section};
 
-pub fn reduce_sections(sections: Vec<Section>) -> Vec<IniCategory> {
+pub fn reduce_sections(sections: Vec<Section>) -> Result<Vec<IniCategory>> {
     sections.into_iter().map(parse_section).collect()
 }
 
-pub fn parse_section(section: Section) -> IniCategory {
-    match section.name.to_lowercase().as_str() {
-        "GfxLandscape" => IniCategory::GfxLandscape(parse_GfxLandscape(section.items)),
+/// Section and key names are matched case-insensitively.
+pub fn parse_section(section: Section) -> Result<IniCategory> {
+    Ok(match section.name.to_lowercase().as_str() {
+        "gfxlandscape" => IniCategory::GfxLandscape(parse_GfxLandscape(section.items)?),
         _ => Unknown(section),
-    }
+    })
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn parse_value<T: FromStr>(key: &str, value: &str) -> Result<T> {
+    value.parse().map_err(|_| invalid(format!("invalid value for {}: {}", key, value)))
 }
 
-fn parse_GfxLandscape(items: Vec<Item>) -> GfxLandscape {
+fn parse_GfxLandscape(items: Vec<Item>) -> Result<GfxLandscape> {
     let mut builder = GfxLandscapeBuilder::default();
 
     for item in items {
+        let value = &item.value;
         match item.key.to_lowercase().as_str() {
-            lower!("EditName") => builder.EditName(item.value),
-            lower!("EditGroups") => builder.EditGroups(item.value),
-            lower!("LogicType") => builder.LogicType(item.value.parse().unwrap()),
-            lower!("LogicMaximumValency") => builder.LogicMaximumValency(item.value.parse().unwrap()),
-            lower!("LogicIsWorkable") => builder.LogicIsWorkable(item.value.parse().unwrap()),
-            lower!("logicispileableonmap") => builder.logicispileableonmap(item.value.parse().unwrap()),
-            lower!("LogicWalkBlockArea") => builder.LogicWalkBlockArea(parse_coords(&item.value)),
-            lower!("LogicBuildBlockArea") => builder.LogicBuildBlockArea(parse_coords(&item.value)),
-            lower!("LogicWorkArea") => builder.LogicWorkArea(parse_coords(&item.value)),
-            lower!("GfxBobLibs") => builder.GfxBobLibs(parse_GfxBobLibs(&item.value)),
-            lower!("GfxPalette") => builder.GfxPalette(Some(parse_GfxPalette(&item.value))),
-            lower!("GfxFrames") => parse_GfxFrames(&mut builder, &item.value),
-            lower!("GfxStatic") => builder.GfxStatic(item.value.parse().unwrap()),
-            lower!("GfxLoopAnimation") => builder.GfxLoopAnimation(item.value.parse().unwrap()),
-            lower!("GfxShadingFactor") => builder.GfxShadingFactor(item.value.parse().unwrap()),
-            lower!("GfxUserFXMatrix") => builder.GfxUserFXMatrix(item.value.parse().unwrap()),
-            lower!("GfxDynamicBackground") => builder.GfxDynamicBackground(item.value.parse().unwrap()),
-            lower!("gfxdrawvoidever") => builder.gfxdrawvoidever(item.value.parse().unwrap()),
-            lower!("GfxTransition") => parse_GfxTransition(&mut builder, &item.value),
+            "editname" => { builder.EditName(unquote(value)); }
+            "editgroups" => { builder.EditGroups(unquote(value)); }
+            "logictype" => { builder.LogicType(parse_value(&item.key, value)?); }
+            "logicmaximumvalency" => { builder.LogicMaximumValency(parse_value(&item.key, value)?); }
+            "logicisworkable" => { builder.LogicIsWorkable(parse_flag(value)); }
+            "logicispileableonmap" => { builder.logicispileableonmap(parse_flag(value)); }
+            "logicwalkblockarea" => { builder.LogicWalkBlockArea(parse_coords(value)?); }
+            "logicbuildblockarea" => { builder.LogicBuildBlockArea(parse_coords(value)?); }
+            "logicworkarea" => { builder.LogicWorkArea(parse_coords(value)?); }
+            "gfxboblibs" => { builder.GfxBobLibs(parse_GfxBobLibs(value)?); }
+            "gfxpalette" => { builder.GfxPalette(Some(parse_GfxPalette(value))); }
+            "gfxframes" => parse_GfxFrames(&mut builder, value)?,
+            "gfxstatic" => { builder.GfxStatic(parse_flag(value)); }
+            "gfxloopanimation" => { builder.GfxLoopAnimation(parse_flag(value)); }
+            "gfxshadingfactor" => { builder.GfxShadingFactor(parse_value(&item.key, value)?); }
+            "gfxuserfxmatrix" => { builder.GfxUserFXMatrix(parse_value(&item.key, value)?); }
+            "gfxdynamicbackground" => { builder.GfxDynamicBackground(parse_flag(value)); }
+            "gfxdrawvoidever" => { builder.gfxdrawvoidever(parse_flag(value)); }
+            "gfxtransition" => parse_GfxTransition(&mut builder, value)?,
+            _ => {}
         };
     }
 
-    return builder.build().unwrap();
+    // Every field has a default, so building cannot fail.
+    Ok(builder.build().unwrap())
+}
+
+/// Strips the quotes around a string value.
+fn unquote(value: &str) -> String {
+    value.trim_matches('"').to_owned()
+}
+
+/// Flags are written as 0 or 1.
+fn parse_flag(value: &str) -> bool {
+    value != "0"
 }
 
-fn parse_GfxBobLibs(value: &String) -> GfxBobLibs {
-    let mut parts: Vec<&str> = value.split_whitespace().collect();
+fn parse_GfxBobLibs(value: &String) -> Result<GfxBobLibs> {
+    let mut parts: Vec<String> = value.split_whitespace().map(unquote).collect();
     match parts.len() {
-        1 => GfxBobLibs {
-            bmd: parts.remove(0).to_owned(),
+        1 => Ok(GfxBobLibs {
+            bmd: parts.remove(0),
             shadow: None,
-        },
-        2 => GfxBobLibs {
-            bmd: parts.remove(0).to_owned(),
-            shadow: Some(parts.remove(0).to_owned()),
-        },
-        _ => panic!("Too many or too little fields for GfxBobLibs")
+        }),
+        2 => Ok(GfxBobLibs {
+            bmd: parts.remove(0),
+            shadow: Some(parts.remove(0)),
+        }),
+        _ => Err(invalid(format!("expected one or two files for GfxBobLibs: {}", value))),
     }
 }
 
 fn parse_GfxPalette(value: &String) -> Vec<String> {
-    value.split_whitespace().map(str::to_owned).collect()
+    value.split_whitespace().map(unquote).collect()
 }
 
-fn parse_GfxTransition<'a>(builder: &'a mut GfxLandscapeBuilder, value: &String) -> &'a GfxLandscapeBuilder {
+fn parse_GfxTransition(builder: &mut GfxLandscapeBuilder, value: &String) -> Result<()> {
     if builder.GfxTransition == None {
         builder.GfxTransition(HashMap::new());
     }
     let existing = builder.GfxTransition.as_mut().unwrap();
-    let mut split: Vec<String> = value.split_whitespace().map(str::to_owned).collect();
-    let k: u8 = split.get(0).unwrap().parse().unwrap();
-    let v: String = split.remove(1).to_owned();
-    existing.entry(k).or_insert(v);
-    &builder
+    // The name may contain spaces.
+    let split = value.find(' ').ok_or_else(|| invalid(format!("expected a level and a name for GfxTransition: {}", value)))?;
+    let (k, v) = value.split_at(split);
+    existing.entry(parse_value("GfxTransition", k)?).or_insert(unquote(v.trim()));
+    Ok(())
 }
 
-fn parse_GfxFrames<'a>(builder: &'a mut GfxLandscapeBuilder, value: &String) -> &'a GfxLandscapeBuilder {
+fn parse_GfxFrames(builder: &mut GfxLandscapeBuilder, value: &String) -> Result<()> {
     if builder.GfxFrames == None {
         builder.GfxFrames(HashMap::new());
     }
     let existing = builder.GfxFrames.as_mut().unwrap();
-    for (k, v) in parse_GfxFrames_parts(value) {
+    for (k, v) in parse_GfxFrames_parts(value)? {
         existing.entry(k).or_insert(v);
     }
-    &builder
+    Ok(())
 }
 
-fn parse_GfxFrames_parts(s: &String) -> HashMap<u8, Vec<u8>> {
-    let parts: Vec<u8> = split_string_to_ints(s);
+fn parse_GfxFrames_parts(s: &String) -> Result<HashMap<u8, Vec<u16>>> {
+    let parts: Vec<u16> = split_string_to_ints(s)?;
     let mut i = parts.into_iter();
-    let id = i.next().unwrap();
+    let id = i.next().ok_or_else(|| invalid("expected a level for GfxFrames".to_owned()))?;
+    let id = u8::try_from(id).map_err(|_| invalid(format!("GfxFrames level {} is out of range", id)))?;
 
     let mut r = HashMap::new();
     r.insert(id, i.collect());
-    return r;
+    Ok(r)
 }
 
-fn parse_coords(s: &String) -> ((i8, i8), (i8, i8)) {
-    let r: Vec<i8> = split_string_to_ints(s);
+fn parse_coords(s: &String) -> Result<((i8, i8), (i8, i8))> {
+    let r: Vec<i8> = split_string_to_ints(s)?;
     if r.len() != 4 {
-        panic!("Expected exactly 4!")
+        return Err(invalid(format!("expected exactly 4 coordinates: {}", s)));
     }
-    (
-        (*r.get(0).unwrap(), *r.get(1).unwrap()),
-        (*r.get(2).unwrap(), *r.get(3).unwrap())
-    )
+    Ok((
+        (r[0], r[1]),
+        (r[2], r[3])
+    ))
 }
 
-fn split_string_to_ints<T: FromStr>(s: &String) -> Vec<T> where <T as FromStr>::Err: Debug {
-    s.split_whitespace().map(|x| x.parse::<T>().unwrap()).collect()
+fn split_string_to_ints<T: FromStr>(s: &String) -> Result<Vec<T>> {
+    s.split_whitespace().map(|x| x.parse::<T>().map_err(|_| invalid(format!("invalid number {} in {}", x, s)))).collect()
 }
\ No newline at end of file
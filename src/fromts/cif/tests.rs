@@ -1,4 +1,60 @@
+use crate::fromts::cif::definitions::IniCategory;
+use crate::fromts::cif::parsed::reduce_sections;
+use crate::fromts::cif::{parse, Section};
+
 #[test]
 fn test_add() {
     assert_eq!(3, 3);
 }
+
+fn landscape_section(lines: &[&str]) -> Section {
+    Section {
+        name: "GfxLandscape".to_owned(),
+        items: lines.iter().map(|l| parse(l.to_string()).unwrap()).collect(),
+    }
+}
+
+#[test]
+fn test_parse_landscape() {
+    let section = landscape_section(&[
+        "EditName \"tree 01\"",
+        "LogicType 3",
+        "LogicIsWorkable 1",
+        "LogicWalkBlockArea -1 0 1 1",
+        "GfxBobLibs \"data\\ls_trees.bmd\" \"data\\ls_trees_s.bmd\"",
+        "GfxFrames 1 300 301 302",
+        "GfxStatic 0",
+        "GfxLoopAnimation 1",
+        "GfxShadingFactor 0.500000",
+        "GfxTransition 3 \"tree trunk 01\"",
+    ]);
+
+    let landscape = match reduce_sections(vec![section]).unwrap().remove(0) {
+        IniCategory::GfxLandscape(l) => l,
+        _ => panic!("Expected a GfxLandscape"),
+    };
+
+    assert_eq!(landscape.EditName, "tree 01");
+    assert_eq!(landscape.LogicType, 3);
+    assert!(landscape.LogicIsWorkable);
+    assert_eq!(landscape.LogicWalkBlockArea, ((-1, 0), (1, 1)));
+    assert_eq!(landscape.GfxBobLibs.bmd, "data\\ls_trees.bmd");
+    assert_eq!(landscape.GfxBobLibs.shadow.as_deref(), Some("data\\ls_trees_s.bmd"));
+    assert_eq!(landscape.GfxFrames[&1], vec![300, 301, 302]);
+    assert!(!landscape.GfxStatic && landscape.GfxLoopAnimation);
+    assert_eq!(landscape.GfxShadingFactor, 0.5);
+    assert_eq!(landscape.GfxTransition[&3], "tree trunk 01");
+}
+
+#[test]
+fn test_parse_landscape_rejects_invalid_values() {
+    for line in &["LogicType 1.5", "GfxBobLibs \"a\" \"b\" \"c\"", "GfxTransition 3", "GfxFrames 256 1 2", "LogicWalkBlockArea 0 1"] {
+        assert!(reduce_sections(vec![landscape_section(&[line])]).is_err(), "{}", line);
+    }
+}
+
+#[test]
+fn test_read_cif_buf_rejects_unknown_magic() {
+    assert!(crate::fromts::cif::read_cif_buf(&[0, 0, 0, 0]).is_err());
+    assert!(crate::fromts::cif::read_cif_buf(&[]).is_err());
+}
@@ -1,7 +1,6 @@
-use std::collections::HashSet;
-use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::io::{Cursor, Error, ErrorKind, Read, Seek, SeekFrom};
 use byteorder::{LittleEndian, ReadBytesExt};
-use crate::fromts::util::{is_eof_vec, read_fixed_string_vec, read_short_string_vec};
+use crate::fromts::util::is_eof_slice;
 
 pub struct CommonDecoded {
     pub unk1: u8,
@@ -11,7 +10,6 @@ pub struct CommonDecoded {
     pub unk_len_dup: u32, // = header.section_length - 5
     /** @type {Uint8Array=} */
     pub data: Box<[u8]>,
-    pub range: HashSet<u8>, // type yolo?
 }
 
 pub struct CommonDecoded2 {
@@ -22,7 +20,6 @@ pub struct CommonDecoded2 {
     pub unk_len_dup: u32, // = header.section_length - 5
     /** @type {Uint16Array=} */
     pub data: Box<[u16]>,
-    pub range: HashSet<u8>,
 }
 
 pub struct RawDecoded {
@@ -33,36 +30,45 @@ pub struct RawDecoded {
     pub   unk_len_dup: u32, // = header.section_length - 5
     /** @type {Uint8Array=} */
     pub  data: Box<[u8]>,
-    pub   range: HashSet<u8>,
 }
 
-pub fn common_decoding(view: &mut Cursor<Vec<u8>>) -> std::io::Result<CommonDecoded> {
+fn read_magic(view: &mut Cursor<&[u8]>) -> std::io::Result<String> {
+    let mut buffer = [0u8; 8];
+    view.read_exact(&mut buffer)?;
+
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
+
+fn overflow() -> Error {
+    Error::new(ErrorKind::InvalidData, "run exceeds the layer size")
+}
+
+pub fn common_decoding(view: &mut Cursor<&[u8]>) -> std::io::Result<CommonDecoded> {
     let mut content = CommonDecoded {
         unk1: view.read_u8()?,
         unk_len: view.read_u32::<LittleEndian>()?, // = header.section_length - 5
-        unk_magic: read_fixed_string_vec(view, 8),
+        unk_magic: read_magic(view)?,
         length: view.read_u32::<LittleEndian>()?, // width * height
         unk_len_dup: view.read_u32::<LittleEndian>()?, // = header.section_length - 5
         /** @type {Uint8Array=} */
         data: Box::new([]),
-        range: HashSet::new(),
     };
 
     let mut count = 0;
     let mut data = vec![0u8; content.length as usize].into_boxed_slice();
 
-    while !is_eof_vec(view) {
+    while !is_eof_slice(view) {
         let head = view.read_u8()?;
 
         if head > 0x80 {
             let value = view.read_u8()?;
-            for i in 0..head - 0x80 {
-                data[count] = value;
+            for _ in 0..head - 0x80 {
+                *data.get_mut(count).ok_or_else(overflow)? = value;
                 count += 1;
             }
         } else {
-            for i in 0..head {
-                data[count] = view.read_u8()?;
+            for _ in 0..head {
+                *data.get_mut(count).ok_or_else(overflow)? = view.read_u8()?;
                 count += 1;
             }
         }
@@ -73,33 +79,32 @@ pub fn common_decoding(view: &mut Cursor<Vec<u8>>) -> std::io::Result<CommonDeco
     return Ok(content);
 }
 
-pub fn common_decoding2(view: &mut Cursor<Vec<u8>>) -> std::io::Result<CommonDecoded2> {
+pub fn common_decoding2(view: &mut Cursor<&[u8]>) -> std::io::Result<CommonDecoded2> {
     let mut content = CommonDecoded2 {
         unk1: view.read_u8()?,
         unk_len: view.read_u32::<LittleEndian>()?, // = header.section_length - 5
-        unk_magic: read_fixed_string_vec(view, 8),
+        unk_magic: read_magic(view)?,
         length: view.read_u32::<LittleEndian>()? / 2, // width * height
         unk_len_dup: view.read_u32::<LittleEndian>()?, // = header.section_length - 5
         /** @type {Uint16Array=} */
         data: Box::new([]),
-        range: HashSet::new(),
     };
 
     let mut count = 0;
     let mut data = vec![0u16; content.length as usize].into_boxed_slice();
 
-    while is_eof_vec(&view) {
-        let head = view.read_u8().unwrap();
+    while !is_eof_slice(view) {
+        let head = view.read_u8()?;
 
         if head > 0x80 {
             let value = view.read_u16::<LittleEndian>()?;
-            for i in 0..head - 0x80 {
-                data[count] = value;
+            for _ in 0..head - 0x80 {
+                *data.get_mut(count).ok_or_else(overflow)? = value;
                 count += 1;
             }
         } else {
-            for i in 0..head {
-                data[count] = view.read_u16::<LittleEndian>()?;
+            for _ in 0..head {
+                *data.get_mut(count).ok_or_else(overflow)? = view.read_u16::<LittleEndian>()?;
                 count += 1;
             }
         }
@@ -110,39 +115,39 @@ pub fn common_decoding2(view: &mut Cursor<Vec<u8>>) -> std::io::Result<CommonDec
     return Ok(content);
 }
 
-pub fn dictionary(view: &mut Cursor<Vec<u8>>) -> std::io::Result<Box<[String]>> {
+pub fn dictionary(view: &mut Cursor<&[u8]>) -> std::io::Result<Box<[String]>> {
     let len = view.read_u32::<LittleEndian>()?;
 
     let mut dictionary = Vec::new();
-    for i in 0..len {
-        let str = read_short_string_vec(view);
-        view.seek(SeekFrom::Current(1)).unwrap();
-        dictionary.push(str);
+    for _ in 0..len {
+        let mut str = vec![0u8; view.read_u8()? as usize];
+        view.read_exact(str.as_mut_slice())?;
+        view.seek(SeekFrom::Current(1))?;
+        dictionary.push(String::from_utf8_lossy(&str).into_owned());
     }
 
     return Ok(dictionary.into_boxed_slice());
 }
 
-pub fn raw(view: &mut Cursor<Vec<u8>>) -> std::io::Result<RawDecoded> {
+pub fn raw(view: &mut Cursor<&[u8]>) -> std::io::Result<RawDecoded> {
     let mut content = RawDecoded {
         unk1: view.read_u8()?,
         data_len: view.read_u32::<LittleEndian>()?, // = header.section_length - 5
-        unk_magic: read_fixed_string_vec(view, 8),
+        unk_magic: read_magic(view)?,
         length: view.read_u32::<LittleEndian>()? / 2, // width * height
         unk_len_dup: view.read_u32::<LittleEndian>()?, // = header.section_length - 5
         /** @type {Uint8Array=} */
         data: Box::new([]),
-        range: HashSet::new(),
     };
 
     let mut data = vec![0u8; content.length as usize].into_boxed_slice();
-    view.read_exact(data.as_mut()).unwrap();
+    view.read_exact(data.as_mut())?;
     content.data = data;
 
     return Ok(content);
 }
 
-pub fn hoixzisl_parse(view: &mut Cursor<Vec<u8>>) -> std::io::Result<HoixzislData> {
+pub fn hoixzisl_parse(view: &mut Cursor<&[u8]>) -> std::io::Result<HoixzislData> {
     return Ok(HoixzislData {
         width: view.read_u32::<LittleEndian>()?,
         height: view.read_u32::<LittleEndian>()?,
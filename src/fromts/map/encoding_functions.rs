@@ -0,0 +1,127 @@
+use byteorder::{LittleEndian, WriteBytesExt};
+
+/// Longest run or literal block a single head byte can describe.
+const MAX_BLOCK: usize = 0x7F;
+/// Runs shorter than this are cheaper as literals.
+const MIN_RUN: usize = 3;
+
+/// Inverse of the RLE loop in `common_decoding`: a head above 0x80 repeats the
+/// next value `head - 0x80` times, any other head is followed by `head`
+/// literal values.
+fn rle_encode<T: Copy + PartialEq>(data: &[T], out: &mut Vec<u8>, write: impl Fn(&mut Vec<u8>, T)) {
+    let mut literal_start = 0;
+    let mut i = 0;
+
+    let flush = |out: &mut Vec<u8>, literals: &[T]| {
+        for block in literals.chunks(MAX_BLOCK) {
+            out.push(block.len() as u8);
+            block.iter().for_each(|&v| write(out, v));
+        }
+    };
+
+    while i < data.len() {
+        let mut run = 1;
+        while i + run < data.len() && run < MAX_BLOCK && data[i + run] == data[i] {
+            run += 1;
+        }
+
+        if run >= MIN_RUN {
+            flush(out, &data[literal_start..i]);
+            out.push(0x80 + run as u8);
+            write(out, data[i]);
+            i += run;
+            literal_start = i;
+        } else {
+            i += run;
+        }
+    }
+
+    flush(out, &data[literal_start..]);
+}
+
+/// Section header shared by `common_decoding`, `common_decoding2` and `raw`.
+fn layer_header(out: &mut Vec<u8>, unk1: u8, magic: &[u8], length: u32, payload_len: usize) {
+    let unk_len = (magic.len() + 4 + 4 + payload_len) as u32;
+
+    out.push(unk1);
+    out.write_u32::<LittleEndian>(unk_len).unwrap();
+    out.extend_from_slice(magic);
+    out.write_u32::<LittleEndian>(length).unwrap();
+    out.write_u32::<LittleEndian>(unk_len).unwrap();
+}
+
+pub fn common_encoding(unk1: u8, magic: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    rle_encode(data, &mut payload, |out, v| out.push(v));
+
+    let mut out = Vec::with_capacity(21 + payload.len());
+    layer_header(&mut out, unk1, magic, data.len() as u32, payload.len());
+    out.extend_from_slice(&payload);
+
+    return out;
+}
+
+pub fn common_encoding2(unk1: u8, magic: &[u8], data: &[u16]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    rle_encode(data, &mut payload, |out, v| out.write_u16::<LittleEndian>(v).unwrap());
+
+    let mut out = Vec::with_capacity(21 + payload.len());
+    layer_header(&mut out, unk1, magic, 2 * data.len() as u32, payload.len());
+    out.extend_from_slice(&payload);
+
+    return out;
+}
+
+pub fn raw_encoding(unk1: u8, magic: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(21 + data.len());
+    layer_header(&mut out, unk1, magic, 2 * data.len() as u32, data.len());
+    out.extend_from_slice(data);
+
+    return out;
+}
+
+/// Names longer than 255 bytes are cut off, the length is a single byte.
+pub fn dictionary_encoding(names: &[String]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.write_u32::<LittleEndian>(names.len() as u32).unwrap();
+
+    for name in names {
+        let bytes = &name.as_bytes()[..name.len().min(0xFF)];
+        out.push(bytes.len() as u8);
+        out.extend_from_slice(bytes);
+        out.push(0);
+    }
+
+    return out;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::fromts::map::decoding_functions::{common_decoding, common_decoding2, dictionary};
+
+    #[test]
+    fn test_common_encoding_roundtrip() {
+        let mut data: Vec<u8> = (0..300).map(|i| (i * 7 % 13) as u8).collect();
+        data.extend(vec![4u8; 400]);
+        data.extend([1, 1, 2, 2, 2, 3].iter());
+
+        let encoded = common_encoding(1, b"hoixdata", &data);
+        let decoded = common_decoding(&mut Cursor::new(&encoded[..])).unwrap();
+        assert_eq!(&decoded.data[..], &data[..]);
+        assert_eq!(decoded.unk_magic, "hoixdata");
+
+        let data: Vec<u16> = (0..500).map(|i| if i < 200 { 0x1234 } else { i }).collect();
+        let decoded = common_decoding2(&mut Cursor::new(&common_encoding2(1, b"hoixdata", &data)[..])).unwrap();
+        assert_eq!(&decoded.data[..], &data[..]);
+    }
+
+    #[test]
+    fn test_dictionary_encoding_roundtrip() {
+        let names = vec!["grass".to_string(), "".to_string(), "water deep".to_string()];
+        let decoded = dictionary(&mut Cursor::new(&dictionary_encoding(&names)[..])).unwrap();
+
+        assert_eq!(&decoded[..], &names[..]);
+    }
+}
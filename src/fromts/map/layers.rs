@@ -0,0 +1,154 @@
+use crate::fromts::map::decodings::MapSectionName;
+
+/// The generic layers, in the order `CulturesMapData::layers` lists them.
+/// Their meaning is not confirmed, so they are named after their tag. The
+/// wrappers below give the guessed meaning of some of them.
+pub const NAMED_LAYERS: [(MapSectionName, &str); 14] = [
+    (MapSectionName::hoixapml, "apml"),
+    (MapSectionName::hoixbpml, "bpml"),
+    (MapSectionName::hoixplml, "plml"),
+    (MapSectionName::hoixocml, "ocml"),
+    (MapSectionName::hoixwtml, "wtml"),
+    (MapSectionName::hoixsmml, "smml"),
+    (MapSectionName::hoixrpml, "rpml"),
+    (MapSectionName::hoixbwml, "bwml"),
+    (MapSectionName::hoixbbml, "bbml"),
+    (MapSectionName::hoixorml, "orml"),
+    (MapSectionName::hoixbsml, "bsml"),
+    (MapSectionName::hoixoaml, "oaml"),
+    (MapSectionName::hoix1mme, "1mme"),
+    (MapSectionName::hoiximme, "imme"),
+];
+
+pub enum LayerData {
+    U8(Box<[u8]>),
+    U16(Box<[u16]>),
+}
+
+pub struct MapLayer {
+    pub section: MapSectionName,
+    pub name: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub data: LayerData,
+}
+
+impl MapLayer {
+    /// Layers are stored per cell, some at half the resolution. Anything else
+    /// is kept as a single row.
+    pub fn new(section: MapSectionName, name: &'static str, data: LayerData, map_width: u32, map_height: u32) -> MapLayer {
+        let len = match &data {
+            LayerData::U8(d) => d.len(),
+            LayerData::U16(d) => d.len(),
+        } as u32;
+
+        let (width, height) = if len == map_width * map_height {
+            (map_width, map_height)
+        } else if len == (map_width / 2) * (map_height / 2) {
+            (map_width / 2, map_height / 2)
+        } else {
+            (len, 1)
+        };
+
+        MapLayer { section, name, width, height, data }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Option<u16> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        let i = (y * self.width + x) as usize;
+        match &self.data {
+            LayerData::U8(d) => Some(d[i] as u16),
+            LayerData::U16(d) => Some(d[i]),
+        }
+    }
+
+    /// Value of the map cell `(x, y)`, scaled down for half resolution layers.
+    pub fn at_cell(&self, x: u32, y: u32, map_width: u32) -> Option<u16> {
+        if self.width == map_width {
+            self.get(x, y)
+        } else if self.width == map_width / 2 {
+            self.get(x / 2, y / 2)
+        } else {
+            None
+        }
+    }
+}
+
+// The wrappers below interpret single layers. Their meanings are guesses from
+// the tag names and from looking at maps, not confirmed by the game.
+
+/// Cells a settler cannot enter, guessed to be non-zero in `bwml` ("block
+/// walk").
+pub struct Walkability<'a> {
+    pub layer: &'a MapLayer,
+    pub map_width: u32,
+}
+
+impl<'a> Walkability<'a> {
+    pub fn is_walkable(&self, x: u32, y: u32) -> bool {
+        self.layer.at_cell(x, y, self.map_width) == Some(0)
+    }
+}
+
+/// Owning player of every cell, guessed from `plml` ("player"), 0 for none.
+pub struct Ownership<'a> {
+    pub layer: &'a MapLayer,
+    pub map_width: u32,
+}
+
+impl<'a> Ownership<'a> {
+    pub fn owner(&self, x: u32, y: u32) -> Option<u16> {
+        self.layer.at_cell(x, y, self.map_width).filter(|&p| p != 0)
+    }
+}
+
+/// Water cells, guessed to be non-zero in `wtml` ("water").
+pub struct Water<'a> {
+    pub layer: &'a MapLayer,
+    pub map_width: u32,
+}
+
+impl<'a> Water<'a> {
+    pub fn is_water(&self, x: u32, y: u32) -> bool {
+        self.layer.at_cell(x, y, self.map_width).is_some_and(|v| v != 0)
+    }
+}
+
+/// Resource deposit of every cell, guessed from `rpml` ("resource
+/// placement"), 0 for none.
+pub struct Resources<'a> {
+    pub layer: &'a MapLayer,
+    pub map_width: u32,
+}
+
+impl<'a> Resources<'a> {
+    pub fn resource(&self, x: u32, y: u32) -> Option<u16> {
+        self.layer.at_cell(x, y, self.map_width).filter(|&r| r != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layer_dimensions() {
+        let full = MapLayer::new(MapSectionName::hoixbwml, "bwml", LayerData::U8(vec![0, 1, 0, 0, 0, 0].into()), 3, 2);
+        assert_eq!((full.width, full.height), (3, 2));
+        assert!(!Walkability { layer: &full, map_width: 3 }.is_walkable(1, 0));
+        assert!(Walkability { layer: &full, map_width: 3 }.is_walkable(2, 1));
+
+        let half = MapLayer::new(MapSectionName::hoixplml, "plml", LayerData::U16(vec![0, 2].into()), 4, 2);
+        assert_eq!((half.width, half.height), (2, 1));
+        assert_eq!(Ownership { layer: &half, map_width: 4 }.owner(3, 1), Some(2));
+        assert_eq!(Ownership { layer: &half, map_width: 4 }.owner(1, 0), None);
+
+        let odd = MapLayer::new(MapSectionName::hoixocml, "ocml", LayerData::U8(vec![1; 5].into()), 4, 2);
+        assert_eq!((odd.width, odd.height), (5, 1));
+        assert_eq!(odd.get(4, 0), Some(1));
+        assert_eq!(odd.at_cell(0, 0, 4), None);
+    }
+}
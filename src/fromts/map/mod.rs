@@ -1,37 +1,110 @@
 mod decoding_functions;
 mod decodings;
+mod encoding_functions;
+pub mod layers;
+pub mod stats;
 
 use std::collections::HashMap;
-use std::io::{Cursor, Read, Seek, SeekFrom};
-use byteorder::{LittleEndian, ReadBytesExt};
-use web_sys::Blob;
+use std::io::{Cursor, Error, ErrorKind, Read};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use crate::fromts::map::decodings::{hoix1tme, hoix2tme, hoix3tme, hoix4tme, hoixalme, hoixapme, hoixbpme, hoixdlae, hoixdpae, hoixdtae, hoixehml, hoixrbme, hoixtlml, hoixvlml, hoixzisl, MapSectionName};
-use crate::fromts::util::{is_eof_box, read_bytes, read_file, read_fixed_string_box};
+use crate::fromts::map::decoding_functions::{common_decoding, common_decoding2, dictionary};
+use crate::fromts::map::layers::{LayerData, MapLayer, Ownership, Resources, Walkability, Water, NAMED_LAYERS};
+use crate::fromts::map::encoding_functions::{common_encoding, common_encoding2, dictionary_encoding, raw_encoding};
+use crate::fromts::middlelayer::file_interface::FileAbstraction;
 
 pub struct CulturesMapData {
     width: u32,
     height: u32,
     elevation: Box<[u8]>,
-    lighting: Box<[u8]>,
-    tiles_index: Box<[String]>,
-    tiles_a: Box<[u16]>,
-    tiles_b: Box<[u16]>,
-    transitions_index: Box<[String]>,
-    trans_a1: Box<[u8]>,
-    trans_b1: Box<[u8]>,
-    trans_a2: Box<[u8]>,
-    trans_b2: Box<[u8]>,
-
-    landscape_index: Box<[String]>,
-    landscape_levels: Box<[u8]>,
-    landscape_types: Box<[u8]>,
-    landscape_job_types: Box<[u8]>,
-}
-
-fn read_header(view: &mut Cursor<Box<[u8]>>) -> std::io::Result<Header> {
+    lighting: Option<Box<[u8]>>,
+    tiles_index: Option<Box<[String]>>,
+    tiles_a: Option<Box<[u16]>>,
+    tiles_b: Option<Box<[u16]>>,
+    transitions_index: Option<Box<[String]>>,
+    trans_a1: Option<Box<[u8]>>,
+    trans_b1: Option<Box<[u8]>>,
+    trans_a2: Option<Box<[u8]>>,
+    trans_b2: Option<Box<[u8]>>,
+
+    landscape_index: Option<Box<[String]>>,
+    landscape_levels: Option<Box<[u8]>>,
+    landscape_types: Option<Box<[u8]>>,
+    landscape_job_types: Option<Box<[u8]>>,
+
+    /// The remaining decoded sections, see `layers::NAMED_LAYERS`.
+    layers: Vec<MapLayer>,
+
+    /// Every section in file order, kept to write the map back.
+    sections: Vec<MapSection>,
+}
+
+impl CulturesMapData {
+    pub fn width(&self) -> u32 { self.width }
+    pub fn height(&self) -> u32 { self.height }
+
+    /// Height of every vertex, `width` × `height` values.
+    pub fn elevation(&self) -> &[u8] { &self.elevation }
+    pub fn lighting(&self) -> Option<&[u8]> { self.lighting.as_deref() }
+
+    /// Pattern names, indexed by `tiles_a` and `tiles_b`.
+    pub fn tiles_index(&self) -> Option<&[String]> { self.tiles_index.as_deref() }
+    /// Pattern of the upward pointing triangle of every cell.
+    pub fn tiles_a(&self) -> Option<&[u16]> { self.tiles_a.as_deref() }
+    /// Pattern of the downward pointing triangle of every cell.
+    pub fn tiles_b(&self) -> Option<&[u16]> { self.tiles_b.as_deref() }
+
+    /// Transition names, indexed by the four transition layers.
+    pub fn transitions_index(&self) -> Option<&[String]> { self.transitions_index.as_deref() }
+    pub fn trans_a1(&self) -> Option<&[u8]> { self.trans_a1.as_deref() }
+    pub fn trans_b1(&self) -> Option<&[u8]> { self.trans_b1.as_deref() }
+    pub fn trans_a2(&self) -> Option<&[u8]> { self.trans_a2.as_deref() }
+    pub fn trans_b2(&self) -> Option<&[u8]> { self.trans_b2.as_deref() }
+
+    /// Landscape names, indexed by `landscape_types`.
+    pub fn landscape_index(&self) -> Option<&[String]> { self.landscape_index.as_deref() }
+    pub fn landscape_levels(&self) -> Option<&[u8]> { self.landscape_levels.as_deref() }
+    pub fn landscape_types(&self) -> Option<&[u8]> { self.landscape_types.as_deref() }
+    pub fn landscape_job_types(&self) -> Option<&[u8]> { self.landscape_job_types.as_deref() }
+
+    /// Every section of the file in order, including the ones the loader
+    /// does not understand.
+    pub fn sections(&self) -> &[MapSection] { &self.sections }
+
+    pub fn layers(&self) -> &[MapLayer] { &self.layers }
+
+    pub fn layer(&self, name: &str) -> Option<&MapLayer> {
+        self.layers.iter().find(|l| l.name == name)
+    }
+
+    pub fn layer_by_section(&self, section: MapSectionName) -> Option<&MapLayer> {
+        self.layers.iter().find(|l| l.section == section)
+    }
+
+    pub fn walkability(&self) -> Option<Walkability> {
+        self.layer_by_section(MapSectionName::hoixbwml).map(|layer| Walkability { layer, map_width: self.width })
+    }
+
+    pub fn ownership(&self) -> Option<Ownership> {
+        self.layer_by_section(MapSectionName::hoixplml).map(|layer| Ownership { layer, map_width: self.width })
+    }
+
+    pub fn water(&self) -> Option<Water> {
+        self.layer_by_section(MapSectionName::hoixwtml).map(|layer| Water { layer, map_width: self.width })
+    }
+
+    pub fn resources(&self) -> Option<Resources> {
+        self.layer_by_section(MapSectionName::hoixrpml).map(|layer| Resources { layer, map_width: self.width })
+    }
+}
+
+fn read_header(view: &mut Cursor<&[u8]>) -> std::io::Result<Header> {
+    // map section name, "hoix"
+    let mut tag = [0u8; 8];
+    view.read_exact(&mut tag)?;
+
     Ok(Header {
-        // map section name, "hoix"
-        tag: read_fixed_string_box(view, 8),
+        tag,
         unk1: view.read_u32::<LittleEndian>()?,
         section_length: view.read_u32::<LittleEndian>()?,
         unk2: view.read_u32::<LittleEndian>()?,
@@ -42,7 +115,7 @@ fn read_header(view: &mut Cursor<Box<[u8]>>) -> std::io::Result<Header> {
 }
 
 struct Header {
-    tag: String,
+    tag: [u8; 8],
     unk1: u32,
     section_length: u32,
     unk2: u32,
@@ -51,64 +124,375 @@ struct Header {
     unk5: u32,
 }
 
+impl Header {
+    fn name(&self) -> Option<MapSectionName> {
+        std::str::from_utf8(&self.tag).ok().and_then(MapSectionName::from_str)
+    }
+}
+
+fn write_header(out: &mut Vec<u8>, header: &Header, section_length: u32) {
+    out.extend_from_slice(&header.tag);
+    for v in [header.unk1, section_length, header.unk2, header.unk3, header.unk4, header.unk5].iter() {
+        out.write_u32::<LittleEndian>(*v).unwrap();
+    }
+}
+
+/// Sections whose content is held by a field of `CulturesMapData`.
+const DECODED_SECTIONS: [MapSectionName; 29] = [
+    MapSectionName::hoixzisl, MapSectionName::hoixehml, MapSectionName::hoixrbme,
+    MapSectionName::hoixdpae, MapSectionName::hoixapme, MapSectionName::hoixbpme,
+    MapSectionName::hoixdtae, MapSectionName::hoix1tme, MapSectionName::hoix2tme, MapSectionName::hoix3tme, MapSectionName::hoix4tme,
+    MapSectionName::hoixdlae, MapSectionName::hoixtlml, MapSectionName::hoixalme, MapSectionName::hoixvlml,
+    MapSectionName::hoixapml, MapSectionName::hoixbpml, MapSectionName::hoixplml, MapSectionName::hoixocml,
+    MapSectionName::hoixwtml, MapSectionName::hoixsmml, MapSectionName::hoixrpml, MapSectionName::hoixbwml,
+    MapSectionName::hoixbbml, MapSectionName::hoixorml, MapSectionName::hoixbsml, MapSectionName::hoixoaml,
+    MapSectionName::hoix1mme, MapSectionName::hoiximme,
+];
+
+/// A section as stored in the file, known to the loader or not.
+pub struct MapSection {
+    prefix: [u8; 0x20],
+    header: Header,
+    data: Vec<u8>,
+    decoded: bool,
+}
+
+impl MapSection {
+    /// Section name, e.g. `hoixehml`.
+    pub fn tag(&self) -> String {
+        String::from_utf8_lossy(&self.header.tag).into_owned()
+    }
+
+    /// The header fields around the section length, in file order.
+    pub fn unknown(&self) -> [u32; 5] {
+        let h = &self.header;
+        [h.unk1, h.unk2, h.unk3, h.unk4, h.unk5]
+    }
+
+    /// The 0x20 bytes in front of the header.
+    pub fn prefix(&self) -> &[u8] { &self.prefix }
+    pub fn data(&self) -> &[u8] { &self.data }
+
+    /// Whether the content is available through the accessors of
+    /// `CulturesMapData`. Other sections are only kept as bytes.
+    pub fn is_decoded(&self) -> bool { self.decoded }
+}
+
+fn missing(name: MapSectionName) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("map section {} is missing", name.as_str()))
+}
+
+/// Checks that a layer has one value per cell.
+fn layer<T>(data: Box<[T]>, name: MapSectionName, width: u32, height: u32) -> std::io::Result<Box<[T]>> {
+    if data.len() != width as usize * height as usize {
+        return Err(Error::new(ErrorKind::InvalidData, format!("map section {} does not match the map size", name.as_str())));
+    }
+
+    Ok(data)
+}
+
 macro_rules! decode_hoix {
     ($func:ident, $from:ident) => {
-        $func (&mut Cursor::new($from.remove(&MapSectionName::$func).unwrap())).unwrap()
+        match $from.remove(&MapSectionName::$func) {
+            Some(data) => Some($func(&mut Cursor::new(data))?),
+            None => None,
+        }
     }
 }
 
+macro_rules! decode_layer {
+    ($func:ident, $from:ident, $width:expr, $height:expr) => {
+        match decode_hoix!($func, $from) {
+            Some(decoded) => Some(layer(decoded.data, MapSectionName::$func, $width, $height)?),
+            None => None,
+        }
+    }
+}
 
-async fn read_map_data(blob: Blob) -> std::io::Result<CulturesMapData> {
-
-    let mut section_headers: HashMap<MapSectionName, Header> = HashMap::new();
-    let mut section_datas: HashMap<MapSectionName, Vec<u8>> = HashMap::new();
+pub fn parse_map_data(buf: &[u8]) -> std::io::Result<CulturesMapData> {
+    let mut sections: Vec<MapSection> = Vec::new();
 
-    let mut cursor = Cursor::new(read_file(blob).await.to_vec().into_boxed_slice());
+    let mut cursor = Cursor::new(buf);
 
     loop {
-        let mut buf = [0u8; 0x20];
-        cursor.read_exact(&mut buf)?;
-        let header = read_header(&mut cursor).unwrap();
-
-        let section_name = MapSectionName::from_str(header.tag.as_str());
-        if let Some(section_name) = section_name {
-
-            let mut data =  vec![0u8; header.section_length as usize];
-            cursor.read_exact(data.as_mut_slice()).unwrap();
-            section_datas.insert(section_name, data);
-            section_headers.insert(section_name, header);
-        } else {
-            cursor.seek(SeekFrom::Current(header.section_length as i64)).unwrap();
-        }
+        let mut prefix = [0u8; 0x20];
+        cursor.read_exact(&mut prefix)?;
+        let header = read_header(&mut cursor)?;
+
+        let mut data = vec![0u8; header.section_length as usize];
+        cursor.read_exact(data.as_mut_slice())?;
 
-        if is_eof_box(&cursor) {
+        sections.push(MapSection { prefix, header, data, decoded: false });
+
+        if cursor.position() as usize >= buf.len() {
             break;
         }
     }
 
+    // The content of the known sections, borrowed from `sections`. A later
+    // section replaces an earlier one with the same name.
+    let mut section_datas: HashMap<MapSectionName, &[u8]> = sections.iter()
+        .filter_map(|s| s.header.name().map(|name| (name, &s.data[..])))
+        .collect();
 
-    let hoixzisl = decode_hoix!(hoixzisl, section_datas);
+    let size = decode_hoix!(hoixzisl, section_datas).ok_or_else(|| missing(MapSectionName::hoixzisl))?;
+    let (width, height) = (size.width, size.height);
 
-    return Ok(CulturesMapData {
-        width: hoixzisl.width,
-        height: hoixzisl.height,
-        elevation: decode_hoix!(hoixehml, section_datas).data,
-        lighting: decode_hoix!(hoixrbme, section_datas).data,
+    let elevation = decode_layer!(hoixehml, section_datas, width, height).ok_or_else(|| missing(MapSectionName::hoixehml))?;
+
+    let mut layers = Vec::new();
+    let mut undecoded = Vec::new();
+    for &(section, name) in NAMED_LAYERS.iter() {
+        if let Some(data) = section_datas.remove(&section) {
+            let mut view = Cursor::new(data);
+            let data = match section {
+                MapSectionName::hoixoaml => common_decoding2(&mut view).map(|d| LayerData::U16(d.data)),
+                _ => common_decoding(&mut view).map(|d| LayerData::U8(d.data)),
+            };
+            match data {
+                Ok(data) => layers.push(MapLayer::new(section, name, data, width, height)),
+                // The meaning of these layers is unknown, so one that does not
+                // decode is only kept as bytes.
+                Err(_) => undecoded.push(section),
+            }
+        }
+    }
+
+    let mut map = CulturesMapData {
+        width,
+        height,
+        elevation,
+        lighting: decode_layer!(hoixrbme, section_datas, width, height),
 
         tiles_index: decode_hoix!(hoixdpae, section_datas),
-        tiles_a: decode_hoix!(hoixapme, section_datas).data,
-        tiles_b: decode_hoix!(hoixbpme, section_datas).data,
+        tiles_a: decode_layer!(hoixapme, section_datas, width, height),
+        tiles_b: decode_layer!(hoixbpme, section_datas, width, height),
 
         transitions_index: decode_hoix!(hoixdtae, section_datas),
-        trans_a1: decode_hoix!(hoix1tme, section_datas).data,
-        trans_b1: decode_hoix!(hoix2tme, section_datas).data,
-        trans_a2: decode_hoix!(hoix3tme, section_datas).data,
-        trans_b2: decode_hoix!(hoix4tme, section_datas).data,
+        trans_a1: decode_layer!(hoix1tme, section_datas, width, height),
+        trans_b1: decode_layer!(hoix2tme, section_datas, width, height),
+        trans_a2: decode_layer!(hoix3tme, section_datas, width, height),
+        trans_b2: decode_layer!(hoix4tme, section_datas, width, height),
 
         landscape_index: decode_hoix!(hoixdlae, section_datas),
 
-        landscape_job_types: decode_hoix!(hoixtlml, section_datas).data,
-        landscape_types: decode_hoix!(hoixalme, section_datas).data,
-        landscape_levels: decode_hoix!(hoixvlml, section_datas).data,
-    });
+        landscape_job_types: decode_layer!(hoixtlml, section_datas, width, height),
+        landscape_types: decode_layer!(hoixalme, section_datas, width, height),
+        landscape_levels: decode_layer!(hoixvlml, section_datas, width, height),
+
+        layers,
+        sections,
+    };
+
+    for section in map.sections.iter_mut() {
+        section.decoded = section.header.name()
+            .is_some_and(|name| DECODED_SECTIONS.contains(&name) && !undecoded.contains(&name));
+    }
+
+    Ok(map)
+}
+
+/// Re-encodes the sections backed by a layer of `map`. Everything else is
+/// left to be copied verbatim.
+fn encode_section(map: &CulturesMapData, name: MapSectionName, original: &[u8]) -> Option<Vec<u8>> {
+    // `unk1` and the magic of the layer sections.
+    let unk1 = original.first().copied().unwrap_or(1);
+    let magic = original.get(5..13).unwrap_or(&[0u8; 8]);
+
+    let common = |data: Option<&[u8]>| data.map(|d| common_encoding(unk1, magic, d));
+    let common2 = |data: Option<&[u16]>| data.map(|d| common_encoding2(unk1, magic, d));
+    // Names are decoded lossily, so unchanged dictionaries keep their bytes.
+    let names = |data: Option<&[String]>| data
+        .filter(|&d| dictionary(&mut Cursor::new(original)).map_or(true, |o| &o[..] != d))
+        .map(dictionary_encoding);
+
+    match name {
+        MapSectionName::hoixzisl => {
+            let mut out = Vec::with_capacity(original.len());
+            out.write_u32::<LittleEndian>(map.width).unwrap();
+            out.write_u32::<LittleEndian>(map.height).unwrap();
+            out.extend_from_slice(original.get(8..).unwrap_or(&[]));
+            Some(out)
+        }
+        MapSectionName::hoixehml => common(Some(map.elevation())),
+        MapSectionName::hoixrbme => common(map.lighting()),
+
+        MapSectionName::hoixdpae => names(map.tiles_index()),
+        MapSectionName::hoixapme => common2(map.tiles_a()),
+        MapSectionName::hoixbpme => common2(map.tiles_b()),
+
+        MapSectionName::hoixdtae => names(map.transitions_index()),
+        MapSectionName::hoix1tme => common(map.trans_a1()),
+        MapSectionName::hoix2tme => common(map.trans_b1()),
+        MapSectionName::hoix3tme => common(map.trans_a2()),
+        MapSectionName::hoix4tme => common(map.trans_b2()),
+
+        MapSectionName::hoixdlae => names(map.landscape_index()),
+        MapSectionName::hoixtlml => common(map.landscape_job_types()),
+        MapSectionName::hoixalme => map.landscape_types().map(|d| raw_encoding(unk1, magic, d)),
+        MapSectionName::hoixvlml => common(map.landscape_levels()),
+
+        _ => None,
+    }
+}
+
+/// Serialises `map` in the layout `parse_map_data` reads. Sections keep their
+/// order, prefix and unknown header fields.
+pub fn write_map_data(map: &CulturesMapData) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    for section in map.sections.iter() {
+        let encoded = section.header.name()
+            .and_then(|name| encode_section(map, name, &section.data));
+        let data = encoded.as_deref().unwrap_or(&section.data);
+
+        out.extend_from_slice(&section.prefix);
+        write_header(&mut out, &section.header, data.len() as u32);
+        out.extend_from_slice(data);
+    }
+
+    return out;
+}
+
+pub async fn read_map_data(file: FileAbstraction) -> std::io::Result<CulturesMapData> {
+    let cursor = file.get_as_cursor().await;
+
+    parse_map_data(cursor.get_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn section(tag: &str, data: &[u8]) -> Vec<u8> {
+        let mut out = vec![0u8; 0x20];
+        out.extend_from_slice(tag.as_bytes());
+        for v in [0, data.len() as u32, 0, 0, 0, 0].iter() {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        out.extend_from_slice(data);
+        out
+    }
+
+    fn rle_layer(length: u32, rle: &[u8]) -> Vec<u8> {
+        let mut out = vec![1u8];
+        out.extend_from_slice(&(rle.len() as u32 + 16).to_le_bytes());
+        out.extend_from_slice(b"hoixdata");
+        out.extend_from_slice(&length.to_le_bytes());
+        out.extend_from_slice(&(rle.len() as u32 + 16).to_le_bytes());
+        out.extend_from_slice(rle);
+        out
+    }
+
+    fn size(width: u32, height: u32) -> Vec<u8> {
+        [width.to_le_bytes(), height.to_le_bytes()].concat()
+    }
+
+    #[test]
+    fn test_parse_map_data() {
+        let mut buf = section("hoixzisl", &size(2, 2));
+        buf.extend(section("hoixehml", &rle_layer(4, &[0x82, 7, 2, 1, 2])));
+        buf.extend(section("hoixapme", &rle_layer(8, &[0x83, 5, 0, 1, 9, 0])));
+        buf.extend(section("hoixunkn", &[1, 2, 3]));
+
+        let map = parse_map_data(&buf).unwrap();
+        assert_eq!((map.width(), map.height()), (2, 2));
+        assert_eq!(map.elevation(), &[7, 7, 1, 2]);
+        assert_eq!(map.tiles_a(), Some(&[5u16, 5, 5, 9][..]));
+        assert!(map.lighting().is_none());
+    }
+
+    #[test]
+    fn test_write_map_data_roundtrip() {
+        let mut names = encoding_functions::dictionary_encoding(&["grass".to_string(), "rock".to_string()]);
+        // A cp1252 "é" in place of the "g", which is not valid UTF-8.
+        names[5] = 0xE9;
+        let elevation: Vec<u8> = (0..300u32).map(|i| if i < 150 { 3 } else { i as u8 }).collect();
+        let tiles: Vec<u16> = (0..300u16).map(|i| i / 7).collect();
+
+        let mut buf = section("hoixzisl", &[size(20, 15), vec![9, 9]].concat());
+        buf.extend(section("hoixehml", &common_encoding(1, b"hoixdata", &elevation)));
+        buf.extend(section("hoixdpae", &names));
+        buf.extend(section("hoixapme", &common_encoding2(1, b"hoixdata", &tiles)));
+        buf.extend(section("hoixunkn", &[1, 2, 3]));
+        buf.extend(section("hoixocml", &[4, 5]));
+        buf[0x20 + 8] = 0x42;
+
+        let map = parse_map_data(&buf).unwrap();
+        let saved = write_map_data(&map);
+        let reloaded = parse_map_data(&saved).unwrap();
+
+        assert_eq!((reloaded.width(), reloaded.height()), (20, 15));
+        assert_eq!(reloaded.elevation(), &elevation[..]);
+        assert_eq!(reloaded.tiles_a(), Some(&tiles[..]));
+        assert_eq!(reloaded.tiles_index(), map.tiles_index());
+        assert_eq!(write_map_data(&reloaded), saved);
+
+        // Unknown header fields, trailing data and foreign sections survive.
+        assert_eq!(saved[0x20 + 8], 0x42);
+        assert_eq!(&saved[0x40..0x4A], &[20, 0, 0, 0, 15, 0, 0, 0, 9, 9]);
+        assert!(saved.ends_with(&section("hoixunkn", &[1, 2, 3]).into_iter().chain(section("hoixocml", &[4, 5])).collect::<Vec<u8>>()));
+        assert!(saved.windows(names.len()).any(|w| w == &names[..]));
+    }
+
+    #[test]
+    fn test_section_inventory() {
+        let mut buf = section("hoixzisl", &size(1, 1));
+        buf.extend(section("hoixehml", &rle_layer(1, &[1, 7])));
+        buf.extend(section("hoixfhml", &[1, 2, 3]));
+        buf.extend(section("hoixocml", &[4, 5]));
+        // `unk2` of the size section and a prefix byte.
+        buf[0x30] = 7;
+        buf[8] = 3;
+
+        let map = parse_map_data(&buf).unwrap();
+        let sections = map.sections();
+
+        let tags: Vec<String> = sections.iter().map(|s| s.tag()).collect();
+        assert_eq!(tags, vec!["hoixzisl", "hoixehml", "hoixfhml", "hoixocml"]);
+        assert_eq!(sections.iter().map(|s| s.is_decoded()).collect::<Vec<bool>>(), vec![true, true, false, false]);
+        assert_eq!(sections[0].unknown(), [0, 7, 0, 0, 0]);
+        assert_eq!(sections[2].data(), &[1, 2, 3]);
+        assert_eq!(sections[0].prefix()[8], 3);
+    }
+
+    #[test]
+    fn test_named_layers() {
+        let mut buf = section("hoixzisl", &size(2, 2));
+        buf.extend(section("hoixehml", &rle_layer(4, &[0x84, 0])));
+        buf.extend(section("hoixbwml", &rle_layer(4, &[4, 0, 1, 0, 0])));
+        buf.extend(section("hoixoaml", &rle_layer(2, &[1, 0x34, 0x12])));
+
+        let map = parse_map_data(&buf).unwrap();
+        let names: Vec<&str> = map.layers().iter().map(|l| l.name).collect();
+        assert_eq!(names, vec!["bwml", "oaml"]);
+        assert!(map.sections().iter().all(|s| s.is_decoded()));
+
+        let oaml = map.layer("oaml").unwrap();
+        assert_eq!((oaml.width, oaml.height), (1, 1));
+        assert!(matches!(&oaml.data, LayerData::U16(d) if d[..] == [0x1234]));
+        assert!(matches!(&map.layer("bwml").unwrap().data, LayerData::U8(d) if d[..] == [0, 1, 0, 0]));
+
+        let walkability = map.walkability().unwrap();
+        assert!(walkability.is_walkable(0, 0));
+        assert!(!walkability.is_walkable(1, 0));
+        assert!(map.water().is_none());
+    }
+
+    #[test]
+    fn test_parse_map_data_errors() {
+        let buf = section("hoixzisl", &size(2, 2));
+        assert!(parse_map_data(&buf).is_err());
+
+        let mut buf = section("hoixzisl", &size(2, 2));
+        buf.extend(section("hoixehml", &rle_layer(4, &[0x85, 7])));
+        assert!(parse_map_data(&buf).is_err());
+
+        assert!(parse_map_data(&[0u8; 0x30]).is_err());
+
+        let mut buf = section("hoixzisl", &size(2, 2));
+        buf.extend(section("hoixehml", &rle_layer(4, &[0x84, 0])));
+        buf.extend(section("hoixalme", &encoding_functions::raw_encoding(1, b"hoixdata", &[1, 2, 3])));
+        assert!(parse_map_data(&buf).is_err());
+    }
 }
@@ -0,0 +1,326 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use crate::fromts::map::CulturesMapData;
+use crate::fromts::map::layers::{LayerData, MapLayer};
+use crate::png;
+
+/// Any decoded layer of a map, widened to 16 bits.
+pub struct LayerValues {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub values: Vec<u16>,
+}
+
+/// Layers held by fields of `CulturesMapData`, in the order `map_layers`
+/// lists them.
+const FIELD_LAYERS: [&str; 11] = [
+    "elevation", "lighting", "tiles_a", "tiles_b", "trans_a1", "trans_b1", "trans_a2", "trans_b2",
+    "landscape_levels", "landscape_types", "landscape_job_types",
+];
+
+fn field_layer(map: &CulturesMapData, name: &str) -> Option<Vec<u16>> {
+    let wide = |d: Option<&[u8]>| d.map(|d| d.iter().map(|&v| v as u16).collect());
+
+    match name {
+        "elevation" => wide(Some(map.elevation())),
+        "lighting" => wide(map.lighting()),
+        "tiles_a" => map.tiles_a().map(|d| d.to_vec()),
+        "tiles_b" => map.tiles_b().map(|d| d.to_vec()),
+        "trans_a1" => wide(map.trans_a1()),
+        "trans_b1" => wide(map.trans_b1()),
+        "trans_a2" => wide(map.trans_a2()),
+        "trans_b2" => wide(map.trans_b2()),
+        "landscape_levels" => wide(map.landscape_levels()),
+        "landscape_types" => wide(map.landscape_types()),
+        "landscape_job_types" => wide(map.landscape_job_types()),
+        _ => None,
+    }
+}
+
+fn generic_layer(layer: &MapLayer) -> LayerValues {
+    let values = match &layer.data {
+        LayerData::U8(d) => d.iter().map(|&v| v as u16).collect(),
+        LayerData::U16(d) => d.to_vec(),
+    };
+
+    LayerValues { name: layer.name.to_string(), width: layer.width, height: layer.height, values }
+}
+
+/// The decoded layer called `name`, see `map_layers` for the names.
+pub fn map_layer(map: &CulturesMapData, name: &str) -> Option<LayerValues> {
+    if let Some(values) = field_layer(map, name) {
+        let (w, h) = (map.width(), map.height());
+        let (width, height) = if values.len() == (w * h) as usize { (w, h) } else { (values.len() as u32, 1) };
+        return Some(LayerValues { name: name.to_string(), width, height, values });
+    }
+
+    map.layer(name).map(generic_layer)
+}
+
+/// Every decoded layer of `map`, the ones held by fields first.
+pub fn map_layers(map: &CulturesMapData) -> Vec<LayerValues> {
+    FIELD_LAYERS.iter().filter_map(|name| map_layer(map, name))
+        .chain(map.layers().iter().map(generic_layer))
+        .collect()
+}
+
+pub struct LayerStats {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    /// Number of cells per value.
+    pub histogram: BTreeMap<u16, u32>,
+    pub min: u16,
+    pub max: u16,
+    /// Number of runs per run length, counted along the rows.
+    pub runs: BTreeMap<u32, u32>,
+    /// Pearson correlation with the elevation, for full size layers.
+    pub elevation_correlation: Option<f64>,
+    /// Share of the variance explained by `tiles_a` (correlation ratio η²),
+    /// for full size layers.
+    pub tile_correlation: Option<f64>,
+}
+
+impl LayerStats {
+    pub fn distinct(&self) -> usize {
+        self.histogram.len()
+    }
+
+    pub fn cells(&self) -> u32 {
+        self.histogram.values().sum()
+    }
+
+    /// Adds the counts of `other`, usually the same layer of another map.
+    /// The correlations become the mean of both weighted by their cells, the
+    /// size is reset to 0 × 0 if it differs.
+    pub fn merge(&mut self, other: &LayerStats) {
+        let (a, b) = (self.cells() as f64, other.cells() as f64);
+        let mean = |x: Option<f64>, y: Option<f64>| match (x, y) {
+            (Some(x), Some(y)) => Some((x * a + y * b) / (a + b)),
+            (x, None) => x,
+            (None, y) => y,
+        };
+        self.elevation_correlation = mean(self.elevation_correlation, other.elevation_correlation);
+        self.tile_correlation = mean(self.tile_correlation, other.tile_correlation);
+
+        if a == 0.0 {
+            self.min = other.min;
+            self.max = other.max;
+        } else if b > 0.0 {
+            self.min = self.min.min(other.min);
+            self.max = self.max.max(other.max);
+        }
+
+        for (&v, &n) in other.histogram.iter() {
+            *self.histogram.entry(v).or_insert(0) += n;
+        }
+        for (&l, &n) in other.runs.iter() {
+            *self.runs.entry(l).or_insert(0) += n;
+        }
+
+        if (self.width, self.height) != (other.width, other.height) {
+            self.width = 0;
+            self.height = 0;
+        }
+    }
+}
+
+/// Merges `stats` into `into` by layer name, appending layers `into` does
+/// not have yet.
+pub fn merge_stats(into: &mut Vec<LayerStats>, stats: Vec<LayerStats>) {
+    for s in stats {
+        match into.iter_mut().find(|t| t.name == s.name) {
+            Some(t) => t.merge(&s),
+            None => into.push(s),
+        }
+    }
+}
+
+fn pearson(a: &[u16], b: &[u16]) -> Option<f64> {
+    let n = a.len() as f64;
+    let mean_a = a.iter().map(|&v| v as f64).sum::<f64>() / n;
+    let mean_b = b.iter().map(|&v| v as f64).sum::<f64>() / n;
+
+    let (mut cov, mut var_a, mut var_b) = (0.0, 0.0, 0.0);
+    for (&x, &y) in a.iter().zip(b.iter()) {
+        let (dx, dy) = (x as f64 - mean_a, y as f64 - mean_b);
+        cov += dx * dy;
+        var_a += dx * dx;
+        var_b += dy * dy;
+    }
+
+    if var_a == 0.0 || var_b == 0.0 {
+        return None;
+    }
+
+    Some(cov / (var_a * var_b).sqrt())
+}
+
+/// η² of `values` grouped by `groups`.
+fn correlation_ratio(values: &[u16], groups: &[u16]) -> Option<f64> {
+    let n = values.len() as f64;
+    let mean = values.iter().map(|&v| v as f64).sum::<f64>() / n;
+
+    let mut by_group: BTreeMap<u16, (f64, f64)> = BTreeMap::new();
+    for (&v, &g) in values.iter().zip(groups.iter()) {
+        let e = by_group.entry(g).or_insert((0.0, 0.0));
+        e.0 += v as f64;
+        e.1 += 1.0;
+    }
+
+    let total: f64 = values.iter().map(|&v| (v as f64 - mean).powi(2)).sum();
+    if total == 0.0 {
+        return None;
+    }
+
+    let between: f64 = by_group.values().map(|(sum, count)| count * (sum / count - mean).powi(2)).sum();
+
+    Some(between / total)
+}
+
+pub fn layer_stats(layer: &LayerValues, elevation: &[u16], tiles: Option<&[u16]>) -> LayerStats {
+    let mut histogram = BTreeMap::new();
+    for &v in layer.values.iter() {
+        *histogram.entry(v).or_insert(0) += 1;
+    }
+
+    let mut runs = BTreeMap::new();
+    for row in layer.values.chunks(layer.width.max(1) as usize) {
+        let mut start = 0;
+        for i in 1..=row.len() {
+            if i == row.len() || row[i] != row[start] {
+                *runs.entry((i - start) as u32).or_insert(0) += 1;
+                start = i;
+            }
+        }
+    }
+
+    let full_size = layer.values.len() == elevation.len() && layer.height > 1;
+
+    LayerStats {
+        name: layer.name.clone(),
+        width: layer.width,
+        height: layer.height,
+        min: histogram.keys().next().copied().unwrap_or(0),
+        max: histogram.keys().next_back().copied().unwrap_or(0),
+        histogram,
+        runs,
+        elevation_correlation: if full_size { pearson(&layer.values, elevation) } else { None },
+        tile_correlation: match tiles {
+            Some(t) if full_size && t.len() == layer.values.len() => correlation_ratio(&layer.values, t),
+            _ => None,
+        },
+    }
+}
+
+/// Statistics of every decoded layer of `map`.
+pub fn map_stats(map: &CulturesMapData) -> Vec<LayerStats> {
+    let layers = map_layers(map);
+    let elevation = &layers[0].values;
+    let tiles = map.tiles_a();
+
+    layers.iter().map(|l| layer_stats(l, elevation, tiles)).collect()
+}
+
+/// One line per layer. The histogram is cut off after its `top` most common
+/// values.
+pub fn render_report(stats: &[LayerStats], top: usize) -> String {
+    let mut out = String::new();
+    let fmt = |v: Option<f64>| v.map_or("-".to_string(), |v| format!("{:.3}", v));
+
+    for s in stats {
+        let mut common: Vec<(&u16, &u32)> = s.histogram.iter().collect();
+        common.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let common: Vec<String> = common.iter().take(top).map(|(v, n)| format!("{}:{}", v, n)).collect();
+
+        let runs: u32 = s.runs.values().sum();
+        let cells = s.cells();
+
+        writeln!(out, "{} {}x{} min {} max {} distinct {} mean run {:.2} elevation {} tiles {} values {}",
+            s.name, s.width, s.height, s.min, s.max, s.distinct(),
+            if runs > 0 { cells as f64 / runs as f64 } else { 0.0 },
+            fmt(s.elevation_correlation), fmt(s.tile_correlation), common.join(" ")).unwrap();
+    }
+
+    return out;
+}
+
+/// Colour of a layer value: 0 is black, every other value gets its own hue.
+fn false_colour(v: u16) -> [u8; 3] {
+    if v == 0 {
+        return [0, 0, 0];
+    }
+
+    // Golden ratio steps spread neighbouring values around the colour wheel.
+    let hue = (v as f64 * 0.618_034).fract() * 6.0;
+    let light = 0.55 + 0.35 * ((v / 7) % 2) as f64;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+
+    let (r, g, b) = match hue as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+
+    [(r * light * 255.0) as u8, (g * light * 255.0) as u8, (b * light * 255.0) as u8]
+}
+
+/// PNG of a layer with a distinct colour per value.
+pub fn render_false_colour(layer: &LayerValues) -> Vec<u8> {
+    let rgba: Vec<u8> = layer.values.iter()
+        .flat_map(|&v| { let c = false_colour(v); vec![c[0], c[1], c[2], 0xFF] })
+        .collect();
+
+    png::encode_rgba(layer.width as usize, layer.height as usize, &rgba)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layer_stats() {
+        let layer = LayerValues { name: "test".to_string(), width: 4, height: 2, values: vec![0, 0, 0, 5, 5, 5, 9, 9] };
+        let elevation = vec![1, 1, 1, 6, 6, 6, 10, 10];
+        let tiles = vec![0, 0, 0, 1, 1, 1, 2, 2];
+        let s = layer_stats(&layer, &elevation, Some(&tiles));
+
+        assert_eq!((s.min, s.max, s.distinct()), (0, 9, 3));
+        assert_eq!(s.histogram[&5], 3);
+        assert_eq!(s.runs.iter().map(|(&l, &n)| (l, n)).collect::<Vec<_>>(), vec![(1, 1), (2, 2), (3, 1)]);
+        assert!((s.elevation_correlation.unwrap() - 1.0).abs() < 1e-9);
+        assert!((s.tile_correlation.unwrap() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_merge_stats() {
+        let a = LayerValues { name: "test".to_string(), width: 2, height: 2, values: vec![1, 1, 2, 2] };
+        let b = LayerValues { name: "test".to_string(), width: 4, height: 1, values: vec![0, 2, 7, 7] };
+        let elevation = vec![0, 0, 1, 1];
+
+        let mut stats = vec![layer_stats(&a, &elevation, None)];
+        merge_stats(&mut stats, vec![layer_stats(&b, &elevation, None), layer_stats(&LayerValues { name: "other".to_string(), ..a }, &elevation, None)]);
+
+        assert_eq!(stats.len(), 2);
+        let s = &stats[0];
+        assert_eq!((s.min, s.max, s.cells(), s.distinct()), (0, 7, 8, 4));
+        assert_eq!(s.histogram[&2], 3);
+        assert_eq!(s.runs.iter().map(|(&l, &n)| (l, n)).collect::<Vec<_>>(), vec![(1, 2), (2, 3)]);
+        assert_eq!((s.width, s.height), (0, 0));
+        // Only the first layer is full size, so its correlation is kept.
+        assert!((s.elevation_correlation.unwrap() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_false_colour() {
+        assert_eq!(false_colour(0), [0, 0, 0]);
+        assert_ne!(false_colour(1), false_colour(2));
+
+        let layer = LayerValues { name: "test".to_string(), width: 2, height: 1, values: vec![0, 3] };
+        assert_eq!(&render_false_colour(&layer)[1..4], b"PNG");
+    }
+}
@@ -2,6 +2,8 @@ use std::collections::{HashMap, HashSet};
 use crate::fromts::cif::read_cif;
 use crate::fromts::middlelayer::cultures_fs::CulturesFS;
 use crate::fromts::cif::definitions::{GfxLandscape, GfxPalette256, GfxPattern, IniCategory, PatternTransition, Transition};
+use crate::animation::{AnimationClip, Playback};
+use crate::bmd::BmdFile;
 
 
 pub struct CulturesRegistry {
@@ -65,7 +67,11 @@ async fn load_pattern_transitions<'a>(fs: &CulturesFS) -> HashMap<String, Transi
 async fn load_landscapes<'a>(fs: &CulturesFS) -> HashMap<String, GfxLandscape> {
     let PATH = "data\\engine2d\\inis\\landscapes\\landscapes.cif";
     let cif = read_cif(fs.open(PATH.to_owned())).await.unwrap();
+    landscapes_by_name(cif)
+}
 
+/// The `GfxLandscape` sections of a parsed `landscapes.cif` by `EditName`.
+pub fn landscapes_by_name(cif: Vec<IniCategory>) -> HashMap<String, GfxLandscape> {
     let mut m = HashMap::<String, GfxLandscape>::new();
 
     for section in cif {
@@ -80,6 +86,18 @@ async fn load_landscapes<'a>(fs: &CulturesFS) -> HashMap<String, GfxLandscape> {
     m
 }
 
+/// Builds the animation of `landscape`. `bmd` must be the first file of its
+/// `GfxBobLibs`.
+pub fn landscape_clip(landscape: &GfxLandscape, bmd: &BmdFile, frame_duration: f32) -> Result<AnimationClip, &'static str> {
+    let playback = Playback::from_flags(landscape.GfxStatic, landscape.GfxLoopAnimation);
+
+    let levels = landscape.GfxFrames.iter()
+        .map(|(level, frames)| (*level, frames.iter().map(|f| *f as usize).collect()))
+        .collect();
+
+    AnimationClip::new(playback, frame_duration, &levels, bmd)
+}
+
 pub async fn load_registry<'a>(fs: &CulturesFS) -> CulturesRegistry {
     return CulturesRegistry {
         palettes: load_palettes(fs).await,
@@ -1,8 +1,8 @@
 mod util;
 mod pcx;
-mod cif;
-mod map;
+pub mod cif;
+pub mod map;
 // mod resource_manager;
-mod middlelayer;
+pub mod middlelayer;
 
 
@@ -93,7 +93,7 @@ pub fn is_eof_box<A>(cursor: &Cursor<Box<[A]>>) -> bool {
     cursor.position() as usize == cursor.get_ref().len()
 }
 
-pub fn is_eof_vec<A>(cursor: &Cursor<Vec<A>>) -> bool {
+pub fn is_eof_slice<A>(cursor: &Cursor<&[A]>) -> bool {
     cursor.position() as usize == cursor.get_ref().len()
 }
 
@@ -0,0 +1,229 @@
+//! Minimal animated GIF encoder for 8-bit palette images.
+
+use std::collections::HashMap;
+
+const MAX_CODE_SIZE: u32 = 12;
+
+/// Encodes full size frames of palette indices as a looping GIF. `delay` is
+/// given in hundredths of a second.
+pub fn encode_animation(width: usize, height: usize, frames: &[Vec<u8>], palette: &[u8], transparent: Option<u8>, delay: u16) -> Vec<u8> {
+  let mut out = b"GIF89a".to_vec();
+
+  out.extend_from_slice(&(width as u16).to_le_bytes());
+  out.extend_from_slice(&(height as u16).to_le_bytes());
+  // Global colour table with 256 entries.
+  out.extend_from_slice(&[0xF7, 0, 0]);
+  out.extend_from_slice(&palette[..768]);
+
+  // Loop forever.
+  out.extend_from_slice(&[0x21, 0xFF, 0x0B]);
+  out.extend_from_slice(b"NETSCAPE2.0");
+  out.extend_from_slice(&[0x03, 0x01, 0x00, 0x00, 0x00]);
+
+  for frame in frames {
+    // Graphic control extension: restore to background after every frame so
+    // transparent pixels do not show the previous frame.
+    let flags = (2 << 2) | if transparent.is_some() { 1 } else { 0 };
+    out.extend_from_slice(&[0x21, 0xF9, 0x04, flags]);
+    out.extend_from_slice(&delay.to_le_bytes());
+    out.extend_from_slice(&[transparent.unwrap_or(0), 0x00]);
+
+    out.push(0x2C);
+    out.extend_from_slice(&[0, 0, 0, 0]);
+    out.extend_from_slice(&(width as u16).to_le_bytes());
+    out.extend_from_slice(&(height as u16).to_le_bytes());
+    out.push(0);
+
+    out.push(8);
+    for block in lzw(&frame[..width * height], 8).chunks(255) {
+      out.push(block.len() as u8);
+      out.extend_from_slice(block);
+    }
+    out.push(0);
+  }
+
+  out.push(0x3B);
+
+  return out;
+}
+
+struct BitWriter {
+  out: Vec<u8>,
+  bits: u32,
+  count: u32,
+}
+
+impl BitWriter {
+  fn write(&mut self, value: u32, len: u32) {
+    self.bits |= value << self.count;
+    self.count += len;
+
+    while self.count >= 8 {
+      self.out.push(self.bits as u8);
+      self.bits >>= 8;
+      self.count -= 8;
+    }
+  }
+}
+
+/// Variable code size LZW as used by GIF, restarting when the code table is
+/// full.
+fn lzw(data: &[u8], min_code_size: u32) -> Vec<u8> {
+  let clear = 1u32 << min_code_size;
+  let end = clear + 1;
+
+  let mut w = BitWriter { out: Vec::new(), bits: 0, count: 0 };
+  let mut table: HashMap<(u32, u8), u32> = HashMap::new();
+  let mut code_size = min_code_size + 1;
+  let mut next = end + 1;
+
+  w.write(clear, code_size);
+
+  let mut prefix = match data.first() {
+    Some(&b) => b as u32,
+    None => {
+      w.write(end, code_size);
+      if w.count > 0 {
+        w.out.push(w.bits as u8);
+      }
+      return w.out;
+    }
+  };
+
+  for &b in &data[1..] {
+    if let Some(&code) = table.get(&(prefix, b)) {
+      prefix = code;
+      continue;
+    }
+
+    w.write(prefix, code_size);
+
+    if next < (1 << MAX_CODE_SIZE) {
+      table.insert((prefix, b), next);
+      if next == (1 << code_size) {
+        code_size += 1;
+      }
+      next += 1;
+    } else {
+      w.write(clear, code_size);
+      table.clear();
+      code_size = min_code_size + 1;
+      next = end + 1;
+    }
+
+    prefix = b as u32;
+  }
+
+  w.write(prefix, code_size);
+  w.write(end, code_size);
+  if w.count > 0 {
+    w.out.push(w.bits as u8);
+  }
+
+  return w.out;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Reference decoder, straight from the GIF specification.
+  fn unlzw(data: &[u8], min_code_size: u32) -> Vec<u8> {
+    let clear = 1usize << min_code_size;
+    let mut table: Vec<Vec<u8>> = Vec::new();
+    let mut code_size = min_code_size + 1;
+    let mut out = Vec::new();
+    let mut prev: Option<usize> = None;
+    let (mut bits, mut count, mut pos) = (0u32, 0u32, 0usize);
+
+    loop {
+      while count < code_size {
+        bits |= (data[pos] as u32) << count;
+        pos += 1;
+        count += 8;
+      }
+      let code = (bits & ((1 << code_size) - 1)) as usize;
+      bits >>= code_size;
+      count -= code_size;
+
+      if code == clear {
+        table = (0..clear).map(|i| vec![i as u8]).collect();
+        table.push(Vec::new());
+        table.push(Vec::new());
+        code_size = min_code_size + 1;
+        prev = None;
+        continue;
+      }
+      if code == clear + 1 {
+        return out;
+      }
+
+      let entry = if code < table.len() {
+        table[code].clone()
+      } else {
+        let mut e = table[prev.unwrap()].clone();
+        e.push(e[0]);
+        e
+      };
+
+      out.extend_from_slice(&entry);
+      if let Some(p) = prev {
+        let mut e = table[p].clone();
+        e.push(entry[0]);
+        table.push(e);
+        if table.len() == (1 << code_size) && code_size < MAX_CODE_SIZE {
+          code_size += 1;
+        }
+      }
+      prev = Some(code);
+    }
+  }
+
+  #[test]
+  fn test_lzw_roundtrip() {
+    // Noise fills the code table several times, the runs test long codes.
+    let mut seed = 1u32;
+    let mut data: Vec<u8> = (0..30000).map(|_| { seed = seed.wrapping_mul(1103515245).wrapping_add(12345); (seed >> 16) as u8 }).collect();
+    data.extend((0..20000u32).map(|i| (i / 700 % 5) as u8));
+
+    assert_eq!(unlzw(&lzw(&data, 8), 8), data);
+  }
+
+  #[test]
+  fn test_encode_animation_structure() {
+    let gif = encode_animation(2, 2, &[vec![0, 1, 1, 0], vec![1, 0, 0, 1]], &[0u8; 768], Some(0), 10);
+
+    assert_eq!(&gif[..6], b"GIF89a");
+    assert_eq!(&gif[6..10], &[2, 0, 2, 0]);
+    assert_eq!(&gif[13..13 + 768], &[0u8; 768][..]);
+    assert_eq!(*gif.last().unwrap(), 0x3B);
+  }
+
+  #[test]
+  fn test_encode_animation_decodes() {
+    // Noise fills the code table several times, so the decoder has to follow
+    // the code size changes and clear codes.
+    let (width, height) = (97, 61);
+    let mut seed = 7u32;
+    let frames: Vec<Vec<u8>> = (0..3).map(|f| (0..width * height).map(|i| {
+      seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+      if f == 1 { (i / 13 % 4) as u8 } else { (seed >> 16) as u8 }
+    }).collect()).collect();
+    let palette: Vec<u8> = (0..768).map(|i| (i * 7) as u8).collect();
+
+    let data = encode_animation(width, height, &frames, &palette, Some(5), 12);
+
+    let mut options = ::gif::DecodeOptions::new();
+    options.set_color_output(::gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(&data[..]).unwrap();
+    assert_eq!((decoder.width() as usize, decoder.height() as usize), (width, height));
+    assert_eq!(decoder.global_palette(), Some(&palette[..]));
+
+    let mut decoded = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().unwrap() {
+      assert_eq!((frame.delay, frame.transparent, frame.dispose), (12, Some(5), ::gif::DisposalMethod::Background));
+      decoded.push(frame.buffer.to_vec());
+    }
+    assert_eq!(decoded, frames);
+  }
+}
@@ -0,0 +1,298 @@
+//! Coordinates on the staggered map grid. Every cell is a vertex of the
+//! terrain mesh and odd rows are shifted half a cell to the right:
+//!
+//! ```text
+//!  0 . 1 . 2 . 3        row 0
+//!  . 0 . 1 . 2 . 3      row 1
+//!  0 . 1 . 2 . 3        row 2
+//! ```
+//!
+//! Each cell owns two triangles: A points up with the cell on top, B points
+//! down with the cell on its top left corner.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Cell {
+  pub x: i32,
+  pub y: i32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TriangleKind {
+  A,
+  B,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Triangle {
+  pub cell: Cell,
+  pub kind: TriangleKind,
+}
+
+/// Offsets of the six neighbours of a cell on an even and an odd row,
+/// clockwise from the right.
+const NEIGHBOURS: [[(i32, i32); 6]; 2] = [
+  [(1, 0), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1)],
+  [(1, 0), (1, 1), (0, 1), (-1, 0), (0, -1), (1, -1)],
+];
+
+impl Cell {
+  pub fn new(x: i32, y: i32) -> Cell {
+    Cell { x, y }
+  }
+
+  /// 1 on odd rows, which are shifted to the right.
+  #[inline]
+  fn shift(&self) -> i32 {
+    self.y & 1
+  }
+
+  pub fn right(&self) -> Cell {
+    Cell::new(self.x + 1, self.y)
+  }
+
+  pub fn below_left(&self) -> Cell {
+    Cell::new(self.x + self.shift() - 1, self.y + 1)
+  }
+
+  pub fn below_right(&self) -> Cell {
+    Cell::new(self.x + self.shift(), self.y + 1)
+  }
+
+  /// The six surrounding cells, clockwise from the right, ignoring the map
+  /// bounds.
+  pub fn neighbours(&self) -> [Cell; 6] {
+    let mut out = [*self; 6];
+    for (c, (dx, dy)) in out.iter_mut().zip(NEIGHBOURS[self.shift() as usize].iter()) {
+      *c = Cell::new(self.x + dx, self.y + dy);
+    }
+
+    return out;
+  }
+
+  /// Axial coordinates, in which the grid is a regular hexagonal lattice.
+  fn axial(&self) -> (i32, i32) {
+    (self.x - (self.y - self.shift()) / 2, self.y)
+  }
+
+  fn from_axial(q: i32, r: i32) -> Cell {
+    Cell::new(q + (r - (r & 1)) / 2, r)
+  }
+
+  /// Number of steps between two cells.
+  pub fn distance(&self, other: Cell) -> u32 {
+    let (q0, r0) = self.axial();
+    let (q1, r1) = other.axial();
+    let (dq, dr) = (q1 - q0, r1 - r0);
+
+    ((dq.abs() + dr.abs() + (dq + dr).abs()) / 2) as u32
+  }
+
+  /// Cells on the straight line from `self` to `to`, both included. Every
+  /// cell is a neighbour of the one before.
+  pub fn line_to(&self, to: Cell) -> impl Iterator<Item = Cell> {
+    let n = self.distance(to);
+    let (q0, r0) = self.axial();
+    let (q1, r1) = to.axial();
+
+    (0..=n).map(move |i| {
+      let t = if n == 0 { 0.0 } else { i as f64 / n as f64 };
+      // Nudged off the cell edges so ties round the same way every time.
+      let q = q0 as f64 + (q1 - q0) as f64 * t + 1e-6;
+      let r = r0 as f64 + (r1 - r0) as f64 * t + 1e-6;
+      let (q, r) = cube_round(q, r);
+
+      Cell::from_axial(q, r)
+    })
+  }
+}
+
+fn cube_round(q: f64, r: f64) -> (i32, i32) {
+  let s = -q - r;
+  let (mut rq, mut rr, rs) = (q.round(), r.round(), s.round());
+  let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+
+  if dq > dr && dq > ds {
+    rq = -rr - rs;
+  } else if dr > ds {
+    rr = -rq - rs;
+  }
+
+  (rq as i32, rr as i32)
+}
+
+impl Triangle {
+  pub fn new(cell: Cell, kind: TriangleKind) -> Triangle {
+    Triangle { cell, kind }
+  }
+
+  /// Corners of the triangle: the owning cell first, then clockwise.
+  pub fn vertices(&self) -> [Cell; 3] {
+    let c = self.cell;
+    match self.kind {
+      TriangleKind::A => [c, c.below_right(), c.below_left()],
+      TriangleKind::B => [c, c.right(), c.below_right()],
+    }
+  }
+
+  /// The three triangles sharing an edge with this one, ignoring the map
+  /// bounds.
+  pub fn neighbours(&self) -> [Triangle; 3] {
+    let c = self.cell;
+    match self.kind {
+      TriangleKind::A => [
+        Triangle::new(c, TriangleKind::B),
+        Triangle::new(c.below_left(), TriangleKind::B),
+        Triangle::new(Cell::new(c.x - 1, c.y), TriangleKind::B),
+      ],
+      TriangleKind::B => [
+        // The cell above whose lower edge is our upper edge.
+        Triangle::new(Cell::new(c.x + 1 - ((c.y - 1) & 1), c.y - 1), TriangleKind::A),
+        Triangle::new(c.right(), TriangleKind::A),
+        Triangle::new(c, TriangleKind::A),
+      ],
+    }
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Grid {
+  pub width: usize,
+  pub height: usize,
+}
+
+impl Grid {
+  pub fn new(width: usize, height: usize) -> Grid {
+    Grid { width, height }
+  }
+
+  pub fn contains(&self, c: Cell) -> bool {
+    c.x >= 0 && c.y >= 0 && (c.x as usize) < self.width && (c.y as usize) < self.height
+  }
+
+  /// Cells on the outer ring, which have no complete neighbourhood.
+  pub fn is_border(&self, c: Cell) -> bool {
+    c.x == 0 || c.y == 0 || c.x as usize == self.width - 1 || c.y as usize == self.height - 1
+  }
+
+  /// Position of a cell in the per-cell layers.
+  pub fn index(&self, c: Cell) -> Option<usize> {
+    if self.contains(c) { Some(c.y as usize * self.width + c.x as usize) } else { None }
+  }
+
+  pub fn cell(&self, i: usize) -> Cell {
+    Cell::new((i % self.width) as i32, (i / self.width) as i32)
+  }
+
+  /// Position of a triangle in per-triangle data, A before B of every cell.
+  pub fn triangle_index(&self, t: Triangle) -> Option<usize> {
+    self.index(t.cell).map(|i| 2 * i + if t.kind == TriangleKind::A { 0 } else { 1 })
+  }
+
+  pub fn triangle(&self, i: usize) -> Triangle {
+    Triangle::new(self.cell(i / 2), if i % 2 == 0 { TriangleKind::A } else { TriangleKind::B })
+  }
+
+  /// Every cell, row by row.
+  pub fn cells(&self) -> impl Iterator<Item = Cell> {
+    let width = self.width;
+    (0..self.width * self.height).map(move |i| Cell::new((i % width) as i32, (i / width) as i32))
+  }
+
+  pub fn neighbours(&self, c: Cell) -> impl Iterator<Item = Cell> {
+    let grid = *self;
+    IntoIterator::into_iter(c.neighbours()).filter(move |&n| grid.contains(n))
+  }
+
+  pub fn triangle_neighbours(&self, t: Triangle) -> impl Iterator<Item = Triangle> {
+    let grid = *self;
+    IntoIterator::into_iter(t.neighbours()).filter(move |n| grid.contains(n.cell))
+  }
+
+  /// Cells of the line from `from` to `to` that lie on the map.
+  pub fn line(&self, from: Cell, to: Cell) -> impl Iterator<Item = Cell> {
+    let grid = *self;
+    from.line_to(to).filter(move |&c| grid.contains(c))
+  }
+
+  /// Cells at most `radius` steps from `center`, row by row.
+  pub fn area(&self, center: Cell, radius: u32) -> impl Iterator<Item = Cell> {
+    let grid = *self;
+    let r = radius as i32;
+
+    (center.y - r..=center.y + r)
+      .flat_map(move |y| (center.x - r..=center.x + r).map(move |x| Cell::new(x, y)))
+      .filter(move |&c| grid.contains(c) && center.distance(c) <= radius)
+  }
+
+  /// Cells inside the rectangle spanned by two corners, clipped to the map.
+  pub fn rect(&self, a: Cell, b: Cell) -> impl Iterator<Item = Cell> {
+    let grid = *self;
+    let (x0, x1) = (a.x.min(b.x), a.x.max(b.x));
+    let (y0, y1) = (a.y.min(b.y), a.y.max(b.y));
+
+    (y0..=y1)
+      .flat_map(move |y| (x0..=x1).map(move |x| Cell::new(x, y)))
+      .filter(move |&c| grid.contains(c))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_neighbours_are_one_step_away() {
+    for &c in [Cell::new(4, 4), Cell::new(4, 5)].iter() {
+      let n = c.neighbours();
+      assert!(n.iter().all(|&m| c.distance(m) == 1));
+      // Neighbourhood is symmetric.
+      assert!(n.iter().all(|m| m.neighbours().contains(&c)));
+    }
+
+    let grid = Grid::new(3, 3);
+    assert_eq!(grid.neighbours(Cell::new(0, 0)).count(), 2);
+    assert_eq!(grid.neighbours(Cell::new(1, 1)).count(), 6);
+  }
+
+  #[test]
+  fn test_triangle_neighbours_share_an_edge() {
+    for &kind in [TriangleKind::A, TriangleKind::B].iter() {
+      for &c in [Cell::new(4, 4), Cell::new(4, 5)].iter() {
+        let t = Triangle::new(c, kind);
+        let v = t.vertices();
+
+        for n in t.neighbours().iter() {
+          assert_ne!(n.kind, kind);
+          let shared = n.vertices().iter().filter(|w| v.contains(w)).count();
+          assert_eq!(shared, 2, "{:?} and {:?}", t, n);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_index_roundtrip() {
+    let grid = Grid::new(5, 4);
+    for i in 0..20 {
+      assert_eq!(grid.index(grid.cell(i)), Some(i));
+      assert_eq!(grid.triangle_index(grid.triangle(2 * i + 1)), Some(2 * i + 1));
+    }
+    assert_eq!(grid.index(Cell::new(5, 0)), None);
+  }
+
+  #[test]
+  fn test_line_and_area() {
+    let (a, b) = (Cell::new(1, 1), Cell::new(6, 4));
+    let line: Vec<Cell> = a.line_to(b).collect();
+
+    assert_eq!(line.len() as u32, a.distance(b) + 1);
+    assert_eq!((line[0], *line.last().unwrap()), (a, b));
+    assert!(line.windows(2).all(|w| w[0].distance(w[1]) == 1));
+
+    let grid = Grid::new(10, 10);
+    assert_eq!(grid.area(Cell::new(5, 5), 1).count(), 7);
+    assert_eq!(grid.area(Cell::new(5, 5), 2).count(), 19);
+    assert_eq!(grid.area(Cell::new(0, 0), 1).count(), 3);
+    assert_eq!(grid.rect(Cell::new(8, 8), Cell::new(12, 9)).count(), 4);
+  }
+}
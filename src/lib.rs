@@ -1,12 +1,25 @@
 use web_sys::console;
 
 mod utils;
+pub mod grid;
 mod tessellate;
 mod pcx;
 mod bmd;
 mod timer;
+mod mipmap;
+mod dxt;
+mod texture;
+mod png;
+mod gif;
+mod sprite_export;
+mod atlas;
+mod palette;
+mod quantize;
+mod animation;
+mod minimap;
+mod pathfinding;
+mod render;
 mod fromts;
-mod nithanim;
 
 use wasm_bindgen::prelude::*;
 
@@ -24,26 +37,120 @@ pub fn triangulate(w: usize, h: usize, elevation: &[u8]) -> Box<[f32]> {
   return tris.into_boxed_slice();
 }
 
+/// `fill` (RGBA, default transparent), `format` (default RGBA8) and
+/// `pixel_format` (default straight sRGB) are optional, so callers of the
+/// original five argument form keep working.
 #[wasm_bindgen]
-pub fn create_2d_texture_masked(w: usize, h: usize, buf: &[u8], index: &[usize], mask_index: &[usize]) -> Box<[u8]> {
+pub fn create_2d_texture_masked(w: usize, h: usize, buf: &[u8], index: &[usize], mask_index: &[usize], fill: Option<u32>, format: Option<u8>, pixel_format: Option<u8>) -> Result<Box<[u8]>, JsValue> {
   let _timer = timer::Timer::new("create_2d_texture_masked");
 
+  let format = texture::TextureFormat::from_u8(format.unwrap_or(0)).ok_or("unknown texture format")?;
   let mut out = vec![0u8; w * h * index.len() * 4];
-  pcx::pcx_texture_array(&buf, &mut out[..], &index, Some(&mask_index));
+  pcx::pcx_texture_array(&buf, &mut out[..], w, h, fill.unwrap_or(0).to_be_bytes(), &index, Some(&mask_index), texture::PixelFormat::from_bits(pixel_format.unwrap_or(0)));
 
-  return out.into_boxed_slice();
+  Ok(texture::encode_layers(&out, w, h, index.len(), format).into_boxed_slice())
 }
 
+/// Optional arguments as for `create_2d_texture_masked`.
 #[wasm_bindgen]
-pub fn create_2d_texture(w: usize, h: usize, buf: &[u8], index: &[usize]) -> Box<[u8]> {
+pub fn create_2d_texture(w: usize, h: usize, buf: &[u8], index: &[usize], fill: Option<u32>, format: Option<u8>, pixel_format: Option<u8>) -> Result<Box<[u8]>, JsValue> {
   let _timer = timer::Timer::new("create_2d_texture");
 
+  let format = texture::TextureFormat::from_u8(format.unwrap_or(0)).ok_or("unknown texture format")?;
   let mut out = vec![0u8; w * h * index.len() * 4];
-  pcx::pcx_texture_array(&buf, &mut out[..], &index, None);
+  pcx::pcx_texture_array(&buf, &mut out[..], w, h, fill.unwrap_or(0).to_be_bytes(), &index, None, texture::PixelFormat::from_bits(pixel_format.unwrap_or(0)));
+
+  Ok(texture::encode_layers(&out, w, h, index.len(), format).into_boxed_slice())
+}
+
+/// Like `create_2d_texture_masked`, but appends `levels` mip levels (0 for a
+/// full chain) built with `filter` (0 = box, 1 = Kaiser). An empty
+/// `mask_index` means no masks; with masks the filtering is alpha-aware.
+/// `fill` and `pixel_format` are optional as for `create_2d_texture_masked`.
+///
+/// Layout: level count, then per level width, height, byte offset from the
+/// start of the returned buffer and byte length, followed by the levels.
+#[wasm_bindgen]
+pub fn create_2d_texture_mipmapped(w: usize, h: usize, buf: &[u8], index: &[usize], mask_index: &[usize], fill: Option<u32>, levels: usize, filter: u8, pixel_format: Option<u8>) -> Result<Box<[u8]>, JsValue> {
+  let _timer = timer::Timer::new("create_2d_texture_mipmapped");
+
+  let filter = mipmap::MipFilter::from_u8(filter).ok_or("unknown mip filter")?;
+  let pixel_format = texture::PixelFormat::from_bits(pixel_format.unwrap_or(0));
+  let mask_index = if mask_index.is_empty() { None } else { Some(mask_index) };
+
+  let mut base = vec![0u8; w * h * index.len() * 4];
+  pcx::pcx_texture_array(&buf, &mut base[..], w, h, fill.unwrap_or(0).to_be_bytes(), &index, mask_index, pixel_format);
+
+  // Premultiplied texels are already weighted by their alpha.
+  let alpha_aware = mask_index.is_some() && !pixel_format.premultiplied;
+  let (chain, table) = mipmap::mip_chain(&base, w, h, index.len(), levels, filter, alpha_aware);
+  let header_length = 4 + 4 * 4 * table.len();
+
+  let mut out = vec![0u8; header_length + chain.len()];
+  let mut out_ptr = 0usize;
+
+  write_uint32_le(&mut out[out_ptr..], table.len() as u32); out_ptr += 4;
+
+  for level in table.iter() {
+    write_uint32_le(&mut out[out_ptr..], level.width as u32); out_ptr += 4;
+    write_uint32_le(&mut out[out_ptr..], level.height as u32); out_ptr += 4;
+    write_uint32_le(&mut out[out_ptr..], (header_length + level.offset) as u32); out_ptr += 4;
+    write_uint32_le(&mut out[out_ptr..], level.length as u32); out_ptr += 4;
+  }
+
+  out[out_ptr..].copy_from_slice(&chain);
+
+  Ok(out.into_boxed_slice())
+}
+
+/// Original `[width, height]` of every image in `index`.
+#[wasm_bindgen]
+pub fn texture_array_layers(buf: &[u8], index: &[usize]) -> Box<[u32]> {
+  let mut out = Vec::with_capacity(index.len() * 2);
+
+  for idx in index {
+    let (width, height) = pcx::get_dimensions(&buf[*idx..]);
+    out.push(width as u32);
+    out.push(height as u32);
+  }
 
   return out.into_boxed_slice();
 }
 
+/// Builds one texture array per distinct image size. An empty `mask_index`
+/// means no masks.
+///
+/// Layout: group count, then per group a header of width, height, layer count
+/// and byte length, the input position of every layer and the RGBA layers.
+#[wasm_bindgen]
+pub fn create_2d_texture_groups(buf: &[u8], index: &[usize], mask_index: &[usize], pixel_format: u8) -> Box<[u8]> {
+  let _timer = timer::Timer::new("create_2d_texture_groups");
+
+  let mask_index = if mask_index.is_empty() { None } else { Some(mask_index) };
+  let groups = pcx::pcx_texture_groups(&buf, &index, mask_index, texture::PixelFormat::from_bits(pixel_format));
+  let total_buf_length = groups.iter().fold(4, |r, g| r + 4 * 4 + 4 * g.layers.len() + g.data.len());
+
+  let mut out = vec![0u8; total_buf_length];
+  let mut out_ptr = 0usize;
+
+  write_uint32_le(&mut out[out_ptr..], groups.len() as u32); out_ptr += 4;
+
+  for g in groups {
+    write_uint32_le(&mut out[out_ptr..], g.width as u32); out_ptr += 4;
+    write_uint32_le(&mut out[out_ptr..], g.height as u32); out_ptr += 4;
+    write_uint32_le(&mut out[out_ptr..], g.layers.len() as u32); out_ptr += 4;
+    write_uint32_le(&mut out[out_ptr..], g.data.len() as u32); out_ptr += 4;
+
+    for l in g.layers.iter() {
+      write_uint32_le(&mut out[out_ptr..], *l as u32); out_ptr += 4;
+    }
+
+    out[out_ptr..out_ptr + g.data.len()].copy_from_slice(&g.data);
+    out_ptr += g.data.len();
+  }
+
+  return out.into_boxed_slice();
+}
 
 #[inline]
 fn write_uint32_le(buf: &mut [u8], val: u32) {
@@ -53,28 +160,53 @@ fn write_uint32_le(buf: &mut [u8], val: u32) {
   buf[3] = ((val & 0xFF000000) >> 24) as u8;
 }
 
+/// Parses every BMD of `bmd_buf` together with the shadow BMD that directly
+/// follows it where `has_shadow` is set.
+fn read_bmd_files<'a>(bmd_buf: &'a [u8], bmd_index: &[usize], has_shadow: &[u8]) -> Result<Vec<(bmd::BmdFile<'a>, Option<bmd::BmdFile<'a>>)>, JsValue> {
+  let mut bmds = Vec::with_capacity(bmd_index.len());
+
+  for i in 0..bmd_index.len() {
+    let (rest, bmd) = bmd::read_bmd_file(&bmd_buf[bmd_index[i]..])?;
+    let shadow = if has_shadow[i] > 0 { Some(bmd::BmdFile::parse(rest)?) } else { None };
+    bmds.push((bmd, shadow));
+  }
+
+  Ok(bmds)
+}
+
+/// `shadow_mode` merges shadows with `shadow_alpha` (0), writes them to a
+/// second layer after every frame (1) or drops them (2). `format`,
+/// `pixel_format`, `shadow_mode` and `shadow_alpha` are optional and default
+/// to RGBA8, straight sRGB, merged shadows and `DEFAULT_SHADOW_ALPHA`.
 #[wasm_bindgen]
-pub fn create_bmd_texture_array(bmd_buf: &[u8], palette_buf: &[u8], bmd_index: &[usize], bmd_frame_instance_count: &[usize], has_shadow: &[u8], palette_index: &[usize], frame_palette_index: &[usize]) -> Box<[u8]> {
+pub fn create_bmd_texture_array(bmd_buf: &[u8], palette_buf: &[u8], bmd_index: &[usize], bmd_frame_instance_count: &[usize], has_shadow: &[u8], palette_index: &[usize], frame_palette_index: &[usize], format: Option<u8>, pixel_format: Option<u8>, shadow_mode: Option<u8>, shadow_alpha: Option<u8>) -> Result<Box<[u8]>, JsValue> {
   let _timer = timer::Timer::new("create_bmd_texture_array");
 
+  let format = texture::TextureFormat::from_u8(format.unwrap_or(0)).ok_or("unknown texture format")?;
+  let pixel_format = texture::PixelFormat::from_bits(pixel_format.unwrap_or(0));
+  let shadow_mode = bmd::ShadowMode::from_u8(shadow_mode.unwrap_or(0), shadow_alpha.unwrap_or(bmd::DEFAULT_SHADOW_ALPHA)).ok_or("unknown shadow mode")?;
   let palettes = pcx::pcx_read_palette_array(palette_buf, palette_index);
-  let bmd_stats = bmd::bmd_stats(bmd_buf, has_shadow, bmd_index.len());
-  let total_buf_length = bmd_stats.iter().zip(bmd_frame_instance_count).fold(0, |r, (s, c)| r + 4 * 4 + c * (2 * 4 + s.encoded_length));
+
+  let has_shadow = if shadow_mode == bmd::ShadowMode::Drop { vec![0u8; has_shadow.len()] } else { has_shadow.to_vec() };
+  let bmds = read_bmd_files(bmd_buf, bmd_index, &has_shadow)?;
+  let layers = shadow_mode.layers();
+  let options = bmd::ReadOptions { format, pixel_format, shadow_mode };
+
+  let bmd_stats: Vec<bmd::BmdStats> = bmds.iter().map(|(b, s)| bmd::bmd_stats(b, s.as_ref(), format)).collect();
+  let total_buf_length = bmd_stats.iter().zip(bmd_frame_instance_count).fold(0, |r, (s, c)| r + 4 * 4 + c * (2 * 4 + layers * s.encoded_length));
 
   let mut images = vec![0u8; total_buf_length];
   let mut out_ptr = 0usize;
   let mut frame_ptr = 0;
 
-  for i in 0..bmd_index.len() {
+  for (i, (bmd, shadow)) in bmds.iter().enumerate() {
     let s = &bmd_stats[i];
 
-    // console::log_1(&format!("out_ptr was {}", out_ptr).into());
-
     // Write header
     write_uint32_le(&mut images[out_ptr..], bmd_frame_instance_count[i] as u32); out_ptr += 4;
     write_uint32_le(&mut images[out_ptr..], s.width as u32); out_ptr += 4;
     write_uint32_le(&mut images[out_ptr..], s.height as u32); out_ptr += 4;
-    write_uint32_le(&mut images[out_ptr..], (bmd_frame_instance_count[i] * s.encoded_length) as u32); out_ptr += 4;
+    write_uint32_le(&mut images[out_ptr..], (bmd_frame_instance_count[i] * layers * s.encoded_length) as u32); out_ptr += 4;
 
     // Write texture 2d image
     let frame_instance_count = bmd_frame_instance_count[i];
@@ -82,10 +214,879 @@ pub fn create_bmd_texture_array(bmd_buf: &[u8], palette_buf: &[u8], bmd_index: &
     let mut it = frame_palette_index[bmd_index.len() + frame_ptr..bmd_index.len() + frame_ptr + frame_instance_count * 2].chunks(2).map(|c| (&c[0], &c[1]));
     frame_ptr += frame_instance_count * 2;
 
-    out_ptr += bmd::read_bmd(s.width, s.height, bmd_frame_instance_count[i], has_shadow[i] > 0, &bmd_buf[bmd_index[i]..], &mut images[out_ptr..], &mut it, &palettes, false);
-    // console::log_1(&format!("out_ptr is {}", out_ptr).into());
-    // out_ptr += 2 * 4 * frame_instance_count + bmd_frame_instance_count[i] * s.encoded_length;
+    out_ptr += bmd::read_bmd(s, bmd, shadow.as_ref(), &mut images[out_ptr..], &mut it, &palettes, &options)?;
+  }
+
+  Ok(images.into_boxed_slice())
+}
+
+/// Like `create_bmd_texture_array`, but BMDs of the same frame size share
+/// one texture array in which identical frames are stored once. The output
+/// starts with the group count and the instance count, followed by four 32-bit
+/// values per group: layer count, width, height and byte length of its
+/// layers, and five per instance: its group, its layer, its shadow layer in
+/// the same group (0xFFFFFFFF unless shadows are separate) and the `dx`/`dy`
+/// of the layer's top left corner. The layers of every group follow.
+#[wasm_bindgen]
+pub fn create_bmd_texture_array_dedup(bmd_buf: &[u8], palette_buf: &[u8], bmd_index: &[usize], bmd_frame_instance_count: &[usize], has_shadow: &[u8], palette_index: &[usize], frame_palette_index: &[usize], format: Option<u8>, pixel_format: Option<u8>, shadow_mode: Option<u8>, shadow_alpha: Option<u8>) -> Result<Box<[u8]>, JsValue> {
+  let _timer = timer::Timer::new("create_bmd_texture_array_dedup");
+
+  let format = texture::TextureFormat::from_u8(format.unwrap_or(0)).ok_or("unknown texture format")?;
+  let pixel_format = texture::PixelFormat::from_bits(pixel_format.unwrap_or(0));
+  let shadow_mode = bmd::ShadowMode::from_u8(shadow_mode.unwrap_or(0), shadow_alpha.unwrap_or(bmd::DEFAULT_SHADOW_ALPHA)).ok_or("unknown shadow mode")?;
+  let palettes = pcx::pcx_read_palette_array(palette_buf, palette_index);
+
+  let has_shadow = if shadow_mode == bmd::ShadowMode::Drop { vec![0u8; has_shadow.len()] } else { has_shadow.to_vec() };
+  let bmds = read_bmd_files(bmd_buf, bmd_index, &has_shadow)?;
+
+  // Groups of BMDs with the same frame size, in order of first appearance.
+  let mut groups: Vec<texture::LayerSet> = Vec::new();
+  let mut instances = Vec::new();
+  let mut frame_ptr = bmd_index.len();
+
+  let add_layer = |frame: &bmd::ComposedFrame, layers: &mut texture::LayerSet| {
+    let mut slot = vec![0u8; 4 * layers.width() * layers.height()];
+    frame.copy_rect(0, 0, frame.width, frame.height, &mut slot, layers.width());
+    layers.insert(&slot) as u32
+  };
+
+  for ((bmd, shadow), &count) in bmds.iter().zip(bmd_frame_instance_count) {
+    let stat = bmd::bmd_stats(bmd, shadow.as_ref(), format);
+    let group = match groups.iter().position(|g| g.width() == stat.width && g.height() == stat.height) {
+      Some(g) => g,
+      None => {
+        groups.push(texture::LayerSet::new(stat.width, stat.height));
+        groups.len() - 1
+      }
+    };
+    let layers = &mut groups[group];
+
+    for c in frame_palette_index[frame_ptr..frame_ptr + 2 * count].chunks(2) {
+      let (fi, pi) = (c[0], c[1]);
+      let palette = palettes.get(pi).ok_or("create_bmd_texture_array_dedup: palette index out of range")?;
+
+      let (frame, mask) = match (shadow_mode, shadow) {
+        (bmd::ShadowMode::Merged(alpha), _) => (bmd::compose_frame(bmd, shadow.as_ref(), fi, palette, pixel_format, alpha)?, None),
+        (bmd::ShadowMode::Separate, Some(s)) => {
+          let (frame, mask) = bmd::compose_frame_separate(bmd, s, fi, palette, pixel_format)?;
+          (frame, Some(mask))
+        }
+        _ => (bmd::compose_frame(bmd, None, fi, palette, pixel_format, 0)?, None),
+      };
+
+      let layer = add_layer(&frame, layers);
+      let shadow_layer = mask.map_or(0xFFFFFFFF, |m| add_layer(&m, layers));
+
+      instances.push([group as u32, layer, shadow_layer, frame.x as u32, frame.y as u32]);
+    }
+    frame_ptr += 2 * count;
+  }
+
+  let data: Vec<Vec<u8>> = groups.iter().map(|g| g.encode(format)).collect();
+  let header_length = 2 * 4 + groups.len() * 4 * 4 + instances.len() * 5 * 4;
+  let mut out = vec![0u8; header_length];
+
+  let mut out_ptr = 0;
+  for v in [groups.len(), instances.len()].iter() {
+    write_uint32_le(&mut out[out_ptr..], *v as u32); out_ptr += 4;
+  }
+  for (g, d) in groups.iter().zip(data.iter()) {
+    for v in [g.len(), g.width(), g.height(), d.len()].iter() {
+      write_uint32_le(&mut out[out_ptr..], *v as u32); out_ptr += 4;
+    }
+  }
+  for v in instances.iter().flatten() {
+    write_uint32_le(&mut out[out_ptr..], *v); out_ptr += 4;
   }
+  data.iter().for_each(|d| out.extend_from_slice(d));
+
+  Ok(out.into_boxed_slice())
+}
+
+/// Packs the tightly cropped frame instances of many BMDs into
+/// `page_size` × `page_size` RGBA pages. Takes the same BMD and instance
+/// tables as `create_bmd_texture_array`. The output starts with page count,
+/// page size, page size and instance count, followed by nine 32-bit values per
+/// instance: page, x, y, width and height of its rectangle in texels, the
+/// offset of the cropped image from the anchor and the frame's original
+/// `dx`/`dy`. The pages follow. `shadow_mode` works as in
+/// `create_bmd_texture_array`. Separate shadows are packed as their own
+/// instances, each right after its sprite instance and empty for BMDs
+/// without a shadow, so sprite `i` has its shadow at `2 * i + 1`.
+#[wasm_bindgen]
+pub fn create_bmd_atlas(bmd_buf: &[u8], palette_buf: &[u8], bmd_index: &[usize], bmd_frame_instance_count: &[usize], has_shadow: &[u8], palette_index: &[usize], frame_palette_index: &[usize], page_size: usize, padding: usize, pixel_format: u8, shadow_mode: u8, shadow_alpha: u8) -> Result<Box<[u8]>, JsValue> {
+  let _timer = timer::Timer::new("create_bmd_atlas");
+
+  let pixel_format = texture::PixelFormat::from_bits(pixel_format);
+  let shadow_mode = bmd::ShadowMode::from_u8(shadow_mode, shadow_alpha).ok_or("unknown shadow mode")?;
+  let palettes = pcx::pcx_read_palette_array(palette_buf, palette_index);
+  let bmds = read_bmd_files(bmd_buf, bmd_index, has_shadow)?;
+
+  let mut instances = Vec::new();
+  let mut frame_ptr = bmd_index.len();
+
+  for ((bmd, shadow), &count) in bmds.iter().zip(bmd_frame_instance_count) {
+    for c in frame_palette_index[frame_ptr..frame_ptr + 2 * count].chunks(2) {
+      let (fi, pi) = (c[0], c[1]);
+      let palette = palettes.get(pi).ok_or("create_bmd_atlas: palette index out of range")?;
+      let (dx, dy) = bmd.frames.get(fi).map_or((0, 0), |f| (f.dx, f.dy));
+
+      let frame = match shadow_mode {
+        bmd::ShadowMode::Merged(alpha) => bmd::compose_frame(bmd, shadow.as_ref(), fi, palette, pixel_format, alpha)?,
+        _ => bmd::compose_frame(bmd, None, fi, palette, pixel_format, 0)?,
+      };
+      instances.push((frame.trim(), frame, dx, dy));
+
+      if shadow_mode == bmd::ShadowMode::Separate {
+        let (dx, dy) = shadow.as_ref().and_then(|s| s.frames.get(fi)).map_or((0, 0), |f| (f.dx, f.dy));
+        let frame = match shadow {
+          Some(s) => bmd::compose_frame(s, None, fi, palette, pixel_format, 0xFF)?,
+          None => bmd::ComposedFrame { x: 0, y: 0, width: 0, height: 0, data: Vec::new() },
+        };
+        instances.push((frame.trim(), frame, dx, dy));
+      }
+    }
+    frame_ptr += 2 * count;
+  }
+
+  let sizes: Vec<(usize, usize)> = instances.iter().map(|(t, _, _, _)| t.map_or((0, 0), |(_, _, w, h)| (w, h))).collect();
+  let (placements, page_count) = atlas::pack(&sizes, page_size, page_size, padding)?;
+
+  let header_length = 4 * 4 + instances.len() * 9 * 4;
+  let page_length = 4 * page_size * page_size;
+  let mut out = vec![0u8; header_length + page_count * page_length];
+
+  let mut out_ptr = 0;
+  for v in [page_count, page_size, page_size, instances.len()].iter() {
+    write_uint32_le(&mut out[out_ptr..], *v as u32); out_ptr += 4;
+  }
+
+  for ((trim, frame, dx, dy), p) in instances.iter().zip(placements.iter()) {
+    let (tx, ty, w, h) = trim.unwrap_or((0, 0, 0, 0));
+
+    for v in [p.page as u32, p.x as u32, p.y as u32, w as u32, h as u32, (frame.x + tx as i32) as u32, (frame.y + ty as i32) as u32, *dx as u32, *dy as u32].iter() {
+      write_uint32_le(&mut out[out_ptr..], *v); out_ptr += 4;
+    }
+
+    if w > 0 {
+      let page = header_length + p.page * page_length;
+      frame.copy_rect(tx, ty, w, h, &mut out[page + 4 * (p.y * page_size + p.x)..], page_size);
+    }
+  }
+
+  Ok(out.into_boxed_slice())
+}
+
+/// Values per frame returned by `bmd_frame_info`.
+pub const BMD_FRAME_INFO_LENGTH: usize = 10;
+
+/// Describes every frame of a BMD. With `has_shadow` set, the shadow BMD is
+/// expected directly after it. Every frame takes `BMD_FRAME_INFO_LENGTH`
+/// values: the frame type (1 normal, 2 shadow, 4 extended), dx, dy, width,
+/// rows, flags (1 empty, 2 has a shadow frame, 4 the shadow frame is empty)
+/// and the x, y, width and height of the union of the frame and its shadow
+/// frame relative to the anchor.
+#[wasm_bindgen]
+pub fn bmd_frame_info(bmd_buf: &[u8], has_shadow: bool) -> Result<Box<[i32]>, JsValue> {
+  let (bmd, shadow) = read_bmd_with_shadow(bmd_buf, has_shadow)?;
+  let mut out = Vec::with_capacity(bmd.frames.len() * BMD_FRAME_INFO_LENGTH);
+
+  for (i, f) in bmd.frames.iter().enumerate() {
+    let fs = shadow.as_ref().and_then(|s| s.frames.get(i).map(|fs| (s, fs)));
+    let b = bmd::frame_bounds(f, fs.map(|(_, fs)| fs));
+
+    let flags = bmd.is_frame_empty(f) as i32
+      | (fs.is_some() as i32) << 1
+      | (fs.is_none_or(|(s, fs)| s.is_frame_empty(fs)) as i32) << 2;
+
+    out.extend_from_slice(&[f.frame_type.id() as i32, f.dx, f.dy, f.width as i32, f.len as i32, flags, b.x, b.y, b.width as i32, b.height as i32]);
+  }
+
+  Ok(out.into_boxed_slice())
+}
+
+/// The landscapes of a `landscapes.cif` by name.
+#[wasm_bindgen]
+pub struct Landscapes {
+  landscapes: std::collections::HashMap<String, fromts::cif::definitions::GfxLandscape>,
+}
+
+#[wasm_bindgen]
+impl Landscapes {
+  #[wasm_bindgen(constructor)]
+  pub fn new(cif_buf: &[u8]) -> Result<Landscapes, JsValue> {
+    let cif = fromts::cif::read_cif_buf(cif_buf).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    Ok(Landscapes { landscapes: fromts::middlelayer::cultures_registry::landscapes_by_name(cif) })
+  }
+
+  /// Animation of the landscape `name`. `bmd_buf` must be the first file of
+  /// its `GfxBobLibs`.
+  pub fn animation(&self, name: &str, bmd_buf: &[u8], frame_duration: f32) -> Result<LandscapeAnimation, JsValue> {
+    let landscape = self.landscapes.get(name).ok_or("Landscapes: unknown landscape")?;
+    let bmd = bmd::BmdFile::parse(bmd_buf)?;
+
+    Ok(LandscapeAnimation { clip: fromts::middlelayer::cultures_registry::landscape_clip(landscape, &bmd, frame_duration)? })
+  }
+}
+
+/// Animation of a landscape object, see `GfxFrames`, `GfxStatic` and
+/// `GfxLoopAnimation`. Created by `Landscapes::animation`.
+#[wasm_bindgen]
+pub struct LandscapeAnimation {
+  clip: animation::AnimationClip,
+}
+
+#[wasm_bindgen]
+impl LandscapeAnimation {
+  pub fn duration(&self, level: u8) -> f32 {
+    self.clip.duration(level)
+  }
+
+  /// Frame index, `dx` and `dy` shown `time` milliseconds after the start, or
+  /// an empty array for unknown levels.
+  pub fn frame_at(&self, level: u8, time: f32) -> Box<[i32]> {
+    match self.clip.frame_at(level, time) {
+      Some(f) => vec![f.frame as i32, f.dx, f.dy].into_boxed_slice(),
+      None => Box::new([]),
+    }
+  }
+}
+
+/// Like `create_bmd_texture_array`, but without palettes: every frame is
+/// stored once as a palette index, an alpha and a shadow plane of
+/// `width` × `height` bytes each. Per BMD the output holds a 16-byte header
+/// (frame count, width, height, byte length), the `dx`/`dy` table and the
+/// planes.
+#[wasm_bindgen]
+pub fn create_bmd_indexed_array(bmd_buf: &[u8], bmd_index: &[usize], has_shadow: &[u8]) -> Result<Box<[u8]>, JsValue> {
+  let _timer = timer::Timer::new("create_bmd_indexed_array");
+
+  let bmds = read_bmd_files(bmd_buf, bmd_index, has_shadow)?;
+
+  let bmd_stats: Vec<bmd::BmdStats> = bmds.iter().map(|(b, s)| bmd::bmd_stats(b, s.as_ref(), texture::TextureFormat::Rgba8)).collect();
+  let total_buf_length = bmd_stats.iter().fold(0, |r, s| r + 4 * 4 + s.frames * (2 * 4 + 3 * s.width * s.height));
+
+  let mut images = vec![0u8; total_buf_length];
+  let mut out_ptr = 0usize;
+
+  for (s, (bmd, shadow)) in bmd_stats.iter().zip(bmds.iter()) {
+    write_uint32_le(&mut images[out_ptr..], s.frames as u32); out_ptr += 4;
+    write_uint32_le(&mut images[out_ptr..], s.width as u32); out_ptr += 4;
+    write_uint32_le(&mut images[out_ptr..], s.height as u32); out_ptr += 4;
+    write_uint32_le(&mut images[out_ptr..], (s.frames * 3 * s.width * s.height) as u32); out_ptr += 4;
+
+    out_ptr += bmd::read_bmd_indexed(s, bmd, shadow.as_ref(), &mut images[out_ptr..])?;
+  }
+
+  Ok(images.into_boxed_slice())
+}
+
+/// One 256 × 1 RGBA row per palette, for palette lookups in shaders.
+#[wasm_bindgen]
+pub fn create_palette_texture(palette_buf: &[u8], palette_index: &[usize]) -> Box<[u8]> {
+  let palettes = pcx::pcx_read_palette_array(palette_buf, palette_index);
+  let mut out = vec![0u8; palettes.len() * 256 * 4];
+
+  for (p, row) in palettes.iter().zip(out.chunks_mut(256 * 4)) {
+    for (c, px) in p.chunks(3).zip(row.chunks_mut(4)) {
+      px[..3].copy_from_slice(c);
+      px[3] = 0xFF;
+    }
+  }
+
+  return out.into_boxed_slice();
+}
+
+#[wasm_bindgen]
+pub fn pcx_to_png(buf: &[u8], mask: Option<Box<[u8]>>) -> Box<[u8]> {
+  let (width, height) = pcx::get_dimensions(buf);
+  let mut rgba = vec![0u8; 4 * width * height];
+  pcx::pcx_read(buf, &mut rgba, mask.as_deref());
+
+  return png::encode_rgba(width, height, &rgba).into_boxed_slice();
+}
+
+/// Swatch image of the palette stored at the end of a PCX file.
+#[wasm_bindgen]
+pub fn palette_to_png(palette_buf: &[u8]) -> Box<[u8]> {
+  let palette = pcx::pcx_read_palette_array(palette_buf, &[0]);
+  let (width, height, rgb) = pcx::palette_swatch(palette[0], 8);
+
+  return png::encode_rgb(width, height, &rgb).into_boxed_slice();
+}
+
+#[wasm_bindgen]
+pub fn bmd_frame_to_png(bmd_buf: &[u8], frame: usize, palette_buf: &[u8]) -> Result<Box<[u8]>, JsValue> {
+  let palette = pcx::pcx_read_palette_array(palette_buf, &[0]);
+  let decoded = bmd::BmdFile::parse(bmd_buf)?.decode_frame(frame, palette[0], texture::PixelFormat::STRAIGHT_SRGB)?;
+
+  Ok(png::encode_rgba(decoded.width, decoded.height, &decoded.data).into_boxed_slice())
+}
+
+/// Parses a BMD and, with `has_shadow` set, the shadow BMD directly after it.
+fn read_bmd_with_shadow(bmd_buf: &[u8], has_shadow: bool) -> Result<(bmd::BmdFile, Option<bmd::BmdFile>), JsValue> {
+  let (rest, bmd) = bmd::read_bmd_file(bmd_buf)?;
+  let shadow = if has_shadow { Some(bmd::BmdFile::parse(rest)?) } else { None };
+
+  Ok((bmd, shadow))
+}
+
+/// The extended palette at the end of the PCX file `palette_buf`.
+fn read_pcx_palette(palette_buf: &[u8]) -> Result<&[u8], JsValue> {
+  if palette_buf.len() < 769 {
+    return Err("PCX file too short for an extended palette.".into());
+  }
+
+  Ok(pcx::read_palette(&palette_buf[palette_buf.len() - 769..])?)
+}
+
+/// Animated PNG of the given frames (all if empty), `delay` in milliseconds.
+#[wasm_bindgen]
+pub fn bmd_to_apng(bmd_buf: &[u8], palette_buf: &[u8], has_shadow: bool, frames: &[usize], delay: u16) -> Result<Box<[u8]>, JsValue> {
+  let palette = read_pcx_palette(palette_buf)?;
+  let (bmd, shadow) = read_bmd_with_shadow(bmd_buf, has_shadow)?;
+
+  Ok(sprite_export::to_apng(&bmd, shadow.as_ref(), frames, palette, delay)?.into_boxed_slice())
+}
+
+/// Animated GIF of the given frames (all if empty), `delay` in hundredths of
+/// a second.
+#[wasm_bindgen]
+pub fn bmd_to_gif(bmd_buf: &[u8], palette_buf: &[u8], has_shadow: bool, frames: &[usize], delay: u16) -> Result<Box<[u8]>, JsValue> {
+  let palette = read_pcx_palette(palette_buf)?;
+  let (bmd, shadow) = read_bmd_with_shadow(bmd_buf, has_shadow)?;
+
+  Ok(sprite_export::to_gif(&bmd, shadow.as_ref(), frames, palette, delay)?.into_boxed_slice())
+}
+
+#[wasm_bindgen]
+pub struct SpriteSheet {
+  sheet: sprite_export::SpriteSheet,
+}
 
-  return images.into_boxed_slice();
+#[wasm_bindgen]
+impl SpriteSheet {
+  #[wasm_bindgen(getter)]
+  pub fn png(&self) -> Box<[u8]> {
+    self.sheet.png.clone().into_boxed_slice()
+  }
+
+  /// TexturePacker JSON (hash) descriptor.
+  #[wasm_bindgen(getter)]
+  pub fn json(&self) -> String {
+    self.sheet.json.clone()
+  }
+}
+
+#[wasm_bindgen]
+pub fn bmd_sprite_sheet(bmd_buf: &[u8], palette_buf: &[u8], has_shadow: bool, frames: &[usize], name: &str, padding: usize) -> Result<SpriteSheet, JsValue> {
+  let palette = pcx::pcx_read_palette_array(palette_buf, &[0]);
+  let (bmd, shadow) = read_bmd_with_shadow(bmd_buf, has_shadow)?;
+
+  Ok(SpriteSheet { sheet: sprite_export::sprite_sheet(&bmd, shadow.as_ref(), frames, palette[0], name, padding)? })
+}
+
+/// Exports the palette stored at the end of a PCX file as GIMP (0), Adobe
+/// ACT (1) or JASC (2) palette.
+#[wasm_bindgen]
+pub fn palette_export(palette_buf: &[u8], format: u8, name: &str) -> Result<Box<[u8]>, JsValue> {
+  let format = palette::PaletteFormat::from_u8(format).ok_or("unknown palette format")?;
+  let palette = pcx::pcx_read_palette_array(palette_buf, &[0]);
+
+  Ok(palette::export(palette[0], format, name).into_boxed_slice())
+}
+
+/// Reads a palette file into 768 RGB bytes.
+#[wasm_bindgen]
+pub fn palette_import(data: &[u8], format: u8) -> Result<Box<[u8]>, JsValue> {
+  let format = palette::PaletteFormat::from_u8(format).ok_or("unknown palette format")?;
+
+  Ok(palette::import(data, format)?.into_boxed_slice())
+}
+
+#[wasm_bindgen]
+pub fn pcx_replace_palette(pcx_buf: &[u8], palette: &[u8]) -> Result<Box<[u8]>, JsValue> {
+  Ok(pcx::replace_palette(pcx_buf, palette)?.into_boxed_slice())
+}
+
+fn quantize_options(dither: u8, transparent: Option<u8>, shadow: Option<u8>) -> Result<quantize::QuantizeOptions, JsValue> {
+  let dither = quantize::Dither::from_u8(dither).ok_or("unknown dither mode")?;
+
+  Ok(quantize::QuantizeOptions { dither, transparent, shadow })
+}
+
+/// Converts an RGBA image into a 256 colour PCX file using the palette stored
+/// at the end of the PCX file `palette_buf`. `dither` is none (0),
+/// Floyd–Steinberg (1) or ordered (2).
+#[wasm_bindgen]
+pub fn pcx_encode(rgba: &[u8], width: usize, height: usize, palette_buf: &[u8], dither: u8, transparent: Option<u8>, shadow: Option<u8>) -> Result<Box<[u8]>, JsValue> {
+  let palette = pcx::pcx_read_palette_array(palette_buf, &[0]);
+  let mut quantizer = quantize::Quantizer::new(palette[0], quantize_options(dither, transparent, shadow)?);
+  let frame = quantizer.quantize(rgba, width, height);
+
+  Ok(pcx::pcx_write(width, height, &frame.indices, palette[0])?.into_boxed_slice())
+}
+
+/// Encodes RGBA frames as a BMD file. `sizes` and `anchors` hold a
+/// width/height and a dx/dy pair per frame, the frames' pixels follow each
+/// other in `rgba`. Frames with translucent pixels become extended frames.
+/// Dark translucent pixels stay colour pixels, shadows go into their own BMD:
+/// with `shadow` set, every visible pixel is written to a shadow frame
+/// instead. The unknown header fields are copied from `template_buf`.
+#[wasm_bindgen]
+pub fn bmd_encode(template_buf: &[u8], rgba: &[u8], sizes: &[usize], anchors: &[i32], palette_buf: &[u8], dither: u8, shadow: bool) -> Result<Box<[u8]>, JsValue> {
+  let template = bmd::BmdFile::parse(template_buf)?;
+  if sizes.len() % 2 != 0 || sizes.len() != anchors.len() {
+    return Err("bmd_encode: sizes and anchors must hold a pair per frame".into());
+  }
+
+  let palette = pcx::pcx_read_palette_array(palette_buf, &[0]);
+  // Without a shadow index the quantizer keeps dark translucent pixels.
+  let mut quantizer = quantize::Quantizer::new(palette[0], quantize_options(dither, None, None)?);
+
+  let mut frames = Vec::with_capacity(sizes.len() / 2);
+  let mut ptr = 0;
+
+  for (size, anchor) in sizes.chunks(2).zip(anchors.chunks(2)) {
+    let (width, height) = (size[0], size[1]);
+    let pixels = rgba.get(ptr..ptr + 4 * width * height).ok_or("bmd_encode: rgba buffer too short")?;
+    ptr += 4 * width * height;
+
+    let (frame_type, image) = if shadow {
+      let mut image = bmd::IndexedFrame::new(width, height);
+      for (s, px) in image.shadow.iter_mut().zip(pixels.chunks(4)) {
+        *s = if px[3] != 0 { 0xFF } else { 0 };
+      }
+      (bmd::BmdFrameType::Shadow, image)
+    } else {
+      let image = quantizer.quantize(pixels, width, height);
+      let extended = image.alpha.iter().any(|&a| a != 0 && a != 0xFF);
+      (if extended { bmd::BmdFrameType::Extended } else { bmd::BmdFrameType::Normal }, image)
+    };
+
+    frames.push(bmd::BmdFrameInput { frame_type, dx: anchor[0], dy: anchor[1], image });
+  }
+
+  Ok(bmd::encode_bmd(&template.header, &frames)?.into_boxed_slice())
+}
+
+/// Average RGB colour of a `GfxPattern`, given its texture as RGBA and its
+/// `GfxCoordsA`/`GfxCoordsB`.
+#[wasm_bindgen]
+pub fn pattern_colour(rgba: &[u8], width: usize, height: usize, coords_a: &[u8], coords_b: &[u8]) -> Result<Box<[u8]>, JsValue> {
+  Ok(Box::new(minimap::pattern_colour(rgba, width, height, coords_a, coords_b)?))
+}
+
+/// A map (`map.dat`) loaded from its bytes. Layers hold one value per cell
+/// and are copied into typed arrays, sections missing from the file are
+/// `undefined`.
+#[wasm_bindgen]
+pub struct CulturesMap {
+  map: fromts::map::CulturesMapData,
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct MapSectionInfo {
+  tag: String,
+  pub unk1: u32,
+  pub unk2: u32,
+  pub unk3: u32,
+  pub unk4: u32,
+  pub unk5: u32,
+  /// Length of the section data in bytes.
+  pub size: u32,
+  /// Whether `CulturesMap` exposes the content.
+  pub decoded: bool,
+}
+
+#[wasm_bindgen]
+impl MapSectionInfo {
+  #[wasm_bindgen(getter)]
+  pub fn tag(&self) -> String {
+    self.tag.clone()
+  }
+}
+
+impl CulturesMap {
+  /// Evaluates `f` for every cell at map resolution.
+  fn cells<T>(&self, f: impl Fn(u32, u32) -> T) -> Box<[T]> {
+    let (w, h) = (self.map.width(), self.map.height());
+    (0..h).flat_map(|y| (0..w).map(move |x| (x, y))).map(|(x, y)| f(x, y)).collect()
+  }
+}
+
+fn names(names: Option<&[String]>) -> Option<Box<[JsValue]>> {
+  names.map(|n| n.iter().map(|s| JsValue::from_str(s)).collect())
+}
+
+#[wasm_bindgen]
+impl CulturesMap {
+  #[wasm_bindgen(constructor)]
+  pub fn new(buf: &[u8]) -> Result<CulturesMap, JsValue> {
+    let map = fromts::map::parse_map_data(buf).map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+    Ok(CulturesMap { map })
+  }
+
+  /// Writes the map back to `map.dat` bytes.
+  pub fn save(&self) -> Box<[u8]> {
+    fromts::map::write_map_data(&self.map).into_boxed_slice()
+  }
+
+  /// Every section of the file in order.
+  pub fn sections(&self) -> Vec<MapSectionInfo> {
+    self.map.sections().iter().map(|s| {
+      let unknown = s.unknown();
+      MapSectionInfo {
+        tag: s.tag(),
+        unk1: unknown[0], unk2: unknown[1], unk3: unknown[2], unk4: unknown[3], unk5: unknown[4],
+        size: s.data().len() as u32,
+        decoded: s.is_decoded(),
+      }
+    }).collect()
+  }
+
+  /// Raw bytes of section `index`, without prefix and header.
+  pub fn section_data(&self, index: usize) -> Option<Box<[u8]>> {
+    self.map.sections().get(index).map(|s| s.data().into())
+  }
+
+  /// The 0x20 bytes in front of the header of section `index`.
+  pub fn section_prefix(&self, index: usize) -> Option<Box<[u8]>> {
+    self.map.sections().get(index).map(|s| s.prefix().into())
+  }
+
+  /// Names of the generic layers present in the map.
+  pub fn layer_names(&self) -> Box<[JsValue]> {
+    self.map.layers().iter().map(|l| JsValue::from_str(l.name)).collect()
+  }
+
+  /// Width and height of a generic layer.
+  pub fn layer_size(&self, name: &str) -> Option<Box<[u32]>> {
+    self.map.layer(name).map(|l| vec![l.width, l.height].into_boxed_slice())
+  }
+
+  /// A generic layer with 8-bit values, `undefined` for 16-bit layers.
+  pub fn layer_u8(&self, name: &str) -> Option<Box<[u8]>> {
+    match &self.map.layer(name)?.data {
+      fromts::map::layers::LayerData::U8(d) => Some(d.clone()),
+      _ => None,
+    }
+  }
+
+  /// A generic layer with 16-bit values, `undefined` for 8-bit layers.
+  pub fn layer_u16(&self, name: &str) -> Option<Box<[u16]>> {
+    match &self.map.layer(name)?.data {
+      fromts::map::layers::LayerData::U16(d) => Some(d.clone()),
+      _ => None,
+    }
+  }
+
+  /// 1 for every walkable cell, from `bwml`. The meaning of the layer is a
+  /// guess.
+  pub fn walkable(&self) -> Option<Box<[u8]>> {
+    let w = self.map.walkability()?;
+    Some(self.cells(|x, y| w.is_walkable(x, y) as u8))
+  }
+
+  /// Owning player of every cell, 0 for none, from `plml`. The meaning of
+  /// the layer is a guess.
+  pub fn owners(&self) -> Option<Box<[u16]>> {
+    let o = self.map.ownership()?;
+    Some(self.cells(|x, y| o.owner(x, y).unwrap_or(0)))
+  }
+
+  /// 1 for every water cell, from `wtml`. The meaning of the layer is a
+  /// guess.
+  pub fn water(&self) -> Option<Box<[u8]>> {
+    let w = self.map.water()?;
+    Some(self.cells(|x, y| w.is_water(x, y) as u8))
+  }
+
+  /// Resource of every cell, 0 for none, from `rpml`. The meaning of the
+  /// layer is a guess.
+  pub fn resources(&self) -> Option<Box<[u16]>> {
+    let r = self.map.resources()?;
+    Some(self.cells(|x, y| r.resource(x, y).unwrap_or(0)))
+  }
+
+  /// One line of statistics per decoded layer, listing the `top` most
+  /// common values.
+  pub fn stats_report(&self, top: usize) -> String {
+    fromts::map::stats::render_report(&fromts::map::stats::map_stats(&self.map), top)
+  }
+
+  /// PNG of a decoded layer with a distinct colour per value.
+  pub fn false_colour_png(&self, name: &str) -> Option<Box<[u8]>> {
+    let layer = fromts::map::stats::map_layer(&self.map, name)?;
+
+    Some(fromts::map::stats::render_false_colour(&layer).into_boxed_slice())
+  }
+
+  /// RGBA minimap of `width` × `height` pixels. `tile_colours` holds an RGB
+  /// triple per entry of `tiles_index`, `landscape_colours` an RGBA quad per
+  /// entry of `landscape_index`, alpha being the opacity of the marker.
+  pub fn minimap(&self, tile_colours: &[u8], landscape_colours: &[u8], width: usize, height: usize) -> Result<Box<[u8]>, JsValue> {
+    let src = minimap::MinimapSource::from_map(&self.map)?;
+    let tiles: Vec<[u8; 3]> = tile_colours.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect();
+    let landscapes: Vec<[u8; 4]> = landscape_colours.chunks_exact(4).map(|c| [c[0], c[1], c[2], c[3]]).collect();
+
+    Ok(minimap::render(&src, &tiles, &landscapes, width, height)?.into_boxed_slice())
+  }
+
+  #[wasm_bindgen(getter)]
+  pub fn width(&self) -> u32 { self.map.width() }
+  #[wasm_bindgen(getter)]
+  pub fn height(&self) -> u32 { self.map.height() }
+
+  #[wasm_bindgen(getter)]
+  pub fn elevation(&self) -> Box<[u8]> { self.map.elevation().into() }
+  #[wasm_bindgen(getter)]
+  pub fn lighting(&self) -> Option<Box<[u8]>> { self.map.lighting().map(Into::into) }
+
+  #[wasm_bindgen(getter)]
+  pub fn tiles_index(&self) -> Option<Box<[JsValue]>> { names(self.map.tiles_index()) }
+  #[wasm_bindgen(getter)]
+  pub fn tiles_a(&self) -> Option<Box<[u16]>> { self.map.tiles_a().map(Into::into) }
+  #[wasm_bindgen(getter)]
+  pub fn tiles_b(&self) -> Option<Box<[u16]>> { self.map.tiles_b().map(Into::into) }
+
+  #[wasm_bindgen(getter)]
+  pub fn transitions_index(&self) -> Option<Box<[JsValue]>> { names(self.map.transitions_index()) }
+  #[wasm_bindgen(getter)]
+  pub fn trans_a1(&self) -> Option<Box<[u8]>> { self.map.trans_a1().map(Into::into) }
+  #[wasm_bindgen(getter)]
+  pub fn trans_b1(&self) -> Option<Box<[u8]>> { self.map.trans_b1().map(Into::into) }
+  #[wasm_bindgen(getter)]
+  pub fn trans_a2(&self) -> Option<Box<[u8]>> { self.map.trans_a2().map(Into::into) }
+  #[wasm_bindgen(getter)]
+  pub fn trans_b2(&self) -> Option<Box<[u8]>> { self.map.trans_b2().map(Into::into) }
+
+  #[wasm_bindgen(getter)]
+  pub fn landscape_index(&self) -> Option<Box<[JsValue]>> { names(self.map.landscape_index()) }
+  #[wasm_bindgen(getter)]
+  pub fn landscape_levels(&self) -> Option<Box<[u8]>> { self.map.landscape_levels().map(Into::into) }
+  #[wasm_bindgen(getter)]
+  pub fn landscape_types(&self) -> Option<Box<[u8]>> { self.map.landscape_types().map(Into::into) }
+  #[wasm_bindgen(getter)]
+  pub fn landscape_job_types(&self) -> Option<Box<[u8]>> { self.map.landscape_job_types().map(Into::into) }
+}
+
+/// Layer statistics summed over several maps.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct MapStats {
+  stats: Vec<fromts::map::stats::LayerStats>,
+}
+
+#[wasm_bindgen]
+impl MapStats {
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> MapStats {
+    MapStats::default()
+  }
+
+  pub fn add(&mut self, map: &CulturesMap) {
+    fromts::map::stats::merge_stats(&mut self.stats, fromts::map::stats::map_stats(&map.map));
+  }
+
+  /// Same format as `CulturesMap::stats_report`.
+  pub fn report(&self, top: usize) -> String {
+    fromts::map::stats::render_report(&self.stats, top)
+  }
+}
+
+/// Graphics of a landscape type kept by `MapRenderer`.
+struct LandscapeGraphics {
+  frames: std::collections::HashMap<usize, bmd::ComposedFrame>,
+  clip: animation::AnimationClip,
+}
+
+/// Software renderer for maps. Holds the textures and landscape graphics
+/// resolved against the names of one map.
+#[wasm_bindgen]
+pub struct MapRenderer {
+  layer_width: usize,
+  layer_height: usize,
+  patterns: Vec<u8>,
+  pattern_uvs: Vec<render::PatternUv>,
+  transitions: Vec<u8>,
+  transition_uvs: Vec<render::TransitionUv>,
+  landscapes: Vec<Option<LandscapeGraphics>>,
+}
+
+fn coords(c: &[u8]) -> [u8; 6] {
+  [c[0], c[1], c[2], c[3], c[4], c[5]]
+}
+
+#[wasm_bindgen]
+impl MapRenderer {
+  /// `patterns` are the texture layers from `create_2d_texture`. Per entry of
+  /// `tiles_index`, `pattern_layers` holds the layer and `pattern_coords` the
+  /// `GfxCoordsA` and `GfxCoordsB`, 12 bytes.
+  #[wasm_bindgen(constructor)]
+  pub fn new(layer_width: usize, layer_height: usize, patterns: &[u8], pattern_layers: &[u32], pattern_coords: &[u8]) -> Result<MapRenderer, JsValue> {
+    if pattern_coords.len() != 12 * pattern_layers.len() {
+      return Err("MapRenderer: expected 12 coordinates per pattern.".into());
+    }
+
+    let pattern_uvs = pattern_layers.iter().zip(pattern_coords.chunks(12))
+      .map(|(&layer, c)| render::PatternUv { layer: layer as usize, coords_a: coords(&c[..6]), coords_b: coords(&c[6..]) })
+      .collect();
+
+    Ok(MapRenderer {
+      layer_width,
+      layer_height,
+      patterns: patterns.to_vec(),
+      pattern_uvs,
+      transitions: Vec::new(),
+      transition_uvs: Vec::new(),
+      landscapes: Vec::new(),
+    })
+  }
+
+  /// `textures` are the layers from `create_2d_texture_masked`. Per entry of
+  /// `transitions_index`, `layers` holds the layer and `variant_counts` the
+  /// number of `GfxCoordsA`/`GfxCoordsB` pairs, which follow each other in
+  /// `coords`, 12 bytes each.
+  pub fn set_transitions(&mut self, textures: &[u8], layers: &[u32], variant_counts: &[u32], coords: &[u8]) -> Result<(), JsValue> {
+    let mut pairs = coords.chunks_exact(12);
+    let mut uvs = Vec::with_capacity(layers.len());
+
+    for (&layer, &count) in layers.iter().zip(variant_counts.iter()) {
+      let variants = (0..count)
+        .map(|_| pairs.next().map(|c| (self::coords(&c[..6]), self::coords(&c[6..]))))
+        .collect::<Option<Vec<_>>>()
+        .ok_or("MapRenderer: transition coordinates too short.")?;
+
+      uvs.push(render::TransitionUv { layer: layer as usize, variants });
+    }
+
+    self.transitions = textures.to_vec();
+    self.transition_uvs = uvs;
+
+    Ok(())
+  }
+
+  /// Graphics of entry `index` of `landscape_index`. With `has_shadow` set,
+  /// the shadow BMD directly follows the BMD in `bmd_buf`.
+  pub fn set_landscape(&mut self, index: usize, bmd_buf: &[u8], has_shadow: bool, palette_buf: &[u8], animation: &LandscapeAnimation) -> Result<(), JsValue> {
+    let (bmd, shadow) = read_bmd_with_shadow(bmd_buf, has_shadow)?;
+    let palette = read_pcx_palette(palette_buf)?;
+    let frames = render::compose_clip(&bmd, shadow.as_ref(), palette, &animation.clip)?;
+
+    if self.landscapes.len() <= index {
+      self.landscapes.resize_with(index + 1, || None);
+    }
+    self.landscapes[index] = Some(LandscapeGraphics { frames, clip: animation.clip.clone() });
+
+    Ok(())
+  }
+
+  /// PNG of the `width` × `height` cells at `x`/`y`, `scale` times the
+  /// texture resolution.
+  pub fn render(&self, map: &CulturesMap, x: usize, y: usize, width: usize, height: usize, scale: f32) -> Result<Box<[u8]>, JsValue> {
+    let _timer = timer::Timer::new("render_map");
+
+    let sprites: Vec<Option<render::Sprite>> = self.landscapes.iter()
+      .map(|l| l.as_ref().map(|l| render::Sprite { frames: &l.frames, clip: &l.clip }))
+      .collect();
+
+    let assets = render::Assets {
+      patterns: render::TextureArray { width: self.layer_width, height: self.layer_height, data: &self.patterns },
+      pattern_uvs: &self.pattern_uvs,
+      transitions: render::TextureArray { width: self.layer_width, height: self.layer_height, data: &self.transitions },
+      transition_uvs: &self.transition_uvs,
+      sprites: &sprites,
+    };
+    let scene = render::Scene::from_map(&map.map)?;
+    let region = render::Region { x, y, width, height };
+
+    Ok(render::render_png(&scene, &assets, region, scale)?.into_boxed_slice())
+  }
+}
+
+/// A* paths over a map for unit movement.
+#[wasm_bindgen]
+pub struct PathFinder {
+  walk: pathfinding::WalkGrid,
+  elevation: Vec<u8>,
+  costs: pathfinding::PathCosts,
+}
+
+#[wasm_bindgen]
+impl PathFinder {
+  /// `pattern_types` holds the `LogicType` per entry of `tiles_index`,
+  /// patterns whose type is in `blocking_types` cannot be walked on.
+  /// `block_areas` holds the `LogicWalkBlockArea` per entry of
+  /// `landscape_index`, four values each. Landscapes without a block area
+  /// start their entry with -128.
+  #[wasm_bindgen(constructor)]
+  pub fn new(map: &CulturesMap, pattern_types: &[u8], blocking_types: &[u8], block_areas: &[i8]) -> Result<PathFinder, JsValue> {
+    let map = &map.map;
+    let blocks = pathfinding::pattern_blocks(pattern_types, blocking_types);
+    let areas = pathfinding::block_areas(block_areas)?;
+    let walk = pathfinding::walk_grid(map, &blocks, &areas)?;
+
+    Ok(PathFinder { walk, elevation: map.elevation().to_vec(), costs: pathfinding::PathCosts::default() })
+  }
+
+  /// Cost of a flat step and the extra cost per elevation unit up and down.
+  /// Steps climbing more than `max_climb` are not taken.
+  pub fn set_costs(&mut self, step: f32, uphill: f32, downhill: f32, max_climb: u8) -> Result<(), JsValue> {
+    if [step, uphill, downhill].iter().any(|c| c.is_nan() || *c < 0.0) {
+      return Err("PathFinder: costs must not be negative.".into());
+    }
+    if step == 0.0 {
+      return Err("PathFinder: step must be positive.".into());
+    }
+
+    self.costs = pathfinding::PathCosts { step, uphill, downhill, max_climb };
+    Ok(())
+  }
+
+  pub fn is_walkable(&self, x: i32, y: i32) -> bool {
+    self.walk.is_walkable(grid::Cell::new(x, y))
+  }
+
+  /// Blocks or frees a cell, e.g. for buildings placed while playing.
+  pub fn set_walkable(&mut self, x: i32, y: i32, walkable: bool) {
+    self.walk.set_walkable(grid::Cell::new(x, y), walkable);
+  }
+
+  /// 1 for every walkable cell, 0 for the others.
+  pub fn walkable(&self) -> Box<[u8]> {
+    self.walk.grid.cells().map(|c| self.walk.is_walkable(c) as u8).collect()
+  }
+
+  /// x/y pairs of the cells from `from` to `to`, both included, or
+  /// `undefined` if `to` cannot be reached.
+  pub fn find_path(&self, from_x: i32, from_y: i32, to_x: i32, to_y: i32) -> Option<Box<[i32]>> {
+    let from = grid::Cell::new(from_x, from_y);
+    let to = grid::Cell::new(to_x, to_y);
+    let path = pathfinding::find_path(&self.walk, &self.elevation, &self.costs, from, to)?;
+
+    Some(path.iter().flat_map(|c| [c.x, c.y]).collect())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_create_palette_texture() {
+    let grey: Vec<u8> = (0..768).map(|i| (i / 3) as u8).collect();
+    let red: Vec<u8> = (0..768).map(|i| if i % 3 == 0 { (i / 3) as u8 } else { 0 }).collect();
+
+    let mut buf = pcx::pcx_write(1, 1, &[0], &grey).unwrap();
+    let index = [0, buf.len()];
+    buf.extend(pcx::pcx_write(2, 1, &[0, 0], &red).unwrap());
+
+    let texture = create_palette_texture(&buf, &index);
+    assert_eq!(texture.len(), 2 * 256 * 4);
+    assert_eq!(&texture[4 * 7..4 * 8], &[7, 7, 7, 0xFF]);
+    assert_eq!(&texture[1024 + 4 * 200..1024 + 4 * 201], &[200, 0, 0, 0xFF]);
+  }
 }
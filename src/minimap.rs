@@ -0,0 +1,230 @@
+//! Renders a map into a small RGBA overview from per-pattern colours, shaded
+//! by the terrain and with the landscape objects marked on top.
+
+use crate::fromts::map::CulturesMapData;
+use crate::grid::{Cell, Grid};
+
+/// The map layers the minimap is drawn from. Every layer holds one value per
+/// cell.
+pub struct MinimapSource<'a> {
+  pub width: usize,
+  pub height: usize,
+  pub elevation: &'a [u8],
+  pub lighting: Option<&'a [u8]>,
+  pub tiles_a: &'a [u16],
+  pub tiles_b: &'a [u16],
+  /// Index into the landscape names plus one, 0 for none.
+  pub landscape_types: Option<&'a [u8]>,
+}
+
+impl<'a> MinimapSource<'a> {
+  /// Takes the layers from a loaded map. Landscapes are left out when the
+  /// layer does not hold one value per cell.
+  pub fn from_map(map: &'a CulturesMapData) -> Result<MinimapSource<'a>, &'static str> {
+    let (width, height) = (map.width() as usize, map.height() as usize);
+
+    Ok(MinimapSource {
+      width,
+      height,
+      elevation: map.elevation(),
+      lighting: map.lighting(),
+      tiles_a: map.tiles_a().ok_or("render_minimap: the map has no tiles.")?,
+      tiles_b: map.tiles_b().ok_or("render_minimap: the map has no tiles.")?,
+      landscape_types: map.landscape_types().filter(|l| l.len() == width * height),
+    })
+  }
+}
+
+/// How much a step in elevation towards the lower right darkens a cell.
+const SLOPE_SHADE: f32 = 1.0 / 48.0;
+
+/// Brightness of a cell from the `lighting` layer, 128 is about neutral.
+pub fn lighting_factor(lighting: u8) -> f32 {
+  0.5 + lighting as f32 / 255.0
+}
+
+/// Average colour of the A and B triangles of a pattern. The coordinates are
+/// `GfxCoordsA`/`GfxCoordsB`: three x/y corners in the texture.
+pub fn pattern_colour(rgba: &[u8], width: usize, height: usize, coords_a: &[u8], coords_b: &[u8]) -> Result<[u8; 3], &'static str> {
+  if rgba.len() < 4 * width * height {
+    return Err("pattern_colour: texture is smaller than width * height.");
+  }
+
+  let mut sum = [0u64; 3];
+  let mut count = 0u64;
+
+  for coords in [coords_a, coords_b].iter().filter(|c| c.len() >= 6) {
+    let p: Vec<(f32, f32)> = coords[..6].chunks(2).map(|c| (c[0] as f32, c[1] as f32)).collect();
+    let x0 = coords[..6].iter().step_by(2).min().copied().unwrap() as usize;
+    let x1 = coords[..6].iter().step_by(2).max().copied().unwrap() as usize;
+    let y0 = coords[1..6].iter().step_by(2).min().copied().unwrap() as usize;
+    let y1 = coords[1..6].iter().step_by(2).max().copied().unwrap() as usize;
+
+    for y in y0..=y1.min(height.saturating_sub(1)) {
+      for x in x0..=x1.min(width.saturating_sub(1)) {
+        if !inside(&p, x as f32, y as f32) {
+          continue;
+        }
+
+        let px = &rgba[4 * (y * width + x)..4 * (y * width + x) + 4];
+        if px[3] == 0 {
+          continue;
+        }
+        for c in 0..3 {
+          sum[c] += px[c] as u64;
+        }
+        count += 1;
+      }
+    }
+  }
+
+  if count == 0 {
+    return Ok([0, 0, 0]);
+  }
+
+  Ok([(sum[0] / count) as u8, (sum[1] / count) as u8, (sum[2] / count) as u8])
+}
+
+/// Point in triangle test that includes the edges.
+fn inside(p: &[(f32, f32)], x: f32, y: f32) -> bool {
+  let edge = |a: (f32, f32), b: (f32, f32)| (b.0 - a.0) * (y - a.1) - (b.1 - a.1) * (x - a.0);
+  let (d0, d1, d2) = (edge(p[0], p[1]), edge(p[1], p[2]), edge(p[2], p[0]));
+
+  let negative = d0 < 0.0 || d1 < 0.0 || d2 < 0.0;
+  let positive = d0 > 0.0 || d1 > 0.0 || d2 > 0.0;
+
+  !(negative && positive)
+}
+
+/// Colour of a single cell before scaling.
+fn cell_colour(src: &MinimapSource, grid: &Grid, cell: Cell, tile_colours: &[[u8; 3]], landscape_colours: &[[u8; 4]]) -> [f32; 3] {
+  let i = grid.index(cell).unwrap();
+  let tile = |t: u16| tile_colours.get(t as usize).copied().unwrap_or([0, 0, 0]);
+  let (a, b) = (tile(src.tiles_a[i]), tile(src.tiles_b[i]));
+
+  // Light falls in from the upper left, so slopes rising towards the lower
+  // right are darker.
+  let below = grid.index(cell.below_right()).unwrap_or(i);
+  let mut shade = 1.0 - (src.elevation[below] as f32 - src.elevation[i] as f32) * SLOPE_SHADE;
+  if let Some(lighting) = src.lighting {
+    shade *= lighting_factor(lighting[i]);
+  }
+  let shade = shade.clamp(0.0, 2.0);
+
+  let mut colour = [0f32; 3];
+  for c in 0..3 {
+    colour[c] = (a[c] as f32 + b[c] as f32) / 2.0 * shade;
+  }
+
+  let object = src.landscape_types.and_then(|l| l[i].checked_sub(1)).and_then(|t| landscape_colours.get(t as usize));
+  if let Some(m) = object {
+    let alpha = m[3] as f32 / 255.0;
+    for c in 0..3 {
+      colour[c] = colour[c] * (1.0 - alpha) + m[c] as f32 * alpha;
+    }
+  }
+
+  colour
+}
+
+/// Renders a `width` × `height` RGBA minimap. Every output pixel averages the
+/// cells it covers, so the map can be scaled down as well as up.
+/// `tile_colours` holds a colour per pattern in the tiles index and
+/// `landscape_colours` a colour and opacity per landscape name.
+pub fn render(src: &MinimapSource, tile_colours: &[[u8; 3]], landscape_colours: &[[u8; 4]], width: usize, height: usize) -> Result<Vec<u8>, &'static str> {
+  let cells = src.width * src.height;
+  if cells == 0 || src.elevation.len() != cells || src.tiles_a.len() != cells || src.tiles_b.len() != cells {
+    return Err("render_minimap: layers do not match the map size.");
+  }
+  if src.lighting.is_some_and(|l| l.len() != cells) || src.landscape_types.is_some_and(|l| l.len() != cells) {
+    return Err("render_minimap: layers do not match the map size.");
+  }
+
+  let grid = Grid::new(src.width, src.height);
+  let colours: Vec<[f32; 3]> = grid.cells().map(|c| cell_colour(src, &grid, c, tile_colours, landscape_colours)).collect();
+  let mut out = vec![0u8; 4 * width * height];
+
+  for py in 0..height {
+    let y0 = py * src.height / height;
+    let y1 = ((py + 1) * src.height / height).max(y0 + 1);
+
+    for px in 0..width {
+      let x0 = px * src.width / width;
+      let x1 = ((px + 1) * src.width / width).max(x0 + 1);
+
+      let mut sum = [0f32; 3];
+      for y in y0..y1 {
+        for x in x0..x1 {
+          let c = &colours[y * src.width + x];
+          sum[0] += c[0];
+          sum[1] += c[1];
+          sum[2] += c[2];
+        }
+      }
+
+      let n = ((y1 - y0) * (x1 - x0)) as f32;
+      let o = 4 * (py * width + px);
+      for c in 0..3 {
+        out[o + c] = (sum[c] / n).round().clamp(0.0, 255.0) as u8;
+      }
+      out[o + 3] = 0xFF;
+    }
+  }
+
+  Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_pattern_colour() {
+    // Left half red, right half blue.
+    let rgba: Vec<u8> = (0..16).flat_map(|i| if i % 4 < 2 { vec![255, 0, 0, 255] } else { vec![0, 0, 255, 255] }).collect();
+
+    assert_eq!(pattern_colour(&rgba, 4, 4, &[0, 0, 1, 3, 0, 3], &[]), Ok([255, 0, 0]));
+    assert_eq!(pattern_colour(&rgba, 4, 4, &[0, 0, 1, 3, 0, 3], &[2, 0, 3, 0, 3, 3]), Ok([127, 0, 127]));
+    assert!(pattern_colour(&rgba, 4, 5, &[0, 0, 1, 3, 0, 3], &[]).is_err());
+  }
+
+  #[test]
+  fn test_render_scales_and_marks() {
+    let elevation = vec![0u8; 16];
+    let tiles_a = [0u16, 0, 1, 1].repeat(4);
+    let tiles_b = tiles_a.clone();
+    let mut landscapes = vec![0u8; 16];
+    landscapes[15] = 1;
+
+    let src = MinimapSource {
+      width: 4, height: 4, elevation: &elevation, lighting: None,
+      tiles_a: &tiles_a, tiles_b: &tiles_b, landscape_types: Some(&landscapes),
+    };
+    let tiles = [[200, 0, 0], [0, 200, 0]];
+    let objects = [[0, 0, 255, 255]];
+
+    let small = render(&src, &tiles, &objects, 2, 2).unwrap();
+    assert_eq!(&small[..4], &[200, 0, 0, 255]);
+    // A quarter of the lower right pixel is covered by the object.
+    assert_eq!(&small[12..16], &[0, 150, 64, 255]);
+
+    let large = render(&src, &tiles, &objects, 8, 8).unwrap();
+    assert_eq!(&large[4 * 63..], &[0, 0, 255, 255]);
+
+    assert!(render(&src, &tiles, &objects, 0, 0).unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_render_shades_slopes() {
+    // The lower right neighbour of (0, 0) is (0, 1), one full step up.
+    let elevation = vec![0u8, 0, 48, 0];
+    let tiles = vec![0u16; 4];
+
+    let src = MinimapSource { width: 2, height: 2, elevation: &elevation, lighting: None, tiles_a: &tiles, tiles_b: &tiles, landscape_types: None };
+    let out = render(&src, &[[100, 100, 100]], &[], 2, 2).unwrap();
+
+    assert_eq!(out[0], 0);
+    assert_eq!(out[4], 100);
+    assert_eq!(out[8], 100);
+  }
+}
@@ -0,0 +1,273 @@
+use std::cmp;
+use std::f32::consts::PI;
+
+/// Radius, in destination texels, of the Kaiser-windowed sinc kernel.
+const KAISER_RADIUS: f32 = 3.0;
+const KAISER_BETA: f32 = 4.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MipFilter {
+  Box,
+  Kaiser,
+}
+
+impl MipFilter {
+  pub fn from_u8(v: u8) -> Option<MipFilter> {
+    match v {
+      0 => Some(MipFilter::Box),
+      1 => Some(MipFilter::Kaiser),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct MipLevel {
+  pub width: usize,
+  pub height: usize,
+  /// Byte offset of the level in the chain, covering all layers.
+  pub offset: usize,
+  pub length: usize,
+}
+
+/// Number of levels in a full chain down to 1×1.
+pub fn mip_level_count(width: usize, height: usize) -> usize {
+  let mut size = cmp::max(width, height);
+  let mut levels = 1;
+
+  while size > 1 {
+    size /= 2;
+    levels += 1;
+  }
+
+  return levels;
+}
+
+/// Builds a mip chain for a texture array of `layers` RGBA layers. The result
+/// holds every level one after another, each level containing all layers, so
+/// it can be uploaded level by level. `levels == 0` builds the full chain.
+///
+/// With `alpha_aware` colours are weighted by their alpha while filtering, so
+/// fully transparent texels do not bleed into the visible ones.
+pub fn mip_chain(data: &[u8], width: usize, height: usize, layers: usize, levels: usize, filter: MipFilter, alpha_aware: bool) -> (Vec<u8>, Vec<MipLevel>) {
+  let max_levels = mip_level_count(width, height);
+  let levels = if levels == 0 { max_levels } else { cmp::min(levels, max_levels) };
+
+  let mut table = Vec::with_capacity(levels);
+  let (mut w, mut h) = (width, height);
+  let mut total = 0;
+
+  for _ in 0..levels {
+    let length = w * h * 4 * layers;
+    table.push(MipLevel { width: w, height: h, offset: total, length });
+    total += length;
+    w = cmp::max(1, w / 2);
+    h = cmp::max(1, h / 2);
+  }
+
+  let mut out = vec![0u8; total];
+  out[..table[0].length].copy_from_slice(&data[..table[0].length]);
+
+  let layer_length = width * height * 4;
+
+  for l in 0..layers {
+    let mut current = to_float(&data[l * layer_length..(l + 1) * layer_length], alpha_aware);
+
+    for level in 1..levels {
+      let src = &table[level - 1];
+      let dst = &table[level];
+
+      current = downsample(&current, src.width, src.height, dst.width, dst.height, filter);
+
+      let dst_layer_length = dst.width * dst.height * 4;
+      let start = dst.offset + l * dst_layer_length;
+      from_float(&current, &mut out[start..start + dst_layer_length], alpha_aware);
+    }
+  }
+
+  return (out, table);
+}
+
+fn to_float(data: &[u8], alpha_aware: bool) -> Vec<f32> {
+  let mut out: Vec<f32> = data.iter().map(|&v| v as f32 / 255.0).collect();
+
+  if alpha_aware {
+    for px in out.chunks_mut(4) {
+      px[0] *= px[3];
+      px[1] *= px[3];
+      px[2] *= px[3];
+    }
+  }
+
+  return out;
+}
+
+fn from_float(data: &[f32], out: &mut [u8], alpha_aware: bool) {
+  for (px, o) in data.chunks(4).zip(out.chunks_mut(4)) {
+    let a = px[3].clamp(0.0, 1.0);
+    let scale = if alpha_aware && a > 0.0 { 1.0 / a } else { 1.0 };
+
+    o[0] = quantize(px[0] * scale);
+    o[1] = quantize(px[1] * scale);
+    o[2] = quantize(px[2] * scale);
+    o[3] = quantize(a);
+  }
+}
+
+#[inline]
+fn quantize(v: f32) -> u8 {
+  (v.clamp(0.0, 1.0) * 255.0 + 0.5) as u8
+}
+
+fn downsample(src: &[f32], sw: usize, sh: usize, dw: usize, dh: usize, filter: MipFilter) -> Vec<f32> {
+  let horizontal = downsample_axis(src, sw, sh, dw, false, filter);
+  downsample_axis(&horizontal, dw, sh, dh, true, filter)
+}
+
+/// Filters a `width` × `height` image down to `out_len` texels along one axis.
+fn downsample_axis(src: &[f32], width: usize, height: usize, out_len: usize, vertical: bool, filter: MipFilter) -> Vec<f32> {
+  let (len, lines) = if vertical { (height, width) } else { (width, height) };
+  let (out_width, out_height) = if vertical { (width, out_len) } else { (out_len, height) };
+  let mut out = vec![0f32; out_width * out_height * 4];
+  let taps: Vec<Vec<(usize, f32)>> = (0..out_len).map(|i| weights(i, len, out_len, filter)).collect();
+
+  for line in 0..lines {
+    for (i, t) in taps.iter().enumerate() {
+      let o = if vertical { 4 * (i * out_width + line) } else { 4 * (line * out_width + i) };
+
+      for &(s, w) in t {
+        let p = if vertical { 4 * (s * width + line) } else { 4 * (line * width + s) };
+        for c in 0..4 {
+          out[o + c] += src[p + c] * w;
+        }
+      }
+    }
+  }
+
+  return out;
+}
+
+fn weights(i: usize, len: usize, out_len: usize, filter: MipFilter) -> Vec<(usize, f32)> {
+  if len == out_len {
+    return vec![(i, 1.0)];
+  }
+
+  let scale = len as f32 / out_len as f32;
+  let center = (i as f32 + 0.5) * scale;
+
+  let mut taps: Vec<(usize, f32)> = match filter {
+    MipFilter::Box => {
+      let first = (center - scale / 2.0).floor() as usize;
+      let last = cmp::min(len, (center + scale / 2.0).ceil() as usize);
+      (first..last).map(|s| (s, 1.0)).collect()
+    }
+    MipFilter::Kaiser => {
+      let radius = KAISER_RADIUS * scale;
+      let first = (center - radius).floor() as i64;
+      let last = (center + radius).ceil() as i64;
+
+      (first..last)
+        .map(|s| {
+          let t = (s as f32 + 0.5 - center) / scale;
+          (clamp_index(s, len), sinc(t) * kaiser(t / KAISER_RADIUS))
+        })
+        .collect()
+    }
+  };
+
+  let sum: f32 = taps.iter().map(|(_, w)| w).sum();
+  for t in taps.iter_mut() {
+    t.1 /= sum;
+  }
+
+  return taps;
+}
+
+#[inline]
+fn clamp_index(i: i64, len: usize) -> usize {
+  cmp::max(0, cmp::min(len as i64 - 1, i)) as usize
+}
+
+fn sinc(x: f32) -> f32 {
+  if x.abs() < 1e-6 {
+    1.0
+  } else {
+    (PI * x).sin() / (PI * x)
+  }
+}
+
+fn kaiser(x: f32) -> f32 {
+  if x.abs() > 1.0 {
+    return 0.0;
+  }
+
+  bessel_i0(KAISER_BETA * (1.0 - x * x).sqrt()) / bessel_i0(KAISER_BETA)
+}
+
+fn bessel_i0(x: f32) -> f32 {
+  let mut sum = 1.0;
+  let mut term = 1.0;
+  let half = x / 2.0;
+
+  for k in 1..20 {
+    term *= half / k as f32;
+    sum += term * term;
+  }
+
+  return sum;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn opaque(values: &[u8]) -> Vec<u8> {
+    values.iter().flat_map(|&v| vec![v, v, v, 0xFF]).collect()
+  }
+
+  #[test]
+  fn test_chain_sizes() {
+    assert_eq!(mip_level_count(8, 2), 4);
+    assert_eq!(mip_level_count(1, 1), 1);
+
+    let data = vec![0u8; 8 * 2 * 4 * 2];
+    let (chain, table) = mip_chain(&data, 8, 2, 2, 0, MipFilter::Box, false);
+    let sizes: Vec<(usize, usize, usize, usize)> = table.iter().map(|l| (l.width, l.height, l.offset, l.length)).collect();
+
+    assert_eq!(sizes, vec![(8, 2, 0, 128), (4, 1, 128, 32), (2, 1, 160, 16), (1, 1, 176, 8)]);
+    assert_eq!(chain.len(), 184);
+
+    assert_eq!(mip_chain(&data, 8, 2, 2, 2, MipFilter::Box, false).1.len(), 2);
+    assert_eq!(mip_chain(&data, 8, 2, 2, 10, MipFilter::Box, false).1.len(), 4);
+  }
+
+  #[test]
+  fn test_box_and_kaiser() {
+    // Black on the left, white on the right, two rows.
+    let row = [0, 0, 0, 0, 255, 255, 255, 255];
+    let data = opaque(&[row, row].concat());
+
+    let (chain, table) = mip_chain(&data, 8, 2, 1, 2, MipFilter::Box, false);
+    let level: Vec<u8> = chain[table[1].offset..].chunks(4).map(|p| p[0]).collect();
+    assert_eq!(level, vec![0, 0, 255, 255]);
+
+    // The windowed sinc reaches across the edge, but keeps it sharper than
+    // a wider box would.
+    let (chain, table) = mip_chain(&data, 8, 2, 1, 2, MipFilter::Kaiser, false);
+    let level: Vec<u8> = chain[table[1].offset..].chunks(4).map(|p| p[0]).collect();
+    assert!(level[1] > 0 && level[1] < 64, "{:?}", level);
+    assert!(level[2] > 191 && level[2] < 255, "{:?}", level);
+    assert!(level[0] < 16 && level[3] > 239, "{:?}", level);
+  }
+
+  #[test]
+  fn test_alpha_aware_does_not_bleed() {
+    let data = [255, 0, 0, 255, 0, 255, 0, 0, 0, 255, 0, 0, 0, 255, 0, 0];
+
+    let (chain, table) = mip_chain(&data, 2, 2, 1, 0, MipFilter::Box, true);
+    assert_eq!(&chain[table[1].offset..], &[255, 0, 0, 64]);
+
+    let (chain, table) = mip_chain(&data, 2, 2, 1, 0, MipFilter::Box, false);
+    assert_eq!(&chain[table[1].offset..], &[64, 191, 0, 64]);
+  }
+}
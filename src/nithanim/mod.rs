@@ -1 +0,0 @@
-pub mod bmd;
\ No newline at end of file
@@ -0,0 +1,187 @@
+//! Conversion of 768 byte game palettes from and to common swatch formats.
+
+use std::fmt::Write;
+
+pub const PALETTE_LENGTH: usize = 768;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PaletteFormat {
+  /// GIMP `.gpl`
+  Gpl,
+  /// Adobe `.act`
+  Act,
+  /// JASC / Paint Shop Pro `.pal`
+  JascPal,
+}
+
+impl PaletteFormat {
+  pub fn from_u8(v: u8) -> Option<PaletteFormat> {
+    match v {
+      0 => Some(PaletteFormat::Gpl),
+      1 => Some(PaletteFormat::Act),
+      2 => Some(PaletteFormat::JascPal),
+      _ => None,
+    }
+  }
+}
+
+pub fn export(palette: &[u8], format: PaletteFormat, name: &str) -> Vec<u8> {
+  match format {
+    PaletteFormat::Gpl => to_gpl(palette, name).into_bytes(),
+    PaletteFormat::Act => to_act(palette),
+    PaletteFormat::JascPal => to_jasc_pal(palette).into_bytes(),
+  }
+}
+
+pub fn import(data: &[u8], format: PaletteFormat) -> Result<Vec<u8>, &'static str> {
+  match format {
+    PaletteFormat::Gpl => from_gpl(std::str::from_utf8(data).map_err(|_| "import: palette is not valid text.")?),
+    PaletteFormat::Act => from_act(data),
+    PaletteFormat::JascPal => from_jasc_pal(std::str::from_utf8(data).map_err(|_| "import: palette is not valid text.")?),
+  }
+}
+
+pub fn to_gpl(palette: &[u8], name: &str) -> String {
+  let mut out = String::new();
+  writeln!(out, "GIMP Palette").unwrap();
+  writeln!(out, "Name: {}", name).unwrap();
+  writeln!(out, "Columns: 16").unwrap();
+  writeln!(out, "#").unwrap();
+
+  for (i, c) in palette[..PALETTE_LENGTH].chunks(3).enumerate() {
+    writeln!(out, "{:3} {:3} {:3}\tIndex {}", c[0], c[1], c[2], i).unwrap();
+  }
+
+  return out;
+}
+
+pub fn from_gpl(text: &str) -> Result<Vec<u8>, &'static str> {
+  let mut lines = text.lines();
+
+  if lines.next().map(str::trim) != Some("GIMP Palette") {
+    return Err("from_gpl: missing GIMP Palette header.");
+  }
+
+  let mut palette = Vec::with_capacity(PALETTE_LENGTH);
+
+  for line in lines {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') || line.starts_with("Name:") || line.starts_with("Columns:") {
+      continue;
+    }
+
+    palette.extend_from_slice(&parse_rgb(line)?);
+  }
+
+  validate(palette)
+}
+
+/// Writes the 772 byte variant with an explicit colour count and no
+/// transparent index.
+pub fn to_act(palette: &[u8]) -> Vec<u8> {
+  let mut out = palette[..PALETTE_LENGTH].to_vec();
+  out.extend_from_slice(&256u16.to_be_bytes());
+  out.extend_from_slice(&0xFFFFu16.to_be_bytes());
+
+  return out;
+}
+
+pub fn from_act(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+  match data.len() {
+    PALETTE_LENGTH => Ok(data.to_vec()),
+    772 => {
+      let count = u16::from_be_bytes([data[768], data[769]]);
+      if count != 256 {
+        return Err("from_act: palette does not have 256 entries.");
+      }
+
+      Ok(data[..PALETTE_LENGTH].to_vec())
+    }
+    _ => Err("from_act: unexpected file length."),
+  }
+}
+
+pub fn to_jasc_pal(palette: &[u8]) -> String {
+  let mut out = String::from("JASC-PAL\r\n0100\r\n256\r\n");
+
+  for c in palette[..PALETTE_LENGTH].chunks(3) {
+    write!(out, "{} {} {}\r\n", c[0], c[1], c[2]).unwrap();
+  }
+
+  return out;
+}
+
+pub fn from_jasc_pal(text: &str) -> Result<Vec<u8>, &'static str> {
+  let mut lines = text.lines().map(str::trim);
+
+  if lines.next() != Some("JASC-PAL") {
+    return Err("from_jasc_pal: missing JASC-PAL header.");
+  }
+  if lines.next() != Some("0100") {
+    return Err("from_jasc_pal: unsupported version.");
+  }
+
+  let count: usize = lines.next().and_then(|l| l.parse().ok()).ok_or("from_jasc_pal: missing colour count.")?;
+  if count != 256 {
+    return Err("from_jasc_pal: palette does not have 256 entries.");
+  }
+
+  let mut palette = Vec::with_capacity(PALETTE_LENGTH);
+  for line in lines.filter(|l| !l.is_empty()) {
+    palette.extend_from_slice(&parse_rgb(line)?);
+  }
+
+  validate(palette)
+}
+
+fn parse_rgb(line: &str) -> Result<[u8; 3], &'static str> {
+  let mut parts = line.split_whitespace().map(|p| p.parse::<u8>());
+  let mut rgb = [0u8; 3];
+
+  for c in rgb.iter_mut() {
+    *c = match parts.next() {
+      Some(Ok(v)) => v,
+      _ => return Err("parse_rgb: expected three colour values between 0 and 255."),
+    };
+  }
+
+  Ok(rgb)
+}
+
+fn validate(palette: Vec<u8>) -> Result<Vec<u8>, &'static str> {
+  if palette.len() != PALETTE_LENGTH {
+    return Err("validate: palette does not have 256 entries.");
+  }
+
+  Ok(palette)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn test_palette() -> Vec<u8> {
+    (0..PALETTE_LENGTH).map(|i| (i * 7 % 256) as u8).collect()
+  }
+
+  #[test]
+  fn test_roundtrip() {
+    let palette = test_palette();
+
+    for format in [PaletteFormat::Gpl, PaletteFormat::Act, PaletteFormat::JascPal].iter() {
+      let data = export(&palette, *format, "test");
+      assert_eq!(import(&data, *format).unwrap(), palette);
+    }
+  }
+
+  #[test]
+  fn test_import_rejects_wrong_entry_count() {
+    let gpl = "GIMP Palette\nName: short\n0 0 0\n255 255 255\n";
+    assert!(from_gpl(gpl).is_err());
+
+    let pal = "JASC-PAL\r\n0100\r\n16\r\n0 0 0\r\n";
+    assert!(from_jasc_pal(pal).is_err());
+
+    assert!(from_act(&[0u8; 300]).is_err());
+  }
+}
@@ -0,0 +1,346 @@
+//! Walkability of the map cells and A* paths over the staggered grid.
+
+use crate::fromts::map::CulturesMapData;
+use crate::grid::{Cell, Grid};
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// `LogicWalkBlockArea` of a landscape: two corners of a rectangle of cells,
+/// relative to the cell the landscape stands on.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BlockArea {
+  pub from: (i8, i8),
+  pub to: (i8, i8),
+}
+
+impl From<((i8, i8), (i8, i8))> for BlockArea {
+  fn from(area: ((i8, i8), (i8, i8))) -> BlockArea {
+    BlockArea { from: area.0, to: area.1 }
+  }
+}
+
+/// First value of a landscape without a block area in the flat form read by
+/// `block_areas`.
+pub const NO_BLOCK_AREA: i8 = i8::MIN;
+
+/// Block areas from four values each, `from` then `to`. Entries starting
+/// with `NO_BLOCK_AREA` have none.
+pub fn block_areas(values: &[i8]) -> Result<Vec<Option<BlockArea>>, &'static str> {
+  if values.len() % 4 != 0 {
+    return Err("block_areas: expected four values per block area.");
+  }
+
+  Ok(values.chunks_exact(4)
+    .map(|a| if a[0] == NO_BLOCK_AREA { None } else { Some(BlockArea { from: (a[0], a[1]), to: (a[2], a[3]) }) })
+    .collect())
+}
+
+/// Flags the patterns whose `LogicType` is in `blocking_types`.
+pub fn pattern_blocks(pattern_types: &[u8], blocking_types: &[u8]) -> Vec<bool> {
+  pattern_types.iter().map(|t| blocking_types.contains(t)).collect()
+}
+
+/// Which cells units can stand on.
+#[derive(Clone, Debug)]
+pub struct WalkGrid {
+  pub grid: Grid,
+  blocked: Vec<bool>,
+}
+
+impl WalkGrid {
+  /// A grid without obstacles.
+  pub fn new(width: usize, height: usize) -> WalkGrid {
+    WalkGrid { grid: Grid::new(width, height), blocked: vec![false; width * height] }
+  }
+
+  /// Blocks every cell whose A or B triangle has a pattern flagged in
+  /// `pattern_blocks`, which is indexed by `tiles_a` and `tiles_b`.
+  pub fn block_patterns(&mut self, tiles_a: &[u16], tiles_b: &[u16], pattern_blocks: &[bool]) {
+    let blocks = |t: u16| pattern_blocks.get(t as usize).copied().unwrap_or(false);
+
+    for (b, (&a, &t)) in self.blocked.iter_mut().zip(tiles_a.iter().zip(tiles_b.iter())) {
+      if blocks(a) || blocks(t) {
+        *b = true;
+      }
+    }
+  }
+
+  /// Blocks the area around every placed landscape. `landscape_types` holds
+  /// the index into `areas` plus one per cell, 0 for none.
+  pub fn block_landscapes(&mut self, landscape_types: &[u8], areas: &[Option<BlockArea>]) {
+    for (i, &t) in landscape_types.iter().enumerate() {
+      let area = match t.checked_sub(1).and_then(|t| areas.get(t as usize)) {
+        Some(Some(area)) => area,
+        _ => continue,
+      };
+
+      let c = self.grid.cell(i);
+      let from = Cell::new(c.x + area.from.0 as i32, c.y + area.from.1 as i32);
+      let to = Cell::new(c.x + area.to.0 as i32, c.y + area.to.1 as i32);
+
+      for b in self.grid.rect(from, to).collect::<Vec<_>>() {
+        self.set_walkable(b, false);
+      }
+    }
+  }
+
+  pub fn is_walkable(&self, c: Cell) -> bool {
+    self.grid.index(c).is_some_and(|i| !self.blocked[i])
+  }
+
+  /// Cells outside the map are ignored.
+  pub fn set_walkable(&mut self, c: Cell, walkable: bool) {
+    if let Some(i) = self.grid.index(c) {
+      self.blocked[i] = !walkable;
+    }
+  }
+}
+
+/// Walkability of `map`. `pattern_blocks` flags the blocking patterns per
+/// entry of `tiles_index`, `block_areas` holds the `LogicWalkBlockArea` per
+/// entry of `landscape_index`. The `hoixbwml` layer of the map is not used,
+/// its meaning is only a guess, see `Walkability`.
+pub fn walk_grid(map: &CulturesMapData, pattern_blocks: &[bool], block_areas: &[Option<BlockArea>]) -> Result<WalkGrid, &'static str> {
+  if pattern_blocks.len() != map.tiles_index().map_or(0, |i| i.len()) {
+    return Err("walk_grid: expected one pattern flag per entry of tiles_index.");
+  }
+  if block_areas.len() != map.landscape_index().map_or(0, |i| i.len()) {
+    return Err("walk_grid: expected one block area per entry of landscape_index.");
+  }
+
+  let mut walk = WalkGrid::new(map.width() as usize, map.height() as usize);
+
+  if let (Some(a), Some(b)) = (map.tiles_a(), map.tiles_b()) {
+    walk.block_patterns(a, b, pattern_blocks);
+  }
+  if let Some(types) = map.landscape_types() {
+    walk.block_landscapes(types, block_areas);
+  }
+
+  Ok(walk)
+}
+
+/// Cost of a step between neighbouring cells.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PathCosts {
+  /// A step on flat ground.
+  pub step: f32,
+  /// Added per elevation unit climbed.
+  pub uphill: f32,
+  /// Added per elevation unit descended.
+  pub downhill: f32,
+  /// Steps with a larger elevation difference cannot be taken.
+  pub max_climb: u8,
+}
+
+impl Default for PathCosts {
+  fn default() -> PathCosts {
+    PathCosts { step: 1.0, uphill: 0.05, downhill: 0.01, max_climb: 0xFF }
+  }
+}
+
+impl PathCosts {
+  fn cost(&self, from: u8, to: u8) -> Option<f32> {
+    let d = to as i32 - from as i32;
+    if d.abs() > self.max_climb as i32 {
+      return None;
+    }
+
+    let climb = if d > 0 { self.uphill * d as f32 } else { self.downhill * -d as f32 };
+    Some(self.step + climb)
+  }
+}
+
+/// Open set entry, ordered so the heap pops the lowest estimate first.
+#[derive(Copy, Clone, PartialEq)]
+struct Node {
+  estimate: f32,
+  index: usize,
+}
+
+impl Eq for Node {}
+
+impl Ord for Node {
+  fn cmp(&self, other: &Node) -> Ordering {
+    other.estimate.partial_cmp(&self.estimate).unwrap_or(Ordering::Equal)
+  }
+}
+
+impl PartialOrd for Node {
+  fn partial_cmp(&self, other: &Node) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+/// Cheapest path from `from` to `to`, both included, or `None` if `to`
+/// cannot be reached. `elevation` holds one value per cell.
+pub fn find_path(walk: &WalkGrid, elevation: &[u8], costs: &PathCosts, from: Cell, to: Cell) -> Option<Vec<Cell>> {
+  let grid = walk.grid;
+  if !walk.is_walkable(from) || !walk.is_walkable(to) {
+    return None;
+  }
+
+  let (start, goal) = (grid.index(from)?, grid.index(to)?);
+  // Every step costs at least `step`, so the distance never overestimates.
+  let heuristic = |c: Cell| c.distance(to) as f32 * costs.step;
+
+  let mut cost = vec![f32::INFINITY; grid.width * grid.height];
+  let mut previous = vec![usize::MAX; grid.width * grid.height];
+  let mut open = BinaryHeap::new();
+
+  cost[start] = 0.0;
+  open.push(Node { estimate: heuristic(from), index: start });
+
+  while let Some(Node { estimate, index }) = open.pop() {
+    if index == goal {
+      break;
+    }
+
+    let c = grid.cell(index);
+    // Stale entry of a cell reached more cheaply since.
+    if estimate > cost[index] + heuristic(c) {
+      continue;
+    }
+
+    for n in grid.neighbours(c).filter(|&n| walk.is_walkable(n)) {
+      let j = grid.index(n).unwrap();
+      let step = match costs.cost(elevation[index], elevation[j]) {
+        Some(step) => step,
+        None => continue,
+      };
+
+      if cost[index] + step < cost[j] {
+        cost[j] = cost[index] + step;
+        previous[j] = index;
+        open.push(Node { estimate: cost[j] + heuristic(n), index: j });
+      }
+    }
+  }
+
+  if cost[goal].is_infinite() {
+    return None;
+  }
+
+  let mut path = vec![to];
+  let mut i = goal;
+  while i != start {
+    i = previous[i];
+    path.push(grid.cell(i));
+  }
+  path.reverse();
+
+  Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fromts::map::parse_map_data;
+
+  /// A flat map with nothing but its size and elevation.
+  fn flat_map(width: u32, height: u32) -> CulturesMapData {
+    let section = |tag: &[u8], data: &[u8]| {
+      let mut out = vec![0u8; 0x20];
+      out.extend_from_slice(tag);
+      for v in [0, data.len() as u32, 0, 0, 0, 0].iter() {
+        out.extend_from_slice(&v.to_le_bytes());
+      }
+      out.extend_from_slice(data);
+      out
+    };
+
+    let cells = width * height;
+    let mut elevation = vec![1u8];
+    elevation.extend_from_slice(&18u32.to_le_bytes());
+    elevation.extend_from_slice(b"hoixdata");
+    elevation.extend_from_slice(&cells.to_le_bytes());
+    elevation.extend_from_slice(&18u32.to_le_bytes());
+    elevation.extend_from_slice(&[0x80 + cells as u8, 0]);
+
+    let size = [width.to_le_bytes(), height.to_le_bytes()].concat();
+    parse_map_data(&[section(b"hoixzisl", &size), section(b"hoixehml", &elevation)].concat()).unwrap()
+  }
+
+  #[test]
+  fn test_walk_grid_checks_lengths() {
+    let map = flat_map(3, 2);
+
+    let walk = walk_grid(&map, &[], &[]).unwrap();
+    assert_eq!((walk.grid.width, walk.grid.height), (3, 2));
+    assert!(walk.is_walkable(Cell::new(2, 1)));
+
+    assert!(walk_grid(&map, &[true], &[]).is_err());
+    assert!(walk_grid(&map, &[], &[None]).is_err());
+  }
+
+  #[test]
+  fn test_block_areas_and_pattern_blocks() {
+    let areas = block_areas(&[-1, 0, 1, 1, NO_BLOCK_AREA, 0, 0, 0]).unwrap();
+    assert_eq!(areas, vec![Some(((-1, 0), (1, 1)).into()), None]);
+    assert!(block_areas(&[0, 0, 0]).is_err());
+
+    assert_eq!(pattern_blocks(&[0, 3, 5], &[3, 4]), vec![false, true, false]);
+  }
+
+  #[test]
+  fn test_block_patterns_and_landscapes() {
+    let mut walk = WalkGrid::new(4, 4);
+    let tiles_a = vec![0u16; 16];
+    let mut tiles_b = vec![0u16; 16];
+    tiles_b[5] = 1;
+    walk.block_patterns(&tiles_a, &tiles_b, &[false, true]);
+
+    let mut types = vec![0u8; 16];
+    types[10] = 1;
+    types[15] = 2;
+    walk.block_landscapes(&types, &[Some(((0, 0), (1, 0)).into()), None]);
+
+    let blocked: Vec<usize> = (0..16).filter(|&i| !walk.is_walkable(walk.grid.cell(i))).collect();
+    assert_eq!(blocked, vec![5, 10, 11]);
+    assert!(!walk.is_walkable(Cell::new(4, 0)));
+  }
+
+  #[test]
+  fn test_find_path() {
+    let mut walk = WalkGrid::new(6, 6);
+    let elevation = vec![0u8; 36];
+    let costs = PathCosts::default();
+    let (from, to) = (Cell::new(0, 0), Cell::new(5, 5));
+
+    let path = find_path(&walk, &elevation, &costs, from, to).unwrap();
+    assert_eq!(path.len() as u32, from.distance(to) + 1);
+    assert_eq!((path[0], *path.last().unwrap()), (from, to));
+    assert!(path.windows(2).all(|w| w[0].distance(w[1]) == 1));
+
+    // A wall across row 3 with a gap at its end.
+    for x in 0..5 {
+      walk.set_walkable(Cell::new(x, 3), false);
+    }
+    let path = find_path(&walk, &elevation, &costs, Cell::new(0, 5), Cell::new(0, 0)).unwrap();
+    assert!(path.contains(&Cell::new(5, 3)));
+    assert!(path.iter().all(|&c| walk.is_walkable(c)));
+
+    walk.set_walkable(Cell::new(5, 3), false);
+    assert!(find_path(&walk, &elevation, &costs, Cell::new(0, 5), Cell::new(0, 0)).is_none());
+    assert_eq!(find_path(&walk, &elevation, &costs, from, from), Some(vec![from]));
+  }
+
+  #[test]
+  fn test_find_path_avoids_hills() {
+    let walk = WalkGrid::new(5, 5);
+    let mut elevation = vec![0u8; 25];
+    elevation[12] = 100;
+    let (from, to, hill) = (Cell::new(0, 2), Cell::new(4, 2), Cell::new(2, 2));
+
+    let flat = PathCosts { step: 1.0, uphill: 0.0, downhill: 0.0, max_climb: 0xFF };
+    assert!(find_path(&walk, &elevation, &flat, from, to).unwrap().contains(&hill));
+
+    let path = find_path(&walk, &elevation, &PathCosts::default(), from, to).unwrap();
+    assert!(!path.contains(&hill));
+    assert_eq!(path.len(), 6);
+
+    let steep = PathCosts { max_climb: 50, ..flat };
+    assert!(!find_path(&walk, &elevation, &steep, from, to).unwrap().contains(&hill));
+  }
+}
@@ -1,3 +1,6 @@
+use std::cmp;
+use crate::texture::PixelFormat;
+
 #[inline]
 fn read_uint16_le(buf: &[u8]) -> u16 {
   ((buf[1] as u16) << 8) + buf[0] as u16
@@ -34,7 +37,23 @@ pub fn read_palette(buf: &[u8]) -> Result<&[u8], &'static str> {
   Ok(&buf[1..769])
 }
 
-fn get_dimensions(buf: &[u8]) -> (usize, usize) {
+/// Renders a 768 byte palette as a 16×16 grid of `cell` sized RGB swatches.
+pub fn palette_swatch(palette: &[u8], cell: usize) -> (usize, usize, Vec<u8>) {
+  let size = 16 * cell;
+  let mut out = vec![0u8; 3 * size * size];
+
+  for y in 0..size {
+    for x in 0..size {
+      let c = 3 * ((y / cell) * 16 + x / cell);
+      let o = 3 * (y * size + x);
+      out[o..o + 3].copy_from_slice(&palette[c..c + 3]);
+    }
+  }
+
+  (size, size, out)
+}
+
+pub fn get_dimensions(buf: &[u8]) -> (usize, usize) {
   let x0 = read_uint16_le(&buf[4..6]) as usize;
   let y0 = read_uint16_le(&buf[6..8]) as usize;
   let x1 = read_uint16_le(&buf[8..10]) as usize;
@@ -43,42 +62,112 @@ fn get_dimensions(buf: &[u8]) -> (usize, usize) {
   return (x1 - x0 + 1, y1 - y0 + 1);
 }
 
+/// Length of a decoded scan line, which may include padding after the last
+/// pixel.
+fn bytes_per_line(buf: &[u8], width: usize) -> usize {
+  cmp::max(read_uint16_le(&buf[66..68]) as usize, width)
+}
+
 pub fn pcx_read<'a>(buf: &'a[u8], out: &mut [u8], mask: Option<&[u8]>) -> &'a[u8] {
   let (width, height) = get_dimensions(&buf);
-  let buf_length = width * height;
+  pcx_read_padded(buf, out, width, height, mask, PixelFormat::STRAIGHT_SRGB)
+}
+
+/// Decodes a PCX image into a `layer_width` × `layer_height` RGBA slot. Pixels
+/// outside the image are left untouched, pixels outside the slot are cropped.
+pub fn pcx_read_padded<'a>(buf: &'a[u8], out: &mut [u8], layer_width: usize, layer_height: usize, mask: Option<&[u8]>, pixel_format: PixelFormat) -> &'a[u8] {
+  let (width, height) = get_dimensions(&buf);
+  let stride = bytes_per_line(&buf, width);
 
-  let alpha = match mask {
-    None => vec![0xFFu8; buf_length],
+  let (alpha, mask_stride) = match mask {
+    None => (vec![0xFFu8; width * height], width),
     Some(mask_buf) => {
-      let mut mask_out_buf = vec![0xFFu8; buf_length];
+      let mask_stride = bytes_per_line(&mask_buf, width);
+      let mut mask_out_buf = vec![0xFFu8; mask_stride * height];
       read_pixels(&mask_buf[0x80..], &mut mask_out_buf);
 
-      mask_out_buf
+      (mask_out_buf, mask_stride)
     }
   };
 
-  let mut pixels = vec![0; buf_length];
+  let mut pixels = vec![0; stride * height];
   let (rest, _) = read_pixels(&buf[0x80..], &mut pixels);
   let palette = read_palette(&rest).expect("read_palette failed.");
 
-  for i in 0..pixels.len() {
-    out[4 * i + 0] = palette[0 + 3 * pixels[i] as usize];
-    out[4 * i + 1] = palette[1 + 3 * pixels[i] as usize];
-    out[4 * i + 2] = palette[2 + 3 * pixels[i] as usize];
-    out[4 * i + 3] = alpha[i];
+  for y in 0..cmp::min(height, layer_height) {
+    for x in 0..cmp::min(width, layer_width) {
+      let o = 4 * (y * layer_width + x);
+      let c = 3 * pixels[y * stride + x] as usize;
+
+      pixel_format.write(&mut out[o..o + 4], palette[c], palette[c + 1], palette[c + 2], alpha[y * mask_stride + x]);
+    }
   }
 
   return rest;
 }
 
-pub fn pcx_texture_array(buf: &[u8], out: &mut [u8], index_table: &[usize], mask_index_table: Option<&[usize]>) {
-  let (width, height) = get_dimensions(&buf);
-  let len = width * height * 4;
+#[derive(Clone, Debug)]
+pub struct TextureGroup {
+  pub width: usize,
+  pub height: usize,
+  /// Positions in the input index table of the images in this group, in layer order.
+  pub layers: Vec<usize>,
+  pub data: Vec<u8>,
+}
+
+fn fill_layer(out: &mut [u8], fill: [u8; 4]) {
+  for px in out.chunks_mut(4) {
+    px.copy_from_slice(&fill);
+  }
+}
+
+/// Decodes every image into its own `layer_width` × `layer_height` layer of
+/// `out`. Smaller images are padded with `fill`, larger ones are cropped. The
+/// fill colour is straight sRGB and converted to `pixel_format` like the
+/// pixels. The original dimensions are available from `get_dimensions`.
+pub fn pcx_texture_array(buf: &[u8], out: &mut [u8], layer_width: usize, layer_height: usize, fill: [u8; 4], index_table: &[usize], mask_index_table: Option<&[usize]>, pixel_format: PixelFormat) {
+  let len = layer_width * layer_height * 4;
+  let mut fill_px = [0u8; 4];
+  pixel_format.write(&mut fill_px, fill[0], fill[1], fill[2], fill[3]);
+
   for (i, idx) in index_table.iter().enumerate() {
-    pcx_read(&buf[*idx..], &mut out[(i * len)..], mask_index_table.and_then(|mit| Some(&buf[mit[i]..])));
+    let (width, height) = get_dimensions(&buf[*idx..]);
+    let layer = &mut out[(i * len)..((i + 1) * len)];
+
+    if width != layer_width || height != layer_height {
+      fill_layer(layer, fill_px);
+    }
+
+    pcx_read_padded(&buf[*idx..], layer, layer_width, layer_height, mask_index_table.and_then(|mit| Some(&buf[mit[i]..])), pixel_format);
   }
 }
 
+/// Groups the images by their dimensions and decodes each group into its own
+/// texture array. Groups are ordered by the first appearance of their size.
+pub fn pcx_texture_groups(buf: &[u8], index_table: &[usize], mask_index_table: Option<&[usize]>, pixel_format: PixelFormat) -> Vec<TextureGroup> {
+  let mut groups: Vec<TextureGroup> = Vec::new();
+
+  for (i, idx) in index_table.iter().enumerate() {
+    let (width, height) = get_dimensions(&buf[*idx..]);
+
+    match groups.iter_mut().find(|g| g.width == width && g.height == height) {
+      Some(g) => g.layers.push(i),
+      None => groups.push(TextureGroup { width, height, layers: vec![i], data: Vec::new() }),
+    }
+  }
+
+  for g in groups.iter_mut() {
+    let len = g.width * g.height * 4;
+    g.data = vec![0u8; len * g.layers.len()];
+
+    for (l, &i) in g.layers.iter().enumerate() {
+      pcx_read_padded(&buf[index_table[i]..], &mut g.data[(l * len)..], g.width, g.height, mask_index_table.and_then(|mit| Some(&buf[mit[i]..])), pixel_format);
+    }
+  }
+
+  return groups;
+}
+
 pub fn pcx_read_palette_array<'a>(buf: &'a[u8], index: &[usize]) -> Vec<&'a[u8]> {
   let mut out: Vec<&'a[u8]> = vec![buf; index.len()];
 
@@ -95,6 +184,75 @@ pub fn pcx_read_palette_array<'a>(buf: &'a[u8], index: &[usize]) -> Vec<&'a[u8]>
   return out;
 }
 
+/// Replaces the extended palette at the end of a 256 colour PCX file.
+pub fn replace_palette(buf: &[u8], palette: &[u8]) -> Result<Vec<u8>, &'static str> {
+  if buf.len() < 0x80 + 769 || palette.len() != 768 {
+    return Err("replace_palette: PCX file or palette too short.");
+  }
+
+  let start = buf.len() - 769;
+  read_palette(&buf[start..])?;
+
+  let mut out = buf.to_vec();
+  out[start + 1..].copy_from_slice(palette);
+
+  Ok(out)
+}
+
+/// Encodes 8-bit palette indices as a run-length encoded 256 colour PCX file.
+pub fn pcx_write(width: usize, height: usize, pixels: &[u8], palette: &[u8]) -> Result<Vec<u8>, &'static str> {
+  // The padded line length has to fit the 16-bit bytes per line field.
+  if width == 0 || height == 0 || width > 0xFFFE || height > 0x10000 {
+    return Err("pcx_write: width must be between 1 and 65534 and height between 1 and 65536.");
+  }
+  if pixels.len() < width * height || palette.len() < 768 {
+    return Err("pcx_write: pixels or palette too short.");
+  }
+
+  // Scan lines are padded to an even number of bytes.
+  let bytes_per_line = (width + 1) & !1;
+
+  let mut out = vec![0u8; 0x80];
+  out[0] = 0x0A;
+  out[1] = 5;
+  out[2] = 1;
+  out[3] = 8;
+  out[8..10].copy_from_slice(&((width - 1) as u16).to_le_bytes());
+  out[10..12].copy_from_slice(&((height - 1) as u16).to_le_bytes());
+  out[12..14].copy_from_slice(&72u16.to_le_bytes());
+  out[14..16].copy_from_slice(&72u16.to_le_bytes());
+  out[65] = 1;
+  out[66..68].copy_from_slice(&(bytes_per_line as u16).to_le_bytes());
+  out[68..70].copy_from_slice(&1u16.to_le_bytes());
+
+  let mut line = vec![0u8; bytes_per_line];
+
+  for row in pixels[..width * height].chunks(width) {
+    line[..width].copy_from_slice(row);
+
+    let mut i = 0;
+    while i < line.len() {
+      let val = line[i];
+      let mut len = 1;
+      while i + len < line.len() && line[i + len] == val && len < 63 {
+        len += 1;
+      }
+
+      // Values with the two top bits set must always be written as a run.
+      if len > 1 || val >= 0xC0 {
+        out.push(0xC0 + len as u8);
+      }
+      out.push(val);
+      i += len;
+    }
+  }
+
+  out.push(0x0C);
+  out.extend_from_slice(&palette[..768]);
+
+  Ok(out)
+}
+
 // pub fn pcx_read_palette(buf: &[u8], ) {
 //   let mut palette: [RGBColor; 256] = [RGBColor::default(); 256];
 //   read_palette(rest, &mut palette).expect("read_palette failed.");
@@ -146,4 +304,89 @@ mod tests {
 
     pcx_read_palette_array(&buffer[..], &[0usize; 1]);
   }
+
+  #[test]
+  fn test_pcx_write_roundtrip() {
+    let palette: Vec<u8> = (0..768).map(|i| (i % 256) as u8).collect();
+    let pixels: Vec<u8> = (0..16 * 8).map(|i| if i % 16 < 10 { 0xC5 } else { i as u8 }).collect();
+
+    let buffer = pcx_write(16, 8, &pixels, &palette).unwrap();
+    assert_eq!(get_dimensions(&buffer), (16, 8));
+
+    let mut out = [0u8; 16 * 8 * 4];
+    pcx_read(&buffer, &mut out, None);
+    for (i, p) in pixels.iter().enumerate() {
+      assert_eq!(&out[4 * i..4 * i + 4], &[palette[3 * *p as usize], palette[3 * *p as usize + 1], palette[3 * *p as usize + 2], 0xFF]);
+    }
+
+    let other = vec![7u8; 768];
+    let replaced = replace_palette(&buffer, &other).unwrap();
+    assert_eq!(read_palette(&replaced[replaced.len() - 769..]).unwrap(), &other[..]);
+
+    assert!(pcx_write(0, 8, &pixels, &palette).is_err());
+    assert!(pcx_write(16, 0, &pixels, &palette).is_err());
+    assert!(pcx_write(16, 9, &pixels, &palette).is_err());
+    assert!(pcx_write(0xFFFF, 1, &[0u8; 0xFFFF], &palette).is_err());
+  }
+
+  #[test]
+  fn test_pcx_write_odd_width() {
+    // Odd scan lines are padded to an even length, which the reader skips.
+    let palette: Vec<u8> = (0..768).map(|i| (i / 3) as u8).collect();
+    let pixels: Vec<u8> = (0..5 * 3).map(|i| 10 * i as u8).collect();
+
+    let buffer = pcx_write(5, 3, &pixels, &palette).unwrap();
+    assert_eq!(read_uint16_le(&buffer[66..68]), 6);
+
+    let mut out = [0u8; 5 * 3 * 4];
+    pcx_read(&buffer, &mut out, None);
+    let grey: Vec<u8> = out.chunks(4).map(|p| p[0]).collect();
+    assert_eq!(grey, pixels);
+  }
+
+  /// PCX files of a single colour each, one after another, and their index
+  /// table. Palette entry `i` is grey `i`.
+  fn images(sizes: &[(usize, usize, u8)]) -> (Vec<u8>, Vec<usize>) {
+    let palette: Vec<u8> = (0..768).map(|i| (i / 3) as u8).collect();
+    let mut buf = Vec::new();
+    let mut index = Vec::new();
+
+    for &(width, height, value) in sizes {
+      index.push(buf.len());
+      buf.extend(pcx_write(width, height, &vec![value; width * height], &palette).unwrap());
+    }
+
+    (buf, index)
+  }
+
+  #[test]
+  fn test_texture_array_pads_and_crops() {
+    let (buf, index) = images(&[(2, 2, 10), (6, 4, 20)]);
+    let mut out = vec![0u8; 2 * 4 * 4 * 4];
+    pcx_texture_array(&buf, &mut out, 4, 4, [1, 2, 3, 4], &index, None, PixelFormat::STRAIGHT_SRGB);
+
+    let px = |layer: usize, x: usize, y: usize| &out[4 * (layer * 16 + y * 4 + x)..4 * (layer * 16 + y * 4 + x) + 4];
+    assert_eq!(px(0, 1, 1), &[10, 10, 10, 0xFF]);
+    assert_eq!(px(0, 2, 1), &[1, 2, 3, 4]);
+    assert_eq!(px(0, 0, 3), &[1, 2, 3, 4]);
+    // The larger image fills its layer and is cropped.
+    assert!(out[64..].chunks(4).all(|p| p == [20, 20, 20, 0xFF]));
+
+    // The fill colour is converted like the pixels.
+    pcx_texture_array(&buf, &mut out, 4, 4, [0xFF, 0x80, 0, 0x80], &index, None, PixelFormat::from_bits(1));
+    assert_eq!(&out[24..28], &[0x80, 0x40, 0, 0x80]);
+  }
+
+  #[test]
+  fn test_texture_groups() {
+    let (buf, index) = images(&[(2, 2, 10), (4, 2, 20), (2, 2, 30)]);
+    let groups = pcx_texture_groups(&buf, &index, None, PixelFormat::STRAIGHT_SRGB);
+
+    assert_eq!(groups.len(), 2);
+    assert_eq!((groups[0].width, groups[0].height, &groups[0].layers[..]), (2, 2, &[0, 2][..]));
+    assert_eq!((groups[1].width, groups[1].height, &groups[1].layers[..]), (4, 2, &[1][..]));
+    assert_eq!(groups[0].data.len(), 2 * 2 * 2 * 4);
+    assert_eq!(&groups[0].data[16..20], &[30, 30, 30, 0xFF]);
+    assert!(groups[1].data.chunks(4).all(|p| p == [20, 20, 20, 0xFF]));
+  }
 }
@@ -0,0 +1,344 @@
+//! Minimal PNG encoder. Image data is compressed with fixed Huffman codes and
+//! a greedy LZ77 matcher, which is small and plenty for palette based artwork.
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+const COLOR_TYPE_RGB: u8 = 2;
+const COLOR_TYPE_RGBA: u8 = 6;
+
+const LEN_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LEN_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+const WINDOW_SIZE: usize = 32768;
+const MAX_MATCH: usize = 258;
+const MIN_MATCH: usize = 3;
+const HASH_SIZE: usize = 1 << 15;
+const MAX_CHAIN: usize = 64;
+
+pub fn encode_rgba(width: usize, height: usize, rgba: &[u8]) -> Vec<u8> {
+  encode(width, height, COLOR_TYPE_RGBA, 4, &rgba[..4 * width * height])
+}
+
+pub fn encode_rgb(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+  encode(width, height, COLOR_TYPE_RGB, 3, &rgb[..3 * width * height])
+}
+
+/// Encodes full size RGBA frames as a looping APNG. `delay` is given in
+/// milliseconds.
+pub fn encode_apng(width: usize, height: usize, frames: &[Vec<u8>], delay: u16) -> Vec<u8> {
+  let mut out = SIGNATURE.to_vec();
+
+  let mut ihdr = Vec::with_capacity(13);
+  ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+  ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+  ihdr.extend_from_slice(&[8, COLOR_TYPE_RGBA, 0, 0, 0]);
+  write_chunk(&mut out, b"IHDR", &ihdr);
+
+  let mut actl = Vec::with_capacity(8);
+  actl.extend_from_slice(&(frames.len() as u32).to_be_bytes());
+  actl.extend_from_slice(&0u32.to_be_bytes());
+  write_chunk(&mut out, b"acTL", &actl);
+
+  let mut sequence = 0u32;
+
+  for (i, frame) in frames.iter().enumerate() {
+    let mut fctl = Vec::with_capacity(26);
+    fctl.extend_from_slice(&sequence.to_be_bytes());
+    fctl.extend_from_slice(&(width as u32).to_be_bytes());
+    fctl.extend_from_slice(&(height as u32).to_be_bytes());
+    fctl.extend_from_slice(&[0; 8]);
+    fctl.extend_from_slice(&delay.to_be_bytes());
+    fctl.extend_from_slice(&1000u16.to_be_bytes());
+    // Clear to transparent after the frame and replace instead of blending.
+    fctl.extend_from_slice(&[1, 0]);
+    write_chunk(&mut out, b"fcTL", &fctl);
+    sequence += 1;
+
+    let data = zlib(&scanlines(width, height, 4, &frame[..4 * width * height]));
+
+    if i == 0 {
+      write_chunk(&mut out, b"IDAT", &data);
+    } else {
+      let mut fdat = sequence.to_be_bytes().to_vec();
+      fdat.extend_from_slice(&data);
+      write_chunk(&mut out, b"fdAT", &fdat);
+      sequence += 1;
+    }
+  }
+
+  write_chunk(&mut out, b"IEND", &[]);
+
+  return out;
+}
+
+fn encode(width: usize, height: usize, color_type: u8, bpp: usize, pixels: &[u8]) -> Vec<u8> {
+  let mut out = SIGNATURE.to_vec();
+
+  let mut ihdr = Vec::with_capacity(13);
+  ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+  ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+  ihdr.extend_from_slice(&[8, color_type, 0, 0, 0]);
+  write_chunk(&mut out, b"IHDR", &ihdr);
+
+  write_chunk(&mut out, b"IDAT", &zlib(&scanlines(width, height, bpp, pixels)));
+  write_chunk(&mut out, b"IEND", &[]);
+
+  return out;
+}
+
+/// Prefixes every row with filter type 0 (none).
+pub(crate) fn scanlines(width: usize, height: usize, bpp: usize, pixels: &[u8]) -> Vec<u8> {
+  let stride = width * bpp;
+  let mut raw = Vec::with_capacity((stride + 1) * height);
+
+  for row in pixels.chunks(stride).take(height) {
+    raw.push(0);
+    raw.extend_from_slice(row);
+  }
+
+  return raw;
+}
+
+pub(crate) fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+  out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+  let start = out.len();
+  out.extend_from_slice(kind);
+  out.extend_from_slice(data);
+  let crc = crc32(&out[start..]);
+  out.extend_from_slice(&crc.to_be_bytes());
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+  let mut table = [0u32; 256];
+  let mut n = 0;
+
+  while n < 256 {
+    let mut c = n as u32;
+    let mut k = 0;
+    while k < 8 {
+      c = if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+      k += 1;
+    }
+    table[n] = c;
+    n += 1;
+  }
+
+  return table;
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+  let mut crc = 0xFFFFFFFFu32;
+  for b in data {
+    crc = CRC_TABLE[((crc ^ *b as u32) & 0xFF) as usize] ^ (crc >> 8);
+  }
+
+  return crc ^ 0xFFFFFFFF;
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+  let mut a = 1u32;
+  let mut b = 0u32;
+
+  for chunk in data.chunks(5552) {
+    for v in chunk {
+      a += *v as u32;
+      b += a;
+    }
+    a %= 65521;
+    b %= 65521;
+  }
+
+  return (b << 16) | a;
+}
+
+pub(crate) fn zlib(data: &[u8]) -> Vec<u8> {
+  let mut w = BitWriter { out: vec![0x78, 0x01], bits: 0, count: 0 };
+
+  // A single final block with fixed Huffman codes.
+  w.write(1, 1);
+  w.write(1, 2);
+  deflate(data, &mut w);
+  write_literal(&mut w, 256);
+  w.flush();
+
+  let mut out = w.out;
+  out.extend_from_slice(&adler32(data).to_be_bytes());
+
+  return out;
+}
+
+struct BitWriter {
+  out: Vec<u8>,
+  bits: u32,
+  count: u32,
+}
+
+impl BitWriter {
+  #[inline]
+  fn write(&mut self, value: u32, len: u32) {
+    self.bits |= value << self.count;
+    self.count += len;
+
+    while self.count >= 8 {
+      self.out.push(self.bits as u8);
+      self.bits >>= 8;
+      self.count -= 8;
+    }
+  }
+
+  /// Huffman codes are stored most significant bit first.
+  #[inline]
+  fn write_code(&mut self, code: u32, len: u32) {
+    self.write(code.reverse_bits() >> (32 - len), len);
+  }
+
+  fn flush(&mut self) {
+    if self.count > 0 {
+      self.out.push(self.bits as u8);
+    }
+    self.bits = 0;
+    self.count = 0;
+  }
+}
+
+fn write_literal(w: &mut BitWriter, v: u32) {
+  match v {
+    0..=143 => w.write_code(0x30 + v, 8),
+    144..=255 => w.write_code(0x190 + v - 144, 9),
+    256..=279 => w.write_code(v - 256, 7),
+    _ => w.write_code(0xC0 + v - 280, 8),
+  }
+}
+
+fn write_match(w: &mut BitWriter, len: usize, dist: usize) {
+  let l = LEN_BASE.iter().rposition(|&b| b as usize <= len).unwrap();
+  write_literal(w, 257 + l as u32);
+  w.write((len - LEN_BASE[l] as usize) as u32, LEN_EXTRA[l] as u32);
+
+  let d = DIST_BASE.iter().rposition(|&b| b as usize <= dist).unwrap();
+  w.write_code(d as u32, 5);
+  w.write((dist - DIST_BASE[d] as usize) as u32, DIST_EXTRA[d] as u32);
+}
+
+#[inline]
+fn hash(data: &[u8], i: usize) -> usize {
+  let v = (data[i] as usize) << 16 | (data[i + 1] as usize) << 8 | data[i + 2] as usize;
+  (v.wrapping_mul(2654435761) >> 7) & (HASH_SIZE - 1)
+}
+
+#[inline]
+fn insert(data: &[u8], head: &mut [usize], prev: &mut [usize], i: usize) {
+  if i + MIN_MATCH <= data.len() {
+    let h = hash(data, i);
+    prev[i % WINDOW_SIZE] = head[h];
+    head[h] = i;
+  }
+}
+
+fn deflate(data: &[u8], w: &mut BitWriter) {
+  let mut head = vec![usize::MAX; HASH_SIZE];
+  let mut prev = vec![usize::MAX; WINDOW_SIZE];
+  let mut i = 0;
+
+  while i < data.len() {
+    let mut best_len = 0;
+    let mut best_dist = 0;
+
+    if i + MIN_MATCH <= data.len() {
+      let mut candidate = head[hash(data, i)];
+      let max_len = std::cmp::min(MAX_MATCH, data.len() - i);
+      let mut chain = 0;
+
+      while candidate != usize::MAX && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+        let mut len = 0;
+        while len < max_len && data[candidate + len] == data[i + len] {
+          len += 1;
+        }
+
+        if len > best_len {
+          best_len = len;
+          best_dist = i - candidate;
+          if len == max_len {
+            break;
+          }
+        }
+
+        let next = prev[candidate % WINDOW_SIZE];
+        if next == usize::MAX || next >= candidate {
+          break;
+        }
+        candidate = next;
+        chain += 1;
+      }
+    }
+
+    if best_len >= MIN_MATCH {
+      write_match(w, best_len, best_dist);
+      for j in i..i + best_len {
+        insert(data, &mut head, &mut prev, j);
+      }
+      i += best_len;
+    } else {
+      write_literal(w, data[i] as u32);
+      insert(data, &mut head, &mut prev, i);
+      i += 1;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_checksums() {
+    assert_eq!(crc32(b"IEND"), 0xAE426082);
+    assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+  }
+
+  #[test]
+  fn test_zlib_inflates() {
+    let mut data: Vec<u8> = (0..5000u32).map(|i| (i * i / 7) as u8).collect();
+    // Long runs and repeats far back exercise the length and distance codes.
+    data.extend(vec![0xAB; 700]);
+    let repeat = data[100..3000].to_vec();
+    data.extend(repeat);
+    data.extend((0..40000u32).map(|i| (i % 251) as u8));
+
+    for input in &[&data[..], &[], &[1, 2, 3], &data[..MIN_MATCH + 1]] {
+      let compressed = zlib(input);
+      let inflated = miniz_oxide::inflate::decompress_to_vec_zlib(&compressed).unwrap();
+      assert_eq!(&inflated[..], *input);
+    }
+  }
+
+  #[test]
+  fn test_encode_rgba_chunks() {
+    let png = encode_rgba(2, 1, &[0xFF, 0, 0, 0xFF, 0, 0xFF, 0, 0x80]);
+
+    assert_eq!(&png[..8], &SIGNATURE);
+    assert_eq!(&png[12..16], b"IHDR");
+    assert_eq!(&png[16..24], &[0, 0, 0, 2, 0, 0, 0, 1]);
+    assert_eq!(png[25], COLOR_TYPE_RGBA);
+    assert_eq!(&png[png.len() - 12..], &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+  }
+
+  #[test]
+  fn test_encode_apng_chunks() {
+    let png = encode_apng(1, 1, &[vec![0xFF, 0, 0, 0xFF], vec![0, 0xFF, 0, 0xFF]], 100);
+
+    let mut kinds = Vec::new();
+    let mut pos = 8;
+    while pos < png.len() {
+      let len = u32::from_be_bytes([png[pos], png[pos + 1], png[pos + 2], png[pos + 3]]) as usize;
+      kinds.push(&png[pos + 4..pos + 8]);
+      pos += 12 + len;
+    }
+
+    assert_eq!(kinds, vec![&b"IHDR"[..], b"acTL", b"fcTL", b"IDAT", b"fcTL", b"fdAT", b"IEND"]);
+  }
+}
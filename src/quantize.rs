@@ -0,0 +1,234 @@
+//! Maps true colour artwork onto a 256 entry game palette. Colours are matched
+//! in OKLab, which tracks perceived differences far better than RGB distance.
+
+use crate::bmd::IndexedFrame;
+
+use std::collections::HashMap;
+
+/// Shadow pixels are translucent pixels darker than this in every channel.
+const SHADOW_THRESHOLD: u8 = 0x20;
+/// Strength of the ordered dither, in 8-bit sRGB steps.
+const ORDERED_SPREAD: f32 = 32.0;
+
+const BAYER_4X4: [u8; 16] = [0, 8, 2, 10, 12, 4, 14, 6, 3, 11, 1, 9, 15, 7, 13, 5];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Dither {
+  None,
+  FloydSteinberg,
+  /// 4×4 Bayer matrix, which keeps flat areas stable between animation frames.
+  Ordered,
+}
+
+impl Dither {
+  pub fn from_u8(v: u8) -> Option<Dither> {
+    match v {
+      0 => Some(Dither::None),
+      1 => Some(Dither::FloydSteinberg),
+      2 => Some(Dither::Ordered),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct QuantizeOptions {
+  pub dither: Dither,
+  /// Index written for fully transparent pixels. Never used for colours.
+  pub transparent: Option<u8>,
+  /// Index written for shadow pixels, the translucent near-black ones. Never
+  /// used for colours. Without it, such pixels are matched like any other.
+  pub shadow: Option<u8>,
+}
+
+pub struct Quantizer {
+  palette: Vec<u8>,
+  options: QuantizeOptions,
+  /// OKLab colour of every palette entry that may be matched.
+  candidates: Vec<(u8, [f32; 3])>,
+  cache: HashMap<[u8; 3], u8>,
+}
+
+fn srgb_to_linear(c: u8) -> f64 {
+  let c = c as f64 / 255.0;
+  if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+/// The matrices are given to f64 precision, so the conversion runs in f64.
+pub fn oklab(rgb: [u8; 3]) -> [f32; 3] {
+  let r = srgb_to_linear(rgb[0]);
+  let g = srgb_to_linear(rgb[1]);
+  let b = srgb_to_linear(rgb[2]);
+
+  let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+  let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+  let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+  [
+    (0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s) as f32,
+    (1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s) as f32,
+    (0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s) as f32,
+  ]
+}
+
+impl Quantizer {
+  pub fn new(palette: &[u8], options: QuantizeOptions) -> Quantizer {
+    let candidates = palette[..768].chunks(3).enumerate()
+      .map(|(i, c)| (i as u8, oklab([c[0], c[1], c[2]])))
+      .filter(|(i, _)| Some(*i) != options.transparent && Some(*i) != options.shadow)
+      .collect();
+
+    Quantizer { palette: palette[..768].to_vec(), options, candidates, cache: HashMap::new() }
+  }
+
+  /// Palette index closest to `rgb`, skipping the reserved indices.
+  pub fn nearest(&mut self, rgb: [u8; 3]) -> u8 {
+    if let Some(&i) = self.cache.get(&rgb) {
+      return i;
+    }
+
+    let lab = oklab(rgb);
+    let mut best = (0u8, f32::MAX);
+
+    for (i, c) in self.candidates.iter() {
+      let d = (c[0] - lab[0]).powi(2) + (c[1] - lab[1]).powi(2) + (c[2] - lab[2]).powi(2);
+      if d < best.1 {
+        best = (*i, d);
+      }
+    }
+
+    self.cache.insert(rgb, best.0);
+
+    return best.0;
+  }
+
+  /// Quantizes an RGBA image. Pixels with alpha 0 are left out, translucent
+  /// near-black pixels are flagged as shadow if a shadow index is set and
+  /// every other pixel keeps its alpha as coverage.
+  pub fn quantize(&mut self, rgba: &[u8], width: usize, height: usize) -> IndexedFrame {
+    let mut out = IndexedFrame::new(width, height);
+
+    // Floyd–Steinberg error of the current and the next row.
+    let mut error = vec![[0f32; 3]; 2 * (width + 2)];
+
+    for y in 0..height {
+      let (current, next) = error.split_at_mut(width + 2);
+      next.iter_mut().for_each(|e| *e = [0.0; 3]);
+
+      for x in 0..width {
+        let pos = y * width + x;
+        let px = &rgba[4 * pos..4 * pos + 4];
+
+        if px[3] == 0 {
+          out.indices[pos] = self.options.transparent.unwrap_or(0);
+          continue;
+        }
+
+        if let Some(shadow) = self.options.shadow {
+          if px[3] < 0xFF && px[..3].iter().all(|&c| c < SHADOW_THRESHOLD) {
+            out.indices[pos] = shadow;
+            out.shadow[pos] = 0xFF;
+            continue;
+          }
+        }
+
+        let mut target = [px[0] as f32, px[1] as f32, px[2] as f32];
+        match self.options.dither {
+          Dither::None => {}
+          Dither::FloydSteinberg => {
+            for c in 0..3 {
+              target[c] += current[x + 1][c];
+            }
+          }
+          Dither::Ordered => {
+            let t = (BAYER_4X4[(y % 4) * 4 + x % 4] as f32 + 0.5) / 16.0 - 0.5;
+            for c in target.iter_mut() {
+              *c += t * ORDERED_SPREAD;
+            }
+          }
+        }
+
+        let rgb = [clamp(target[0]), clamp(target[1]), clamp(target[2])];
+        let index = self.nearest(rgb);
+
+        out.indices[pos] = index;
+        out.alpha[pos] = px[3];
+
+        if self.options.dither == Dither::FloydSteinberg {
+          let p = 3 * index as usize;
+          for c in 0..3 {
+            let e = target[c] - self.palette[p + c] as f32;
+            current[x + 2][c] += e * 7.0 / 16.0;
+            next[x][c] += e * 3.0 / 16.0;
+            next[x + 1][c] += e * 5.0 / 16.0;
+            next[x + 2][c] += e * 1.0 / 16.0;
+          }
+        }
+      }
+
+      error.rotate_left(width + 2);
+    }
+
+    return out;
+  }
+}
+
+#[inline]
+fn clamp(v: f32) -> u8 {
+  v.round().clamp(0.0, 255.0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn grey_palette() -> Vec<u8> {
+    (0..256).flat_map(|i| vec![i as u8; 3]).collect()
+  }
+
+  fn options(dither: Dither) -> QuantizeOptions {
+    QuantizeOptions { dither, transparent: Some(0), shadow: Some(1) }
+  }
+
+  #[test]
+  fn test_nearest_skips_reserved() {
+    let palette = grey_palette();
+    let mut q = Quantizer::new(&palette, options(Dither::None));
+
+    assert_eq!(q.nearest([200, 200, 200]), 200);
+    assert_eq!(q.nearest([0, 0, 0]), 2);
+
+    let rgba = [0, 0, 0, 0, 0, 0, 0, 0x80, 90, 90, 90, 0xFF];
+    let frame = q.quantize(&rgba, 3, 1);
+    assert_eq!(frame.indices, vec![0, 1, 90]);
+    assert_eq!(frame.alpha, vec![0, 0, 0xFF]);
+    assert_eq!(frame.shadow, vec![0, 0xFF, 0]);
+  }
+
+  #[test]
+  fn test_floyd_steinberg_mixes_colours() {
+    let mut palette = vec![0u8; 768];
+    palette[3..6].copy_from_slice(&[255, 255, 255]);
+
+    let rgba = [128u8, 128, 128, 0xFF].repeat(64);
+    let mut q = Quantizer::new(&palette, QuantizeOptions { dither: Dither::FloydSteinberg, transparent: None, shadow: None });
+    let frame = q.quantize(&rgba, 8, 8);
+
+    let white = frame.indices.iter().filter(|&&i| i == 1).count();
+    assert!(white > 16 && white < 48);
+  }
+
+  #[test]
+  fn test_shadow_needs_an_index() {
+    let palette = grey_palette();
+    let rgba = [0x10, 0x10, 0x10, 0x80];
+
+    let mut q = Quantizer::new(&palette, QuantizeOptions { dither: Dither::None, transparent: None, shadow: None });
+    let frame = q.quantize(&rgba, 1, 1);
+    assert_eq!((frame.indices[0], frame.alpha[0], frame.shadow[0]), (0x10, 0x80, 0));
+
+    let mut q = Quantizer::new(&palette, options(Dither::None));
+    let frame = q.quantize(&rgba, 1, 1);
+    assert_eq!((frame.indices[0], frame.alpha[0], frame.shadow[0]), (1, 0, 0xFF));
+  }
+}
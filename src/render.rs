@@ -0,0 +1,507 @@
+//! Software renderer for maps. Draws the terrain with its patterns,
+//! transitions and lighting and the landscape objects on top into an RGBA
+//! image, without a GPU. The geometry is the same as `tessellate` builds for
+//! WebGL.
+
+use crate::animation::AnimationClip;
+use crate::bmd::{self, BmdFile, ComposedFrame};
+use crate::fromts::map::CulturesMapData;
+use crate::grid::{Grid, Triangle, TriangleKind};
+use crate::minimap::lighting_factor;
+use crate::png;
+use crate::tessellate;
+use crate::texture::PixelFormat;
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+/// Pixels per map unit at scale 1. A triangle is two units wide and high,
+/// like the 64 pixel squares of the pattern textures.
+pub const UNIT: f32 = 32.0;
+
+/// Largest image `render` draws, in pixels.
+const MAX_PIXELS: usize = 1 << 26;
+
+/// Rows below a region whose terrain can reach into it. Elevation lifts a
+/// vertex by up to 255 / 16 units and a row is 2 units high.
+const LIFT_ROWS: usize = 9;
+
+/// Layers of `width` × `height` RGBA pixels, laid out like the output of
+/// `create_2d_texture`.
+pub struct TextureArray<'a> {
+  pub width: usize,
+  pub height: usize,
+  pub data: &'a [u8],
+}
+
+impl<'a> TextureArray<'a> {
+  /// Nearest pixel, transparent outside the array.
+  fn sample(&self, layer: usize, u: f32, v: f32) -> [u8; 4] {
+    let x = (u.max(0.0) as usize).min(self.width - 1);
+    let y = (v.max(0.0) as usize).min(self.height - 1);
+    let o = 4 * ((layer * self.height + y) * self.width + x);
+
+    match self.data.get(o..o + 4) {
+      Some(p) => [p[0], p[1], p[2], p[3]],
+      None => [0, 0, 0, 0],
+    }
+  }
+}
+
+/// Texture layer and `GfxCoordsA`/`GfxCoordsB` of a pattern.
+#[derive(Clone, Debug)]
+pub struct PatternUv {
+  pub layer: usize,
+  pub coords_a: [u8; 6],
+  pub coords_b: [u8; 6],
+}
+
+/// Texture layer and the `GfxCoordsA`/`GfxCoordsB` pairs of a transition. The
+/// texture alpha holds the `GfxTextureAlpha` mask.
+#[derive(Clone, Debug)]
+pub struct TransitionUv {
+  pub layer: usize,
+  pub variants: Vec<([u8; 6], [u8; 6])>,
+}
+
+/// Graphics of a landscape type.
+pub struct Sprite<'a> {
+  /// The frames of `clip` by BMD frame index, see `compose_clip`.
+  pub frames: &'a HashMap<usize, ComposedFrame>,
+  pub clip: &'a AnimationClip,
+}
+
+/// Composes every frame `clip` shows, so rendering does not decode the BMD.
+pub fn compose_clip(bmd: &BmdFile, shadow: Option<&BmdFile>, palette: &[u8], clip: &AnimationClip) -> Result<HashMap<usize, ComposedFrame>, &'static str> {
+  let mut frames = HashMap::new();
+
+  for frame in clip.levels.values().flatten() {
+    if let Entry::Vacant(e) = frames.entry(frame.frame) {
+      e.insert(bmd::compose_frame(bmd, shadow, frame.frame, palette, PixelFormat::STRAIGHT_SRGB, bmd::DEFAULT_SHADOW_ALPHA)?);
+    }
+  }
+
+  Ok(frames)
+}
+
+pub struct Assets<'a> {
+  pub patterns: TextureArray<'a>,
+  /// Indexed by `tiles_a` and `tiles_b`.
+  pub pattern_uvs: &'a [PatternUv],
+  pub transitions: TextureArray<'a>,
+  /// Indexed like the transition names.
+  pub transition_uvs: &'a [TransitionUv],
+  /// Indexed like the landscape names, `None` for landscapes left out.
+  pub sprites: &'a [Option<Sprite<'a>>],
+}
+
+/// The map layers the renderer reads. Every layer holds one value per cell.
+pub struct Scene<'a> {
+  pub width: usize,
+  pub height: usize,
+  pub elevation: &'a [u8],
+  pub lighting: Option<&'a [u8]>,
+  pub tiles_a: &'a [u16],
+  pub tiles_b: &'a [u16],
+  /// `trans_a1`, `trans_b1`, `trans_a2` and `trans_b2`: index into the
+  /// transition names plus one, 0 for none.
+  pub transitions: [Option<&'a [u8]>; 4],
+  /// Index into the landscape names plus one, 0 for none.
+  pub landscape_types: Option<&'a [u8]>,
+  pub landscape_levels: Option<&'a [u8]>,
+}
+
+impl<'a> Scene<'a> {
+  /// Takes the layers from a loaded map. Layers that do not hold one value
+  /// per cell are left out.
+  pub fn from_map(map: &'a CulturesMapData) -> Result<Scene<'a>, &'static str> {
+    let (width, height) = (map.width() as usize, map.height() as usize);
+    let full = |l: Option<&'a [u8]>| l.filter(|l| l.len() == width * height);
+
+    Ok(Scene {
+      width,
+      height,
+      elevation: map.elevation(),
+      lighting: full(map.lighting()),
+      tiles_a: map.tiles_a().ok_or("render_map: the map has no tiles.")?,
+      tiles_b: map.tiles_b().ok_or("render_map: the map has no tiles.")?,
+      transitions: [full(map.trans_a1()), full(map.trans_b1()), full(map.trans_a2()), full(map.trans_b2())],
+      landscape_types: full(map.landscape_types()),
+      landscape_levels: full(map.landscape_levels()),
+    })
+  }
+}
+
+/// Rectangle of cells to draw.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Region {
+  pub x: usize,
+  pub y: usize,
+  pub width: usize,
+  pub height: usize,
+}
+
+struct Canvas {
+  width: usize,
+  height: usize,
+  data: Vec<u8>,
+  /// Top left corner in map units.
+  origin: (f32, f32),
+  /// Pixels per map unit.
+  unit: f32,
+}
+
+impl Canvas {
+  fn to_px(&self, x: f32, y: f32) -> (f32, f32) {
+    ((x - self.origin.0) * self.unit, (y - self.origin.1) * self.unit)
+  }
+
+  /// Draws `c` with its alpha over the pixel, the colour multiplied by
+  /// `shade`.
+  fn blend(&mut self, x: usize, y: usize, c: [u8; 4], shade: f32) {
+    if c[3] == 0 {
+      return;
+    }
+
+    let o = 4 * (y * self.width + x);
+    let a = c[3] as f32 / 255.0;
+    let px = &mut self.data[o..o + 4];
+
+    for i in 0..3 {
+      let src = (c[i] as f32 * shade).min(255.0);
+      px[i] = (src * a + px[i] as f32 * (1.0 - a)).round() as u8;
+    }
+    px[3] = (c[3] as f32 + px[3] as f32 * (1.0 - a)).round() as u8;
+  }
+
+  /// Draws a frame with its anchor at `x`/`y` in pixels. Sprite pixels are
+  /// texture pixels, so they are scaled like the terrain.
+  fn draw_sprite(&mut self, f: &ComposedFrame, x: f32, y: f32) {
+    let zoom = self.unit / UNIT;
+    let (x0, y0) = (x + f.x as f32 * zoom, y + f.y as f32 * zoom);
+    let (x1, y1) = (x0 + f.width as f32 * zoom, y0 + f.height as f32 * zoom);
+
+    for py in clip(y0, self.height)..clip(y1, self.height) {
+      let sy = ((py as f32 + 0.5 - y0) / zoom) as usize;
+      if sy >= f.height {
+        continue;
+      }
+
+      for px in clip(x0, self.width)..clip(x1, self.width) {
+        let sx = ((px as f32 + 0.5 - x0) / zoom) as usize;
+        if sx >= f.width {
+          continue;
+        }
+
+        let s = 4 * (sy * f.width + sx);
+        self.blend(px, py, [f.data[s], f.data[s + 1], f.data[s + 2], f.data[s + 3]], 1.0);
+      }
+    }
+  }
+}
+
+#[inline]
+fn clip(v: f32, max: usize) -> usize {
+  (v.max(0.0) as usize).min(max)
+}
+
+#[inline]
+fn edge(a: (f32, f32), b: (f32, f32), x: f32, y: f32) -> f32 {
+  (b.0 - a.0) * (y - a.1) - (b.1 - a.1) * (x - a.0)
+}
+
+/// Calls `f` with the barycentric weights of every pixel centre inside the
+/// triangle `p`.
+fn fill_triangle(width: usize, height: usize, p: [(f32, f32); 3], mut f: impl FnMut(usize, usize, [f32; 3])) {
+  let area = edge(p[0], p[1], p[2].0, p[2].1);
+  if area.abs() < 1e-6 {
+    return;
+  }
+
+  let xs = [p[0].0, p[1].0, p[2].0];
+  let ys = [p[0].1, p[1].1, p[2].1];
+  let x0 = clip(xs.iter().cloned().fold(f32::INFINITY, f32::min).floor(), width);
+  let x1 = clip(xs.iter().cloned().fold(f32::NEG_INFINITY, f32::max).ceil(), width);
+  let y0 = clip(ys.iter().cloned().fold(f32::INFINITY, f32::min).floor(), height);
+  let y1 = clip(ys.iter().cloned().fold(f32::NEG_INFINITY, f32::max).ceil(), height);
+
+  for y in y0..y1 {
+    for x in x0..x1 {
+      let (cx, cy) = (x as f32 + 0.5, y as f32 + 0.5);
+      let w = [edge(p[1], p[2], cx, cy) / area, edge(p[2], p[0], cx, cy) / area, edge(p[0], p[1], cx, cy) / area];
+
+      if w.iter().all(|&w| w >= 0.0) {
+        f(x, y, w);
+      }
+    }
+  }
+}
+
+/// Texture coordinates at the barycentric weights `w`.
+#[inline]
+fn uv(coords: &[u8; 6], w: [f32; 3]) -> (f32, f32) {
+  (
+    w[0] * coords[0] as f32 + w[1] * coords[2] as f32 + w[2] * coords[4] as f32,
+    w[0] * coords[1] as f32 + w[1] * coords[3] as f32 + w[2] * coords[5] as f32,
+  )
+}
+
+/// Draws the cells of `region` and the ones around it whose triangles reach
+/// into it.
+fn draw_terrain(canvas: &mut Canvas, scene: &Scene, assets: &Assets, grid: &Grid, region: Region) {
+  let width = scene.width;
+  let (x0, x1) = (region.x.saturating_sub(1), (region.x + region.width + 1).min(scene.width));
+  let (y0, y1) = (region.y.saturating_sub(1), (region.y + region.height + LIFT_ROWS).min(scene.height));
+
+  for i in (y0..y1).flat_map(|y| (x0..x1).map(move |x| y * width + x)) {
+    let cell = grid.cell(i);
+    let v = tessellate::cell_vertices(i, grid, scene.elevation);
+
+    for (t, &kind) in [TriangleKind::A, TriangleKind::B].iter().enumerate() {
+      let p = [canvas.to_px(v[6 * t], v[6 * t + 1]), canvas.to_px(v[6 * t + 2], v[6 * t + 3]), canvas.to_px(v[6 * t + 4], v[6 * t + 5])];
+
+      // Lighting is interpolated between the corners.
+      let corners = Triangle::new(cell, kind).vertices();
+      let mut light = [1.0f32; 3];
+      if let Some(lighting) = scene.lighting {
+        for (l, &c) in light.iter_mut().zip(corners.iter()) {
+          *l = lighting_factor(lighting[grid.index(c).unwrap_or(i)]);
+        }
+      }
+
+      // The pattern, then the first and the second transition.
+      let mut layers: Vec<(&TextureArray, usize, [u8; 6])> = Vec::with_capacity(3);
+      let tile = if kind == TriangleKind::A { scene.tiles_a[i] } else { scene.tiles_b[i] };
+      if let Some(uv) = assets.pattern_uvs.get(tile as usize) {
+        layers.push((&assets.patterns, uv.layer, if kind == TriangleKind::A { uv.coords_a } else { uv.coords_b }));
+      }
+
+      for level in 0..2 {
+        let transition = scene.transitions[2 * level + t]
+          .and_then(|l| l[i].checked_sub(1))
+          .and_then(|n| assets.transition_uvs.get(n as usize))
+          .filter(|uv| !uv.variants.is_empty());
+
+        // The map does not store which variant is drawn, so it is picked by
+        // position.
+        if let Some(uv) = transition {
+          let (a, b) = uv.variants[(cell.x + cell.y) as usize % uv.variants.len()];
+          layers.push((&assets.transitions, uv.layer, if kind == TriangleKind::A { a } else { b }));
+        }
+      }
+
+      if layers.is_empty() {
+        continue;
+      }
+
+      let (width, height) = (canvas.width, canvas.height);
+      fill_triangle(width, height, p, |x, y, w| {
+        let shade = w[0] * light[0] + w[1] * light[1] + w[2] * light[2];
+
+        for (texture, layer, coords) in layers.iter() {
+          let (u, v) = uv(coords, w);
+          canvas.blend(x, y, texture.sample(*layer, u, v), shade);
+        }
+      });
+    }
+  }
+}
+
+/// Draws the landscape objects back to front, ordered by the row and column
+/// of their cell. Elevation does not change the order, an object on a hill
+/// is still in front of the rows above it.
+fn draw_landscapes(canvas: &mut Canvas, scene: &Scene, assets: &Assets, grid: &Grid) {
+  let types = match scene.landscape_types {
+    Some(types) => types,
+    None => return,
+  };
+
+  // Cells are numbered row by row, which is the drawing order.
+  for (i, &t) in types.iter().enumerate() {
+    let t = match t.checked_sub(1) {
+      Some(t) => t as usize,
+      None => continue,
+    };
+
+    if let Some(Some(sprite)) = assets.sprites.get(t) {
+      // Unknown levels fall back to the first one of the landscape.
+      let level = scene.landscape_levels.map_or(0, |l| l[i]);
+      let frame = sprite.clip.frame_at(level, 0.0)
+        .or_else(|| sprite.clip.levels.keys().next().and_then(|&l| sprite.clip.frame_at(l, 0.0)));
+
+      if let Some(composed) = frame.and_then(|f| sprite.frames.get(&f.frame)) {
+        let v = tessellate::cell_vertices(i, grid, scene.elevation);
+        let (x, y) = canvas.to_px(v[0], v[1]);
+        canvas.draw_sprite(composed, x, y);
+      }
+    }
+  }
+}
+
+/// Draws `region` at `scale` times the texture resolution. Returns the width,
+/// the height and the RGBA pixels, transparent where nothing is drawn.
+pub fn render(scene: &Scene, assets: &Assets, region: Region, scale: f32) -> Result<(usize, usize, Vec<u8>), &'static str> {
+  let cells = scene.width * scene.height;
+  if cells == 0 || scene.elevation.len() != cells || scene.tiles_a.len() != cells || scene.tiles_b.len() != cells {
+    return Err("render_map: layers do not match the map size.");
+  }
+
+  let optional = [scene.lighting, scene.transitions[0], scene.transitions[1], scene.transitions[2], scene.transitions[3], scene.landscape_types, scene.landscape_levels];
+  if optional.iter().any(|l| l.is_some_and(|l| l.len() != cells)) {
+    return Err("render_map: layers do not match the map size.");
+  }
+
+  if region.width == 0 || region.height == 0 || region.x + region.width > scene.width || region.y + region.height > scene.height {
+    return Err("render_map: region outside the map.");
+  }
+  if scale.is_nan() || scale <= 0.0 || assets.patterns.width == 0 || assets.patterns.height == 0 || assets.transitions.width == 0 || assets.transitions.height == 0 {
+    return Err("render_map: invalid scale or texture size.");
+  }
+
+  let unit = UNIT * scale;
+  let width = (2.0 * region.width as f32 * unit).ceil() as usize;
+  let height = (2.0 * region.height as f32 * unit).ceil() as usize;
+  if width * height > MAX_PIXELS {
+    return Err("render_map: image too large.");
+  }
+
+  let mut canvas = Canvas {
+    width,
+    height,
+    data: vec![0u8; 4 * width * height],
+    origin: (2.0 * region.x as f32, 2.0 * region.y as f32),
+    unit,
+  };
+
+  let grid = Grid::new(scene.width, scene.height);
+  draw_terrain(&mut canvas, scene, assets, &grid, region);
+  draw_landscapes(&mut canvas, scene, assets, &grid);
+
+  Ok((canvas.width, canvas.height, canvas.data))
+}
+
+pub fn render_png(scene: &Scene, assets: &Assets, region: Region, scale: f32) -> Result<Vec<u8>, &'static str> {
+  let (width, height, rgba) = render(scene, assets, region, scale)?;
+
+  Ok(png::encode_rgba(width, height, &rgba))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::animation::{AnimationFrame, Playback};
+  use crate::bmd::{BmdFrameInput, BmdFrameType, BmdHeader, IndexedFrame};
+  use std::collections::BTreeMap;
+
+  /// 64 × 64 layers of a single colour each.
+  fn layers(colours: &[[u8; 4]]) -> Vec<u8> {
+    colours.iter().flat_map(|c| c.iter().cloned().cycle().take(4 * 64 * 64).collect::<Vec<u8>>()).collect()
+  }
+
+  fn scene<'a>(width: usize, height: usize, elevation: &'a [u8], tiles_a: &'a [u16], tiles_b: &'a [u16]) -> Scene<'a> {
+    Scene { width, height, elevation, lighting: None, tiles_a, tiles_b, transitions: [None; 4], landscape_types: None, landscape_levels: None }
+  }
+
+  fn pixel(rgba: &[u8], width: usize, x: usize, y: usize) -> &[u8] {
+    &rgba[4 * (y * width + x)..4 * (y * width + x) + 4]
+  }
+
+  #[test]
+  fn test_render_terrain() {
+    let patterns = layers(&[[100, 0, 0, 255], [0, 0, 100, 255]]);
+    let transitions = layers(&[[0, 200, 0, 128]]);
+    let pattern_uvs = [
+      PatternUv { layer: 0, coords_a: [0, 0, 63, 63, 0, 63], coords_b: [0, 0, 63, 0, 63, 63] },
+      PatternUv { layer: 1, coords_a: [0, 0, 63, 63, 0, 63], coords_b: [0, 0, 63, 0, 63, 63] },
+    ];
+    let transition_uvs = [TransitionUv { layer: 0, variants: vec![([0, 0, 63, 63, 0, 63], [0, 0, 63, 0, 63, 63])] }];
+    let assets = Assets {
+      patterns: TextureArray { width: 64, height: 64, data: &patterns },
+      pattern_uvs: &pattern_uvs,
+      transitions: TextureArray { width: 64, height: 64, data: &transitions },
+      transition_uvs: &transition_uvs,
+      sprites: &[],
+    };
+
+    let (elevation, tiles_a, tiles_b) = (vec![0u8; 9], vec![0u16; 9], vec![1u16; 9]);
+    let mut scene = scene(3, 3, &elevation, &tiles_a, &tiles_b);
+    let region = Region { x: 1, y: 1, width: 1, height: 1 };
+
+    // The A triangle of (1, 1) points up from the middle of the top edge, B
+    // triangles fill the corners.
+    let (w, h, rgba) = render(&scene, &assets, region, 0.5).unwrap();
+    assert_eq!((w, h), (32, 32));
+    assert_eq!(pixel(&rgba, w, 16, 20), &[100, 0, 0, 255]);
+    assert_eq!(pixel(&rgba, w, 30, 2), &[0, 0, 100, 255]);
+    assert_eq!(pixel(&rgba, w, 2, 2), &[0, 0, 100, 255]);
+
+    let lighting = vec![255u8; 9];
+    let mut trans_a1 = vec![0u8; 9];
+    trans_a1[4] = 1;
+    scene.lighting = Some(&lighting);
+    scene.transitions[0] = Some(&trans_a1);
+
+    let (w, _, rgba) = render(&scene, &assets, region, 0.5).unwrap();
+    // Half of the lit pattern (150) under half of the lit transition (255).
+    assert_eq!(pixel(&rgba, w, 16, 20), &[75, 128, 0, 255]);
+    assert_eq!(pixel(&rgba, w, 30, 2), &[0, 0, 150, 255]);
+
+    assert!(render(&scene, &assets, Region { x: 2, y: 0, width: 2, height: 1 }, 1.0).is_err());
+  }
+
+  #[test]
+  fn test_render_sorts_landscapes() {
+    let frames: Vec<BmdFrameInput> = (1..3).map(|i| {
+      let mut image = IndexedFrame::new(8, 160);
+      image.indices.iter_mut().for_each(|p| *p = i);
+      image.alpha.iter_mut().for_each(|a| *a = 0xFF);
+      BmdFrameInput { frame_type: BmdFrameType::Normal, dx: -4, dy: -150, image }
+    }).collect();
+    let header = BmdHeader { magic: 0x25, zero0: 0, zero1: 0, num_frames: 0, num_pixels: 0, num_rows: 0, unknown0: 0, unknown1: 0, zero2: 0 };
+    let buf = bmd::encode_bmd(&header, &frames).unwrap();
+
+    let mut palette = vec![0u8; 768];
+    palette[3..6].copy_from_slice(&[255, 0, 0]);
+    palette[6..9].copy_from_slice(&[0, 255, 0]);
+
+    let clips: Vec<AnimationClip> = (0..2).map(|frame| {
+      let mut levels = BTreeMap::new();
+      levels.insert(1, vec![AnimationFrame { frame, dx: -4, dy: -150 }]);
+      AnimationClip { playback: Playback::Static, frame_duration: 100.0, levels }
+    }).collect();
+    let bmd = BmdFile::parse(&buf).unwrap();
+    let frames: Vec<HashMap<usize, ComposedFrame>> = clips.iter().map(|clip| compose_clip(&bmd, None, &palette, clip).unwrap()).collect();
+    let sprites: Vec<Option<Sprite>> = clips.iter().zip(frames.iter())
+      .map(|(clip, frames)| Some(Sprite { frames, clip }))
+      .collect();
+
+    let assets = Assets {
+      patterns: TextureArray { width: 64, height: 64, data: &[] },
+      pattern_uvs: &[],
+      transitions: TextureArray { width: 64, height: 64, data: &[] },
+      transition_uvs: &[],
+      sprites: &sprites,
+    };
+
+    let (mut elevation, tiles) = (vec![0u8; 15], vec![0u16; 15]);
+    let mut types = vec![0u8; 15];
+    types[4] = 1;
+    types[10] = 2;
+    let region = Region { x: 0, y: 0, width: 3, height: 5 };
+
+    // On flat ground the lower object, (1, 3), is in front.
+    let mut s = scene(3, 5, &elevation, &tiles, &tiles);
+    s.landscape_types = Some(&types);
+    let (w, _, rgba) = render(&s, &assets, region, 0.25).unwrap();
+    assert_eq!(pixel(&rgba, w, 24, 5), &[255, 0, 0, 255]);
+    assert_eq!(pixel(&rgba, w, 24, 15), &[0, 255, 0, 255]);
+
+    // Raised on a hill it moves up the screen but stays in front.
+    elevation[10] = 80;
+    let mut s = scene(3, 5, &elevation, &tiles, &tiles);
+    s.landscape_types = Some(&types);
+    let (w, _, rgba) = render(&s, &assets, region, 0.25).unwrap();
+    assert_eq!(pixel(&rgba, w, 24, 5), &[0, 255, 0, 255]);
+    assert_eq!(pixel(&rgba, w, 24, 15), &[255, 0, 0, 255]);
+  }
+}
@@ -0,0 +1,223 @@
+//! Exports BMD frames as APNG and GIF animations and as sprite sheets with a
+//! TexturePacker JSON descriptor.
+
+use crate::atlas;
+use crate::bmd::{self, BmdFile, ComposedFrame};
+use crate::gif;
+use crate::png;
+use crate::texture::PixelFormat;
+
+use std::cmp;
+use std::fmt::Write;
+
+/// Selected frames of a BMD, or all of them if `frames` is empty.
+fn frame_list(bmd: &BmdFile, frames: &[usize]) -> Vec<usize> {
+  if frames.is_empty() { (0..bmd.frames.len()).collect() } else { frames.to_vec() }
+}
+
+/// Union of the bounds of all frames, relative to the anchor.
+fn canvas(bmd: &BmdFile, shadow: Option<&BmdFile>, frames: &[usize]) -> Result<bmd::FrameBounds, &'static str> {
+  let (mut x0, mut y0, mut x1, mut y1) = (0, 0, 0, 0);
+
+  for (n, &i) in frames.iter().enumerate() {
+    let b = bmd::frame_bounds(bmd.frame(i)?, shadow.and_then(|s| s.frames.get(i)));
+    if n == 0 {
+      x0 = b.x; y0 = b.y; x1 = b.x + b.width as i32; y1 = b.y + b.height as i32;
+    } else {
+      x0 = cmp::min(x0, b.x);
+      y0 = cmp::min(y0, b.y);
+      x1 = cmp::max(x1, b.x + b.width as i32);
+      y1 = cmp::max(y1, b.y + b.height as i32);
+    }
+  }
+
+  Ok(bmd::FrameBounds { x: x0, y: y0, width: (x1 - x0) as usize, height: (y1 - y0) as usize })
+}
+
+/// Draws the frames with their shadows at their anchor on a shared canvas.
+pub fn render_frames(bmd: &BmdFile, shadow: Option<&BmdFile>, frames: &[usize], palette: &[u8], shadow_alpha: u8) -> Result<(bmd::FrameBounds, Vec<Vec<u8>>), &'static str> {
+  let frames = frame_list(bmd, frames);
+  let c = canvas(bmd, shadow, &frames)?;
+  let mut out = Vec::with_capacity(frames.len());
+
+  for &i in frames.iter() {
+    let f = bmd::compose_frame(bmd, shadow, i, palette, PixelFormat::STRAIGHT_SRGB, shadow_alpha)?;
+    let mut rgba = vec![0u8; 4 * c.width * c.height];
+    let offset = 4 * ((f.y - c.y) as usize * c.width + (f.x - c.x) as usize);
+    f.copy_rect(0, 0, f.width, f.height, &mut rgba[offset..], c.width);
+    out.push(rgba);
+  }
+
+  Ok((c, out))
+}
+
+pub fn to_apng(bmd: &BmdFile, shadow: Option<&BmdFile>, frames: &[usize], palette: &[u8], delay: u16) -> Result<Vec<u8>, &'static str> {
+  let (c, rgba) = render_frames(bmd, shadow, frames, palette, bmd::DEFAULT_SHADOW_ALPHA)?;
+
+  Ok(png::encode_apng(c.width, c.height, &rgba, delay))
+}
+
+/// GIF has no partial transparency: extended pixels are drawn when their
+/// level is at least half, shadows as a checkerboard of the darkest palette
+/// colour. The first palette index not used by any frame becomes transparent.
+pub fn to_gif(bmd: &BmdFile, shadow: Option<&BmdFile>, frames: &[usize], palette: &[u8], delay: u16) -> Result<Vec<u8>, &'static str> {
+  let frames = frame_list(bmd, frames);
+  let c = canvas(bmd, shadow, &frames)?;
+
+  let darkest = (0..256).min_by_key(|&i| {
+    let p = &palette[3 * i..3 * i + 3];
+    299 * p[0] as u32 + 587 * p[1] as u32 + 114 * p[2] as u32
+  }).unwrap() as u8;
+
+  let mut layers = Vec::with_capacity(frames.len());
+  let mut used = [false; 256];
+
+  for &i in frames.iter() {
+    let mut layer: Vec<Option<u8>> = vec![None; c.width * c.height];
+
+    let draw = |b: &BmdFile, f: &bmd::BmdFrameInfo, frame: usize, layer: &mut Vec<Option<u8>>| -> Result<(), &'static str> {
+      let image = b.decode_frame_indexed(frame)?;
+      let (ox, oy) = ((f.dx - c.x) as usize, (f.dy - c.y) as usize);
+
+      for y in 0..image.height {
+        for x in 0..image.width {
+          let pos = y * image.width + x;
+          let out = (y + oy) * c.width + x + ox;
+
+          if image.shadow[pos] != 0 && (x + ox + y + oy) % 2 == 0 {
+            layer[out] = Some(darkest);
+          } else if image.alpha[pos] >= 0x80 {
+            layer[out] = Some(image.indices[pos]);
+          }
+        }
+      }
+
+      Ok(())
+    };
+
+    if let Some((s, fs)) = shadow.and_then(|s| s.frames.get(i).map(|fs| (s, fs))) {
+      draw(s, fs, i, &mut layer)?;
+    }
+    draw(bmd, bmd.frame(i)?, i, &mut layer)?;
+
+    layer.iter().flatten().for_each(|&p| used[p as usize] = true);
+    layers.push(layer);
+  }
+
+  let transparent = used.iter().position(|&u| !u).ok_or("to_gif: no free palette index for transparency.")? as u8;
+  let indices: Vec<Vec<u8>> = layers.iter().map(|l| l.iter().map(|p| p.unwrap_or(transparent)).collect()).collect();
+
+  Ok(gif::encode_animation(c.width, c.height, &indices, palette, Some(transparent), delay))
+}
+
+pub struct SpriteSheet {
+  pub png: Vec<u8>,
+  pub json: String,
+}
+
+/// Packs the trimmed frames into one PNG and describes them in the
+/// TexturePacker JSON hash format. Frames are named `<name>_<frame>` and the
+/// pivot is the anchor relative to the untrimmed frame.
+pub fn sprite_sheet(bmd: &BmdFile, shadow: Option<&BmdFile>, frames: &[usize], palette: &[u8], name: &str, padding: usize) -> Result<SpriteSheet, &'static str> {
+  let frames = frame_list(bmd, frames);
+  let composed = frames.iter()
+    .map(|&i| bmd::compose_frame(bmd, shadow, i, palette, PixelFormat::STRAIGHT_SRGB, bmd::DEFAULT_SHADOW_ALPHA))
+    .collect::<Result<Vec<ComposedFrame>, _>>()?;
+  let trims: Vec<Option<(usize, usize, usize, usize)>> = composed.iter().map(|f| f.trim()).collect();
+
+  let sizes: Vec<(usize, usize)> = trims.iter().map(|t| t.map_or((0, 0), |(_, _, w, h)| (w, h))).collect();
+  let area: usize = sizes.iter().map(|(w, h)| (w + padding) * (h + padding)).sum();
+  let width = cmp::max((area as f64).sqrt().ceil() as usize, sizes.iter().map(|s| s.0 + padding).max().unwrap_or(0)).next_power_of_two();
+  let max_height: usize = sizes.iter().map(|s| s.1 + padding).sum();
+
+  let (placements, _) = atlas::pack(&sizes, width, cmp::max(max_height, 1), padding)?;
+  let height = placements.iter().zip(sizes.iter()).map(|(p, s)| p.y + s.1).max().unwrap_or(0).max(1);
+
+  let mut rgba = vec![0u8; 4 * width * height];
+  let mut json = String::from("{\"frames\": {\n");
+
+  for (n, ((f, trim), p)) in composed.iter().zip(trims.iter()).zip(placements.iter()).enumerate() {
+    let (tx, ty, w, h) = trim.unwrap_or((0, 0, 0, 0));
+    if w > 0 {
+      f.copy_rect(tx, ty, w, h, &mut rgba[4 * (p.y * width + p.x)..], width);
+    }
+
+    let pivot_x = if f.width > 0 { -f.x as f32 / f.width as f32 } else { 0.0 };
+    let pivot_y = if f.height > 0 { -f.y as f32 / f.height as f32 } else { 0.0 };
+
+    writeln!(json,
+      "  \"{}_{}\": {{\"frame\": {{\"x\": {}, \"y\": {}, \"w\": {}, \"h\": {}}}, \"rotated\": false, \"trimmed\": true, \
+       \"spriteSourceSize\": {{\"x\": {}, \"y\": {}, \"w\": {}, \"h\": {}}}, \"sourceSize\": {{\"w\": {}, \"h\": {}}}, \
+       \"pivot\": {{\"x\": {}, \"y\": {}}}}}{}",
+      escape(name), frames[n], p.x, p.y, w, h, tx, ty, w, h, f.width, f.height, pivot_x, pivot_y,
+      if n + 1 < composed.len() { "," } else { "" }).unwrap();
+  }
+
+  write!(json,
+    "}},\n\"meta\": {{\"app\": \"cultures2-wasm\", \"version\": \"1.0\", \"image\": \"{}.png\", \"format\": \"RGBA8888\", \
+     \"size\": {{\"w\": {}, \"h\": {}}}, \"scale\": \"1\"}}\n}}\n",
+    escape(name), width, height).unwrap();
+
+  Ok(SpriteSheet { png: png::encode_rgba(width, height, &rgba), json })
+}
+
+fn escape(s: &str) -> String {
+  s.chars().flat_map(|c| match c {
+    '"' | '\\' => vec!['\\', c],
+    c if (c as u32) < 0x20 => format!("\\u{:04x}", c as u32).chars().collect(),
+    c => vec![c],
+  }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::bmd::{BmdFrameInput, BmdFrameType, BmdHeader, IndexedFrame};
+
+  fn bmd_buf() -> Vec<u8> {
+    let frames: Vec<BmdFrameInput> = (0..3).map(|i| {
+      let mut image = IndexedFrame::new(2 + i, 3);
+      image.indices.iter_mut().for_each(|p| *p = 5);
+      image.alpha.iter_mut().for_each(|a| *a = 0xFF);
+      BmdFrameInput { frame_type: BmdFrameType::Normal, dx: -(i as i32), dy: -3, image }
+    }).collect();
+
+    let header = BmdHeader { magic: 0x25, zero0: 0, zero1: 0, num_frames: 0, num_pixels: 0, num_rows: 0, unknown0: 0, unknown1: 0, zero2: 0 };
+    bmd::encode_bmd(&header, &frames).unwrap()
+  }
+
+  #[test]
+  fn test_render_frames_share_canvas() {
+    let buf = bmd_buf();
+    let bmd = BmdFile::parse(&buf).unwrap();
+    let (c, frames) = render_frames(&bmd, None, &[], &[0x80; 768], 0x50).unwrap();
+
+    assert_eq!((c.x, c.y, c.width, c.height), (-2, -3, 4, 3));
+    assert_eq!(frames.len(), 3);
+    // The first frame starts at the anchor, two pixels into the canvas.
+    assert_eq!(frames[0][3], 0);
+    assert_eq!(frames[0][4 * 2 + 3], 0xFF);
+  }
+
+  #[test]
+  fn test_gif_uses_free_index_for_transparency() {
+    let buf = bmd_buf();
+    let bmd = BmdFile::parse(&buf).unwrap();
+    let gif = to_gif(&bmd, None, &[], &[0x80; 768], 10).unwrap();
+
+    // Graphic control extension of the first frame, transparent index 0.
+    let gce = gif.windows(4).position(|w| w == [0x21, 0xF9, 0x04, 0x09]).unwrap();
+    assert_eq!(gif[gce + 6], 0);
+  }
+
+  #[test]
+  fn test_sprite_sheet_json() {
+    let buf = bmd_buf();
+    let bmd = BmdFile::parse(&buf).unwrap();
+    let sheet = sprite_sheet(&bmd, None, &[0, 2], &[0x80; 768], "tree \"a\"", 1).unwrap();
+
+    assert!(sheet.json.contains("\"tree \\\"a\\\"_2\": {\"frame\""));
+    assert!(sheet.json.contains("\"sourceSize\": {\"w\": 4, \"h\": 3}, \"pivot\": {\"x\": 0.5, \"y\": 1}"));
+    assert_eq!(&sheet.png[1..4], b"PNG");
+  }
+}
@@ -0,0 +1,240 @@
+use crate::dxt::{self, DxtVariant};
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// sRGB to linear conversion of 8-bit channels, rounded to nearest. Checked
+/// against the sRGB transfer function in the tests.
+const SRGB_TO_LINEAR: [u8; 256] = [
+  0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+  1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3,
+  4, 4, 4, 4, 4, 5, 5, 5, 5, 6, 6, 6, 6, 7, 7, 7,
+  8, 8, 8, 8, 9, 9, 9, 10, 10, 10, 11, 11, 12, 12, 12, 13,
+  13, 13, 14, 14, 15, 15, 16, 16, 17, 17, 17, 18, 18, 19, 19, 20,
+  20, 21, 22, 22, 23, 23, 24, 24, 25, 25, 26, 27, 27, 28, 29, 29,
+  30, 30, 31, 32, 32, 33, 34, 35, 35, 36, 37, 37, 38, 39, 40, 41,
+  41, 42, 43, 44, 45, 45, 46, 47, 48, 49, 50, 51, 51, 52, 53, 54,
+  55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 69, 70,
+  71, 72, 73, 74, 76, 77, 78, 79, 80, 81, 82, 84, 85, 86, 87, 88,
+  90, 91, 92, 93, 95, 96, 97, 99, 100, 101, 103, 104, 105, 107, 108, 109,
+  111, 112, 114, 115, 116, 118, 119, 121, 122, 124, 125, 127, 128, 130, 131, 133,
+  134, 136, 138, 139, 141, 142, 144, 146, 147, 149, 151, 152, 154, 156, 157, 159,
+  161, 163, 164, 166, 168, 170, 171, 173, 175, 177, 179, 181, 183, 184, 186, 188,
+  190, 192, 194, 196, 198, 200, 202, 204, 206, 208, 210, 212, 214, 216, 218, 220,
+  222, 224, 226, 229, 231, 233, 235, 237, 239, 242, 244, 246, 248, 250, 253, 255,
+];
+
+/// How decoders write RGBA pixels: straight or premultiplied alpha, in sRGB or
+/// linear colour. Shadow pixels are black, so only their alpha matters.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PixelFormat {
+  pub premultiplied: bool,
+  pub linear: bool,
+}
+
+impl PixelFormat {
+  pub const STRAIGHT_SRGB: PixelFormat = PixelFormat { premultiplied: false, linear: false };
+
+  /// Bit 0 selects premultiplied alpha, bit 1 linear colour.
+  pub fn from_bits(bits: u8) -> PixelFormat {
+    PixelFormat {
+      premultiplied: bits & 1 != 0,
+      linear: bits & 2 != 0,
+    }
+  }
+
+  #[inline]
+  pub fn write(&self, out: &mut [u8], r: u8, g: u8, b: u8, a: u8) {
+    let (mut r, mut g, mut b) = (r, g, b);
+
+    if self.linear {
+      r = SRGB_TO_LINEAR[r as usize];
+      g = SRGB_TO_LINEAR[g as usize];
+      b = SRGB_TO_LINEAR[b as usize];
+    }
+
+    if self.premultiplied && a != 0xFF {
+      r = premultiply(r, a);
+      g = premultiply(g, a);
+      b = premultiply(b, a);
+    }
+
+    out[0] = r;
+    out[1] = g;
+    out[2] = b;
+    out[3] = a;
+  }
+}
+
+#[inline]
+fn premultiply(c: u8, a: u8) -> u8 {
+  ((c as u32 * a as u32 + 127) / 255) as u8
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TextureFormat {
+  Rgba8,
+  Bc1,
+  Bc3,
+}
+
+impl TextureFormat {
+  pub fn from_u8(v: u8) -> Option<TextureFormat> {
+    match v {
+      0 => Some(TextureFormat::Rgba8),
+      1 => Some(TextureFormat::Bc1),
+      2 => Some(TextureFormat::Bc3),
+      _ => None,
+    }
+  }
+
+  fn variant(&self) -> Option<DxtVariant> {
+    match self {
+      TextureFormat::Rgba8 => None,
+      TextureFormat::Bc1 => Some(DxtVariant::BC1),
+      TextureFormat::Bc3 => Some(DxtVariant::BC3),
+    }
+  }
+
+  /// Block compressed formats need layers that are a multiple of 4 pixels.
+  pub fn align(&self, width: usize, height: usize) -> (usize, usize) {
+    match self.variant() {
+      None => (width, height),
+      Some(_) => (dxt::align_to_block(width), dxt::align_to_block(height)),
+    }
+  }
+
+  pub fn encoded_length(&self, width: usize, height: usize) -> usize {
+    match self.variant() {
+      None => 4 * width * height,
+      Some(v) => dxt::calc_output_size(width, height, v),
+    }
+  }
+
+  /// Writes one RGBA layer in this format. `out` must hold `encoded_length` bytes.
+  pub fn encode(&self, rgba: &[u8], width: usize, height: usize, out: &mut [u8]) {
+    match self.variant() {
+      None => out[..4 * width * height].copy_from_slice(&rgba[..4 * width * height]),
+      Some(v) => dxt::encode(rgba, width, height, v, out),
+    }
+  }
+}
+
+/// Converts a texture array of RGBA layers into `format`.
+pub fn encode_layers(rgba: &[u8], width: usize, height: usize, layers: usize, format: TextureFormat) -> Vec<u8> {
+  if format == TextureFormat::Rgba8 {
+    return rgba[..4 * width * height * layers].to_vec();
+  }
+
+  let layer_length = 4 * width * height;
+  let encoded_length = format.encoded_length(width, height);
+  let mut out = vec![0u8; encoded_length * layers];
+
+  for l in 0..layers {
+    format.encode(&rgba[l * layer_length..], width, height, &mut out[l * encoded_length..(l + 1) * encoded_length]);
+  }
+
+  return out;
+}
+
+/// Collects equally sized RGBA layers and stores identical layers only once.
+/// Zero sized layers are all identical, so they collapse into one.
+pub struct LayerSet {
+  width: usize,
+  height: usize,
+  len: usize,
+  data: Vec<u8>,
+  by_hash: HashMap<u64, Vec<usize>>,
+}
+
+impl LayerSet {
+  pub fn new(width: usize, height: usize) -> LayerSet {
+    LayerSet { width, height, len: 0, data: Vec::new(), by_hash: HashMap::new() }
+  }
+
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  pub fn width(&self) -> usize {
+    self.width
+  }
+
+  pub fn height(&self) -> usize {
+    self.height
+  }
+
+  /// Returns the layer holding `rgba`, adding it if it is new.
+  pub fn insert(&mut self, rgba: &[u8]) -> usize {
+    let layer_length = 4 * self.width * self.height;
+    let rgba = &rgba[..layer_length];
+
+    let mut hasher = DefaultHasher::new();
+    rgba.hash(&mut hasher);
+    let data = &mut self.data;
+    let candidates = self.by_hash.entry(hasher.finish()).or_default();
+
+    // Compare the content as well, in case of hash collisions.
+    if let Some(&l) = candidates.iter().find(|&&l| &data[l * layer_length..(l + 1) * layer_length] == rgba) {
+      return l;
+    }
+
+    let l = self.len;
+    self.len += 1;
+    candidates.push(l);
+    data.extend_from_slice(rgba);
+
+    return l;
+  }
+
+  pub fn encode(&self, format: TextureFormat) -> Vec<u8> {
+    encode_layers(&self.data, self.width, self.height, self.len(), format)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_layer_set_dedup() {
+    let mut set = LayerSet::new(2, 1);
+
+    assert_eq!(set.insert(&[1, 2, 3, 4, 5, 6, 7, 8]), 0);
+    assert_eq!(set.insert(&[0; 8]), 1);
+    assert_eq!(set.insert(&[1, 2, 3, 4, 5, 6, 7, 8]), 0);
+    assert_eq!(set.len(), 2);
+    assert_eq!(set.encode(TextureFormat::Rgba8), vec![1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 0, 0, 0, 0, 0]);
+  }
+
+  #[test]
+  fn test_layer_set_zero_size() {
+    let mut set = LayerSet::new(0, 4);
+
+    assert_eq!(set.len(), 0);
+    assert_eq!(set.insert(&[]), 0);
+    assert_eq!(set.insert(&[]), 0);
+    assert_eq!(set.len(), 1);
+    assert!(set.encode(TextureFormat::Rgba8).is_empty());
+  }
+
+  #[test]
+  fn test_srgb_to_linear() {
+    for (i, &l) in SRGB_TO_LINEAR.iter().enumerate() {
+      let c = i as f64 / 255.0;
+      let linear = if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) };
+      assert_eq!(l, (linear * 255.0).round() as u8, "entry {}", i);
+    }
+  }
+
+  #[test]
+  fn test_pixel_format_write() {
+    let mut out = [0u8; 4];
+    PixelFormat::from_bits(3).write(&mut out, 0xFF, 0x80, 0, 0x80);
+    assert_eq!(out, [0x80, 28, 0, 0x80]);
+
+    PixelFormat::STRAIGHT_SRGB.write(&mut out, 0xFF, 0x80, 0, 0x80);
+    assert_eq!(out, [0xFF, 0x80, 0, 0x80]);
+  }
+}